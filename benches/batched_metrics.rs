@@ -0,0 +1,80 @@
+//! The time taken to execute many wide queries at once, with the per-field metrics recorded as
+//! each field resolves versus batched at the end of each operation.
+//!
+//! The metrics are recorded with the SDK's own pull controller, which locks a shard of its map of
+//! records on each call, so with enough requests in flight on a multi-threaded runtime the
+//! immediate recording contends on it. The contention only shows up with several cores, on a
+//! single core the difference is just the cost of the calls themselves. As with
+//! `default_overhead`, changes are compared against a saved baseline, e.g.
+//! `cargo bench --bench batched_metrics -- --save-baseline main` on the main branch and then
+//! `cargo bench --bench batched_metrics -- --baseline main` on the change.
+
+use async_graphql::{EmptyMutation, EmptySubscription, Object, Schema, SimpleObject};
+use async_graphql_telemetry_extension::{OpenTelemetry, OpenTelemetryConfig};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use futures_util::future::join_all;
+use opentelemetry::metrics::MeterProvider;
+use opentelemetry::sdk::export::metrics::ExportKindSelector;
+use opentelemetry::sdk::metrics::controllers;
+use opentelemetry::sdk::metrics::selectors::simple::Selector;
+
+/// The number of requests executed at once
+const CONCURRENCY: usize = 32;
+
+struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn rows(&self, count: usize) -> Vec<Row> {
+        (0..count).map(|id| Row { id, a: 1, b: 2 }).collect()
+    }
+}
+
+#[derive(SimpleObject)]
+struct Row {
+    id: usize,
+    a: i32,
+    b: i32,
+}
+
+/// Every row's fields share their labels, so the batched measurements of each field are recorded
+/// together
+const QUERY: &str = "{ rows(count: 200) { id a b } }";
+
+fn batched_metrics(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let controller = controllers::pull(
+        Box::new(Selector::Inexpensive),
+        Box::new(ExportKindSelector::Cumulative),
+    )
+    .build();
+    let mut group = c.benchmark_group("batched_metrics");
+    for batch in [false, true] {
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(controller.provider().meter("bench", None))
+                    .record_type_resolve_duration(true)
+                    .record_list_sizes(true)
+                    .batch_field_metrics(batch),
+            )
+            .finish();
+        let name = if batch { "batched" } else { "immediate" };
+        group.bench_function(BenchmarkId::new(name, CONCURRENCY), |b| {
+            b.to_async(&runtime).iter(|| {
+                join_all((0..CONCURRENCY).map(|_| {
+                    let schema = schema.clone();
+                    tokio::spawn(async move { schema.execute(QUERY).await })
+                }))
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, batched_metrics);
+criterion_main!(benches);
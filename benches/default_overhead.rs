@@ -0,0 +1,98 @@
+//! The overhead the extension adds to a request when it's registered without any configuration.
+//!
+//! The bench fails if a request with the extension takes more than [`MAX_REGRESSION`] longer,
+//! relative to one without it, than the stored [`BASELINE_OVERHEAD`]. Comparing the ratio rather
+//! than the raw timings keeps the check independent of the machine it runs on. When the overhead
+//! changes on purpose, re-run `cargo bench --bench default_overhead` and update the baseline with
+//! the overhead it prints.
+
+use async_graphql::{EmptyMutation, EmptySubscription, Object, Schema, SimpleObject};
+use async_graphql_telemetry_extension::OpenTelemetry;
+use criterion::{criterion_group, Criterion};
+use std::path::PathBuf;
+
+/// The mean time of a request with the extension, relative to one without it
+const BASELINE_OVERHEAD: f64 = 1.8;
+
+/// How far past the baseline the overhead can go before the bench fails
+const MAX_REGRESSION: f64 = 0.15;
+
+struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn items(&self, count: usize) -> Vec<Item> {
+        (0..count)
+            .map(|id| Item {
+                id,
+                name: id.to_string(),
+            })
+            .collect()
+    }
+}
+
+#[derive(SimpleObject)]
+struct Item {
+    id: usize,
+    name: String,
+}
+
+const QUERY: &str = "{ items(count: 100) { id name } }";
+
+fn default_overhead(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("default_overhead");
+    group.noise_threshold(0.05);
+
+    let schema = Schema::new(QueryRoot, EmptyMutation, EmptySubscription);
+    group.bench_function("without_extension", |b| {
+        b.to_async(&runtime).iter(|| schema.execute(QUERY))
+    });
+
+    let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .extension(OpenTelemetry)
+        .finish();
+    group.bench_function("with_extension", |b| {
+        b.to_async(&runtime).iter(|| schema.execute(QUERY))
+    });
+    group.finish();
+}
+
+/// The mean time of the last run of the given bench in nanoseconds, from criterion's estimates
+fn mean(bench: &str) -> f64 {
+    let target = std::env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target"));
+    let path = target
+        .join("criterion/default_overhead")
+        .join(bench)
+        .join("new/estimates.json");
+    let estimates: serde_json::Value = serde_json::from_slice(
+        &std::fs::read(&path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err)),
+    )
+    .unwrap();
+    estimates["mean"]["point_estimate"].as_f64().unwrap()
+}
+
+criterion_group!(benches, default_overhead);
+
+fn main() {
+    benches();
+    Criterion::default().configure_from_args().final_summary();
+    // `cargo test --benches` only runs each bench once without measuring it
+    if std::env::args().any(|arg| arg == "--test") {
+        return;
+    }
+    let overhead = mean("with_extension") / mean("without_extension");
+    println!(
+        "default overhead: {:.3} (baseline {:.3})",
+        overhead, BASELINE_OVERHEAD
+    );
+    if overhead > BASELINE_OVERHEAD * (1.0 + MAX_REGRESSION) {
+        eprintln!(
+            "the default overhead regressed by {:.1}% over the baseline",
+            (overhead / BASELINE_OVERHEAD - 1.0) * 100.0
+        );
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,52 @@
+use async_graphql::{EmptyMutation, EmptySubscription, Object, Schema, SimpleObject};
+use async_graphql_telemetry_extension::{OpenTelemetry, OpenTelemetryConfig};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn items(&self, count: usize) -> Vec<Item> {
+        (0..count)
+            .map(|id| Item {
+                id,
+                name: id.to_string(),
+            })
+            .collect()
+    }
+}
+
+#[derive(SimpleObject)]
+struct Item {
+    id: usize,
+    name: String,
+}
+
+/// Resolves every item of a wide list concurrently, so that each of the per-field hooks is
+/// competing for the request state
+fn wide_query(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("wide_query");
+    for (name, config) in [
+        ("default", OpenTelemetryConfig::default()),
+        (
+            "tracing_data",
+            OpenTelemetryConfig::default().return_tracing_data_to_client(true),
+        ),
+    ] {
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(config)
+            .finish();
+        for count in [100, 1000] {
+            let query = format!("{{ items(count: {}) {{ id name }} }}", count);
+            group.bench_with_input(BenchmarkId::new(name, count), &query, |b, query| {
+                b.to_async(&runtime).iter(|| schema.execute(query.as_str()))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, wide_query);
+criterion_main!(benches);
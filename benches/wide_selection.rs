@@ -0,0 +1,141 @@
+//! The allocations made while executing a single, very wide selection set with the tracing data
+//! enabled, which is where the per-field timings are collected.
+//!
+//! Rather than wall time, this measures the number of allocations made per request, by counting
+//! them in the global allocator. As with `default_overhead`, changes are compared against a saved
+//! baseline, e.g. `cargo bench --bench wide_selection -- --noplot --save-baseline main` on the
+//! main branch and then `cargo bench --bench wide_selection -- --noplot --baseline main` on the
+//! change. The counts are almost identical between iterations, which the plots can't render, so
+//! `--noplot` is required.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use async_graphql::{EmptyMutation, EmptySubscription, Object, Schema};
+use async_graphql_telemetry_extension::{OpenTelemetry, OpenTelemetryConfig};
+use criterion::measurement::{Measurement, ValueFormatter};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Measures the number of allocations (and reallocations) made by the benchmarked routine
+struct Allocations;
+
+impl Measurement for Allocations {
+    type Intermediate = u64;
+    type Value = u64;
+
+    fn start(&self) -> Self::Intermediate {
+        ALLOCATIONS.load(Ordering::Relaxed)
+    }
+
+    fn end(&self, start: Self::Intermediate) -> Self::Value {
+        ALLOCATIONS.load(Ordering::Relaxed) - start
+    }
+
+    fn add(&self, v1: &Self::Value, v2: &Self::Value) -> Self::Value {
+        v1 + v2
+    }
+
+    fn zero(&self) -> Self::Value {
+        0
+    }
+
+    fn to_f64(&self, value: &Self::Value) -> f64 {
+        *value as f64
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        &AllocationsFormatter
+    }
+}
+
+struct AllocationsFormatter;
+
+impl ValueFormatter for AllocationsFormatter {
+    fn scale_values(&self, _typical_value: f64, _values: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+
+    fn scale_throughputs(
+        &self,
+        _typical_value: f64,
+        throughput: &Throughput,
+        values: &mut [f64],
+    ) -> &'static str {
+        if let Throughput::Elements(elements) = throughput {
+            for value in values {
+                *value /= *elements as f64;
+            }
+        }
+        "allocs/field"
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+}
+
+struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn value(&self) -> i32 {
+        1
+    }
+}
+
+/// A single selection set of `width` aliased fields
+fn wide_query(width: usize) -> String {
+    let fields = (0..width)
+        .map(|i| format!("f{}: value", i))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("{{ {} }}", fields)
+}
+
+fn wide_selection(c: &mut Criterion<Allocations>) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("wide_selection");
+    let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .extension(OpenTelemetry)
+        .data(OpenTelemetryConfig::default().return_tracing_data_to_client(true))
+        .finish();
+    for width in [1000, 5000] {
+        let query = wide_query(width);
+        group.throughput(Throughput::Elements(width as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(width), &query, |b, query| {
+            b.to_async(&runtime).iter(|| schema.execute(query.as_str()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    // The allocation counts barely vary between iterations, which the plots can't render
+    config = Criterion::default().with_measurement(Allocations);
+    targets = wide_selection
+}
+criterion_main!(benches);
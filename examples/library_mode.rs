@@ -0,0 +1,60 @@
+//! A library which exposes a GraphQL API, instrumented without touching any of the global
+//! telemetry state of the application embedding it.
+//!
+//! The library is handed a meter & a `tracing` subscriber by the application, and everything the
+//! extension records goes to those: the application's global meter provider & default subscriber
+//! (if it has any) never see a thing.
+//!
+//! Run with `cargo run --example library_mode --features testing`.
+
+use async_graphql::{EmptyMutation, EmptySubscription, Object, Schema};
+use async_graphql_telemetry_extension::testing::MetricsCollector;
+use async_graphql_telemetry_extension::{OpenTelemetry, OpenTelemetryConfig};
+use opentelemetry::metrics::Meter;
+use tracing::Dispatch;
+
+mod library {
+    use super::*;
+
+    pub struct QueryRoot;
+
+    #[Object]
+    impl QueryRoot {
+        async fn greeting(&self, name: String) -> String {
+            tracing::info!(%name, "greeting");
+            format!("Hello {}", name)
+        }
+    }
+
+    pub type LibrarySchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+    /// Builds the library's schema, recording its telemetry with the meter & dispatcher provided
+    /// by the application
+    pub fn schema(meter: Meter, dispatch: Dispatch) -> LibrarySchema {
+        Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(
+                OpenTelemetry::library(meter, dispatch)
+                    .config(OpenTelemetryConfig::default().record_resolve_depth(true)),
+            )
+            .finish()
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    // In a real application these would be the application's own pipelines, e.g. a Prometheus
+    // exporter & a subscriber writing to its log files
+    let metrics = MetricsCollector::default();
+    let dispatch = Dispatch::new(tracing_subscriber::fmt().with_target(false).finish());
+
+    let schema = library::schema(metrics.meter(), dispatch);
+    let response = schema.execute(r#"{ greeting(name: "Jane") }"#).await;
+    println!("response: {}", serde_json::to_string(&response).unwrap());
+
+    for metric in metrics.metrics() {
+        println!(
+            "{} {:?} sum={} count={:?}",
+            metric.name, metric.labels, metric.sum, metric.count
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! The field timings of a request in the structure of Apollo's `Trace` report.
+//!
+//! Apollo Studio receives traces as the protobuf `Trace` message of its usage reporting protocol,
+//! rather than the inline JSON returned by
+//! [`OpenTelemetryConfig::return_tracing_data_to_client`](crate::OpenTelemetryConfig::return_tracing_data_to_client).
+//! This module doesn't depend on any particular protobuf implementation, instead the [`Trace`] is
+//! handed to the closure set with
+//! [`OpenTelemetryConfig::apollo_trace_sink`](crate::OpenTelemetryConfig::apollo_trace_sink),
+//! and the host maps it onto its generated `Trace` message & sends it. The field names match
+//! those of the message, so the mapping is one to one.
+//!
+//! Only the timings are populated, i.e. the start/end times of the request and the tree of
+//! `Trace.Node`s with the start & end of each resolved field. The rest of the message (the
+//! client, the HTTP details, error nodes, etc.) is left to the host.
+
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+use crate::types::{Metrics, ResolveStat};
+
+/// The timings of a single request, matching Apollo's `Trace` message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    /// When execution started
+    pub start_time: SystemTime,
+    /// When execution finished
+    pub end_time: SystemTime,
+    /// The time between the start & end, in nanoseconds
+    pub duration_ns: u64,
+    /// The root of the field tree, which has no response name itself
+    pub root: Node,
+}
+
+/// A resolved field or list element, matching Apollo's `Trace.Node` message.
+///
+/// Exactly one of `response_name` & `index` is set, except on the root node which has neither.
+/// List elements aren't resolved themselves, so their nodes only carry children.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Node {
+    /// The name of the field in the response, i.e. its alias if it has one
+    pub response_name: Option<String>,
+    /// The index of the element, when the node is an element of a list
+    pub index: Option<u32>,
+    /// The return type of the field, e.g. `[Person!]!`
+    pub r#type: String,
+    /// The type the field belongs to
+    pub parent_type: String,
+    /// When the field started resolving, in nanoseconds relative to the start of the trace
+    pub start_time: u64,
+    /// When the field finished resolving, in nanoseconds relative to the start of the trace
+    pub end_time: u64,
+    /// The fields (or list elements) nested within this field, in the order they were resolved
+    pub child: Vec<Node>,
+}
+
+impl Trace {
+    /// Builds the trace from the timings collected for the inline tracing data.
+    pub(crate) fn from_metrics(metrics: &Metrics) -> Self {
+        let mut root = Builder::default();
+        for stat in &metrics.resolves {
+            root.insert(&stat.path, stat);
+        }
+        Self {
+            start_time: metrics.start_time.into(),
+            end_time: metrics.end_time.into(),
+            duration_ns: (metrics.end_time - metrics.start_time)
+                .num_nanoseconds()
+                .unwrap_or_default()
+                .max(0) as u64,
+            root: root.build(Node::default()),
+        }
+    }
+}
+
+/// A node of the tree while it's being assembled, keyed by the segments of the path, so that the
+/// children can be found no matter what order the fields finished in.
+#[derive(Default)]
+struct Builder<'a> {
+    stat: Option<&'a ResolveStat>,
+    children: BTreeMap<&'a str, Builder<'a>>,
+}
+
+impl<'a> Builder<'a> {
+    fn insert(&mut self, path: &'a [String], stat: &'a ResolveStat) {
+        match path.split_first() {
+            Some((segment, rest)) => self
+                .children
+                .entry(segment.as_str())
+                .or_default()
+                .insert(rest, stat),
+            None => self.stat = Some(stat),
+        }
+    }
+
+    fn build(self, mut node: Node) -> Node {
+        if let Some(stat) = self.stat {
+            let start = stat.start_offset.max(0) as u64;
+            node.r#type = stat.return_type.clone();
+            node.parent_type = stat.parent_type.clone();
+            node.start_time = start;
+            node.end_time = start + stat.duration_nanos().unwrap_or_default().max(0) as u64;
+        }
+        let mut children = self
+            .children
+            .into_iter()
+            .map(|(segment, child)| {
+                let node = match segment.parse::<u32>() {
+                    Ok(index) => Node {
+                        index: Some(index),
+                        ..Node::default()
+                    },
+                    Err(_) => Node {
+                        response_name: Some(segment.to_string()),
+                        ..Node::default()
+                    },
+                };
+                child.build(node)
+            })
+            .collect::<Vec<_>>();
+        children.sort_by_key(|child| (child.index, child.start_time));
+        node.child = children;
+        node
+    }
+}
@@ -0,0 +1,42 @@
+//! Detection of resolvers which block the async runtime.
+//!
+//! A resolver which does heavy synchronous work (or calls `block_on`) holds onto the executor
+//! thread for the whole of a single poll, so the longest poll of its future is a good hint that
+//! it's blocking, even though the total time taken to resolve isn't.
+
+use std::cell::Cell;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::Clock;
+
+thread_local! {
+    /// The time spent polling the timed futures nested within the one currently being polled
+    static NESTED: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+/// Runs the future to completion, also returning the longest time a single poll of it took by the
+/// clock.
+///
+/// A field's future polls the futures of its children, so the time spent polling any nested
+/// timed futures is excluded, otherwise a blocking leaf would be reported for every one of its
+/// parents too.
+pub(crate) async fn with_longest_poll<F: Future>(
+    clock: Arc<dyn Clock>,
+    fut: F,
+) -> (F::Output, Duration) {
+    futures_util::pin_mut!(fut);
+    let mut longest = Duration::ZERO;
+    let output = std::future::poll_fn(|cx| {
+        let outer = NESTED.with(|nested| nested.replace(Duration::ZERO));
+        let start = clock.now();
+        let poll = fut.as_mut().poll(cx);
+        let elapsed = clock.now().saturating_duration_since(start);
+        let nested = NESTED.with(|nested| nested.replace(outer + elapsed));
+        longest = longest.max(elapsed.saturating_sub(nested));
+        poll
+    })
+    .await;
+    (output, longest)
+}
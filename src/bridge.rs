@@ -0,0 +1,28 @@
+//! Detecting whether an OpenTelemetry span is available to record onto.
+//!
+//! The OpenTelemetry ids of the tracing data come from the request's `tracing` span, through the
+//! `tracing-opentelemetry` layer, while the error span events go onto the span active in the
+//! OpenTelemetry context. Neither has to be wired up by the host: without the layer (or with its
+//! tracer's pipeline shut down) the request span's context is empty, and without an active span
+//! the OpenTelemetry context only holds a no-op span, whose ids are all zeroes. So every feature
+//! which depends on an OpenTelemetry span finds it through these helpers, and leaves its data out
+//! when there's no valid span rather than recording the zeroes.
+
+use opentelemetry::trace::{SpanContext, TraceContextExt};
+use opentelemetry::Context;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// The span active in the OpenTelemetry context, if it has one with valid ids
+pub(crate) fn active_otel_span(context: &Context) -> Option<&dyn opentelemetry::trace::Span> {
+    if !context.has_active_span() {
+        return None;
+    }
+    Some(context.span()).filter(|span| span.span_context().is_valid())
+}
+
+/// The ids of the OpenTelemetry span the `tracing-opentelemetry` layer exports the span as, if
+/// the layer is installed
+pub(crate) fn bridged_span_context(span: &Span) -> Option<SpanContext> {
+    active_otel_span(&span.context()).map(|span| span.span_context().clone())
+}
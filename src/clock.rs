@@ -0,0 +1,39 @@
+//! The source of the times the extension measures durations with.
+
+use std::fmt::Debug;
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+
+/// Tells the time, for the durations & timestamps recorded by the extension.
+///
+/// Every duration the extension records (whether as a metric, a span field or in the tracing
+/// data) is measured with the clock set with
+/// [`OpenTelemetryConfig::clock`](crate::OpenTelemetryConfig::clock), which by default is the
+/// [`SystemClock`]. Swapping in a clock which only moves when told to, such as the `testing`
+/// feature's `testing::MockClock`, makes the recorded durations exact, and so possible to assert
+/// on.
+///
+/// The [`ReceivedAt`](crate::ReceivedAt) & [`Deadline`](crate::Deadline) instants given by the
+/// host are compared with the clock's instants, so they have to come from the same clock.
+pub trait Clock: Debug + Send + Sync {
+    /// The current instant, for measuring durations
+    fn now(&self) -> Instant;
+
+    /// The current time, for the timestamps of the tracing data
+    fn utc_now(&self) -> DateTime<Utc>;
+}
+
+/// The system's own clocks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
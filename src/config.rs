@@ -0,0 +1,1546 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::sync::Arc;
+#[cfg(feature = "prometheus")]
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use async_graphql::extensions::ResolveInfo;
+use async_graphql::{QueryPathNode, QueryPathSegment, ServerError};
+use chrono::{DateTime, Utc};
+#[cfg(feature = "metrics")]
+use opentelemetry::metrics::Meter;
+#[cfg(feature = "metrics")]
+use opentelemetry::{Key, KeyValue};
+
+use crate::apollo::Trace;
+#[cfg(feature = "metrics")]
+use crate::metrics::{
+    self, BusinessMetricSpec, InstrumentCache, Instruments, MetricNames, Severity,
+    DEPLOYMENT_TRACK_KEY,
+};
+#[cfg(feature = "prometheus")]
+use crate::metrics::{CoreInstruments, LabelPolicy};
+use crate::sink::{LogTracingJson, ReturnTracingToClient, TracingSink, TracingSummary};
+use crate::types::Metrics;
+use crate::Clock;
+use crate::SystemClock;
+#[cfg(feature = "metrics")]
+use crate::{ClientInfo, SchemaVersion};
+
+/// Configuration for the [`OpenTelemetry`](crate::OpenTelemetry) extension.
+///
+/// The extension reads its configuration from the schema data, so it should be
+/// registered alongside the extension:
+///
+/// ```rust,ignore
+/// let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+///     .extension(OpenTelemetry)
+///     .data(OpenTelemetryConfig::default().return_tracing_data_to_client(true))
+///     .finish();
+/// ```
+///
+/// If no configuration is registered then the defaults are used. The configuration is looked up
+/// once at the start of each request, so any configuration attached to the request data rather
+/// than the schema data is ignored.
+#[derive(Debug, Clone, Default)]
+pub struct OpenTelemetryConfig {
+    // The config is cloned for every request, so anything it keeps on the heap goes behind an `Arc`
+    pub(crate) return_tracing_data_to_client: bool,
+    pub(crate) log_tracing_json: bool,
+    pub(crate) record_resolve_depth: bool,
+    pub(crate) tail_sampling_threshold: Option<Duration>,
+    pub(crate) field_span_min_duration: Option<Duration>,
+    pub(crate) ignore_upstream_sampling: bool,
+    pub(crate) stages: StageFlags,
+    pub(crate) field_representation: FieldRepresentation,
+    pub(crate) return_type_as_label: bool,
+    pub(crate) root_span_from_operation: bool,
+    pub(crate) record_alias_count: bool,
+    #[cfg(feature = "metrics")]
+    pub(crate) record_variables_bytes: bool,
+    pub(crate) record_root_selection_width: bool,
+    #[cfg(feature = "metrics")]
+    pub(crate) parse_cache_hit_threshold: Option<Duration>,
+    #[cfg(feature = "metrics")]
+    pub(crate) response_cache_hit_threshold: Option<Duration>,
+    pub(crate) error_events: ErrorEvents,
+    pub(crate) max_error_logs_per_request: Option<usize>,
+    pub(crate) omit_query_source: bool,
+    #[cfg(feature = "metrics")]
+    pub(crate) record_type_resolve_duration: bool,
+    #[cfg(feature = "metrics")]
+    pub(crate) record_field_self_duration: bool,
+    #[cfg(feature = "metrics")]
+    pub(crate) record_types_touched: bool,
+    #[cfg(feature = "metrics")]
+    pub(crate) record_phase_schedule_delay: bool,
+    #[cfg(feature = "metrics")]
+    pub(crate) batch_field_metrics: bool,
+    pub(crate) record_complexity_estimate_error: bool,
+    pub(crate) response_field_limit: Option<usize>,
+    pub(crate) record_field_nullability: bool,
+    pub(crate) record_field_complexity: bool,
+    fragment_name_allowlist: Option<Arc<HashSet<String>>>,
+    pub(crate) record_field_location: bool,
+    pub(crate) blocking_poll_threshold: Option<Duration>,
+    pub(crate) instrumentation_budget: Option<Duration>,
+    pub(crate) slow_request_threshold: Option<Duration>,
+    #[cfg(feature = "metrics")]
+    pub(crate) record_phase_duration: bool,
+    #[cfg(feature = "metrics")]
+    pub(crate) record_instrumentation_overhead: bool,
+    #[cfg(feature = "metrics")]
+    pub(crate) record_variable_validation: bool,
+    pub(crate) record_validation_rule: bool,
+    #[cfg(feature = "metrics")]
+    pub(crate) http_protocol_label: bool,
+    validation_rule_classifier: Option<ValidationRuleClassifier>,
+    pub(crate) dry_run: bool,
+    pub(crate) record_query_signature: bool,
+    #[cfg(feature = "metrics")]
+    pub(crate) record_list_sizes: bool,
+    #[cfg(feature = "metrics")]
+    path_normalizer: Option<PathNormalizer>,
+    #[cfg(feature = "metrics")]
+    error_severity: Option<ErrorSeverity>,
+    field_span_fields: Option<FieldSpanFields>,
+    apollo_trace_sink: Option<ApolloTraceSink>,
+    #[cfg(feature = "metrics")]
+    client_allowlist: Option<Arc<HashSet<String>>>,
+    #[cfg(feature = "metrics")]
+    pub(crate) operation_directive_allowlist: Option<Arc<HashSet<String>>>,
+    #[cfg(feature = "metrics")]
+    pub(crate) batch_size_limit: Option<usize>,
+    #[cfg(feature = "metrics")]
+    authz_error_codes: Option<Arc<HashSet<String>>>,
+    #[cfg(feature = "metrics")]
+    pub(crate) schema_version: Option<Arc<SchemaVersion>>,
+    #[cfg(feature = "metrics")]
+    pub(crate) deployment_track: Option<Arc<KeyValue>>,
+    #[cfg(feature = "metrics")]
+    pub(crate) metric_names: Arc<MetricNames>,
+    #[cfg(feature = "metrics")]
+    pub(crate) business_metrics: Arc<[BusinessMetricSpec]>,
+    /// The labels set with `static_labels`, which take precedence over those from the environment
+    #[cfg(feature = "metrics")]
+    explicit_labels: Arc<[KeyValue]>,
+    #[cfg(feature = "metrics")]
+    env_labels: Arc<[KeyValue]>,
+    /// The explicit & environment labels combined, attached to every measurement
+    #[cfg(feature = "metrics")]
+    pub(crate) static_labels: Arc<[KeyValue]>,
+    /// The labels to rename when they're recorded, from their original name to the new one
+    #[cfg(feature = "metrics")]
+    pub(crate) label_names: Arc<[(Key, Key)]>,
+    #[cfg(feature = "metrics")]
+    meter: Option<Arc<Meter>>,
+    #[cfg(feature = "metrics")]
+    instruments: Arc<InstrumentCache>,
+    /// The registry set with `prometheus_registry`, and the instruments registered with it the
+    /// first time they're used, or `None` if they couldn't be registered
+    #[cfg(feature = "prometheus")]
+    prometheus: Option<(prometheus::Registry, Arc<OnceLock<Option<CoreInstruments>>>)>,
+    clock: Option<Arc<dyn Clock>>,
+    tracing_sinks: Arc<[Arc<dyn TracingSink>]>,
+}
+
+impl OpenTelemetryConfig {
+    /// Return the collected tracing data to the client in the `tracing` key of
+    /// the response extensions, using the Apollo Tracing format.
+    ///
+    /// When the `tracing-opentelemetry` bridge is active, the returned block
+    /// also contains the `traceId` & `spanId` of the root `request` span.
+    ///
+    /// This returns the tracing data with every response. To only return it
+    /// for some of the operations, or a fraction of the requests, leave this
+    /// off and add a [`ReturnTracingFor`](crate::sink::ReturnTracingFor) sink
+    /// with [`tracing_sink`](Self::tracing_sink) instead.
+    pub fn return_tracing_data_to_client(mut self, enabled: bool) -> Self {
+        self.return_tracing_data_to_client = enabled;
+        self
+    }
+
+    /// Log the collected tracing data, in the same Apollo Tracing format, as a single JSON line:
+    /// the `tracing` field of a `tracing summary` event on the `request` span, emitted once the
+    /// request has been executed. This is independent of whether it's returned to the client, so
+    /// the timings can be shipped with the rest of the structured logs (e.g. to Elasticsearch).
+    ///
+    /// The line holds an entry for every resolved field, including each element of a list, so on
+    /// large queries it can easily run to megabytes. Consider pairing this with a log pipeline
+    /// which drops or truncates oversized lines, or only enabling it for some of the traffic.
+    pub fn log_tracing_json(mut self, enabled: bool) -> Self {
+        self.log_tracing_json = enabled;
+        self
+    }
+
+    /// Record the deepest field nesting reached while resolving each request as
+    /// the `graphql_max_resolve_depth` metric.
+    ///
+    /// Unlike the static depth computed during validation, this reflects the
+    /// fields that were actually resolved at runtime, which makes it useful for
+    /// debugging deeply recursive resolvers.
+    pub fn record_resolve_depth(mut self, enabled: bool) -> Self {
+        self.record_resolve_depth = enabled;
+        self
+    }
+
+    /// Only emit the per-field spans of requests which take longer than the threshold.
+    ///
+    /// Tracing spans can't be dropped once they've been created, so in this mode the field
+    /// timings are buffered while the request resolves and the `field` spans are only constructed
+    /// at the end of `execute`, if the request exceeded the threshold. The `request`, `parse`,
+    /// `validation` & `execute` spans are still recorded live for every request.
+    ///
+    /// Compared to live field spans this has a couple of tradeoffs:
+    /// - the timestamps of the emitted spans are the time they were emitted, so the actual timings
+    ///   are recorded in the `start_offset` & `duration` (nanosecond) fields of each span
+    /// - events emitted by resolvers aren't nested within the span of their field
+    /// - the field timings are held in memory until the request completes
+    ///
+    /// Each request whose field spans are emitted is counted by the `graphql_traces_sampled`
+    /// metric, with the same labels as `graphql_requests`, so the ratio of the two gives the
+    /// realised sampling rate. Without a threshold it counts every request whose fields were
+    /// traced, i.e. those not excluded by the [`stages`](Self::stages) or an unsampled upstream
+    /// trace.
+    ///
+    /// async-graphql executes each event of a subscription on its own, so the fields of each
+    /// event are buffered, and emitted at the end of its execution, like those of a request.
+    pub fn tail_sampling_threshold(mut self, threshold: Duration) -> Self {
+        self.tail_sampling_threshold = Some(threshold);
+        self
+    }
+
+    /// Only emit the `field` spans of fields which took at least `duration` to resolve, cutting
+    /// the noise of the many fields which resolve instantly out of the trace.
+    ///
+    /// A span can't be dropped once it's been created, so this uses the same buffering as
+    /// [`tail_sampling_threshold`](Self::tail_sampling_threshold), with the same tradeoffs: the
+    /// timings of the slow fields are held until the end of `execute`, when their spans are
+    /// emitted carrying the `start_offset` & `duration` fields. A slow field whose parent was
+    /// fast is nested directly under the `execute` span. When both are set, only the slow fields
+    /// of requests over the tail sampling threshold are emitted.
+    pub fn field_span_min_duration(mut self, duration: Duration) -> Self {
+        self.field_span_min_duration = Some(duration);
+        self
+    }
+
+    /// Only create the spans of the listed stages, e.g. `StageFlags::REQUEST | StageFlags::EXECUTE`
+    /// for a coarse trace of each request without any `parse`, `validation` or `field` spans.
+    ///
+    /// All of the stages are enabled by default. This only affects the spans, the metrics are
+    /// recorded for every stage regardless.
+    pub fn stages(mut self, stages: StageFlags) -> Self {
+        self.stages = stages;
+        self
+    }
+
+    /// Choose how the resolved fields are recorded on the trace, defaults to
+    /// [`FieldRepresentation::Spans`].
+    pub fn field_representation(mut self, representation: FieldRepresentation) -> Self {
+        self.field_representation = representation;
+        self
+    }
+
+    /// Create the `field` spans even when the upstream service decided not to sample the trace.
+    ///
+    /// When the host extracts the trace context from the incoming request (e.g. the
+    /// `traceparent` header) and executes the request within it, a trace which the upstream
+    /// marked as not sampled produces no `field` spans, so that each service along the trace
+    /// makes the same decision. Everything else, including all of the metrics, is still
+    /// recorded. The decision is read from the sampled flag of the request's
+    /// [`TraceParent`](crate::TraceParent) if it has one, otherwise the `opentelemetry::Context`
+    /// that is current when the request starts.
+    pub fn ignore_upstream_sampling(mut self, enabled: bool) -> Self {
+        self.ignore_upstream_sampling = enabled;
+        self
+    }
+
+    /// Attach the `return_type` of each field as a label on the field duration and error metrics.
+    ///
+    /// The return type is always recorded on the `field` spans, but it's left off the metrics by
+    /// default. Each distinct return type (`User`, `User!`, `[User!]!` etc.) multiplies the number
+    /// of series produced for every path, which can add up to a lot of series on a large schema.
+    pub fn return_type_as_label(mut self, enabled: bool) -> Self {
+        self.return_type_as_label = enabled;
+        self
+    }
+
+    /// Name the root `request` span after the operation being executed, e.g. `query GetJane` or
+    /// `mutation CreateOrder`, for trace viewers which group traces by span name.
+    ///
+    /// The operation isn't known until the query has been parsed, so the span is renamed through
+    /// the `otel.name` field once execution starts. This means the new name only applies to the
+    /// spans exported through `tracing-opentelemetry`. Anonymous operations keep the `request`
+    /// name.
+    pub fn root_span_from_operation(mut self, enabled: bool) -> Self {
+        self.root_span_from_operation = enabled;
+        self
+    }
+
+    /// Record the number of aliased fields in each query as the `graphql_alias_count` metric.
+    ///
+    /// The count is always attached to the `request` span as the `alias_count` field. Aliasing the
+    /// same expensive field many times in one query is a cheap way to amplify the load a single
+    /// request puts on the server, so a high count is worth alerting on.
+    pub fn record_alias_count(mut self, enabled: bool) -> Self {
+        self.record_alias_count = enabled;
+        self
+    }
+
+    /// Record the size of each request's variables, serialized as JSON, as the
+    /// `graphql_variables_bytes` metric by `operation_kind`.
+    ///
+    /// The size is always attached to the `parse` span as the `variables_bytes` field, whereas the
+    /// variables themselves are never recorded, so this is safe to enable whatever the variables
+    /// hold. It's the size the variables serialize to rather than the size they were sent as, so
+    /// it doesn't count any whitespace in the request.
+    #[cfg(feature = "metrics")]
+    pub fn record_variables_bytes(mut self, enabled: bool) -> Self {
+        self.record_variables_bytes = enabled;
+        self
+    }
+
+    /// Record the number of distinct top-level fields selected by each operation as the
+    /// `graphql_root_selection_width` metric.
+    ///
+    /// The width is always attached to the `request` span as the `root_selection_width` field.
+    /// Fields selected through fragments are included, and a field selected more than once under
+    /// the same name is only counted once. A very wide root selection usually means a client is
+    /// fetching far more in a single request than it needs.
+    pub fn record_root_selection_width(mut self, enabled: bool) -> Self {
+        self.record_root_selection_width = enabled;
+        self
+    }
+
+    /// Count the parsed-document cache hits & misses as the `graphql_parse_cache_hits` &
+    /// `graphql_parse_cache_misses` metrics, treating any parse that completes within the
+    /// threshold as a cache hit.
+    ///
+    /// This is only a heuristic. async-graphql doesn't cache parsed documents itself, and the
+    /// `parse_query` hook has no way of telling whether a document came from a cache (e.g. one
+    /// maintained by another extension) or from a fresh parse. Counting them exactly would need
+    /// async-graphql to report the source of the document to the hook, e.g. through a flag on the
+    /// `ExtensionContext`. Until then the threshold should be set somewhere between the time a
+    /// cache lookup takes and the time the cheapest real parse takes.
+    #[cfg(feature = "metrics")]
+    pub fn parse_cache_hit_threshold(mut self, threshold: Duration) -> Self {
+        self.parse_cache_hit_threshold = Some(threshold);
+        self
+    }
+
+    /// Count the responses served by a response cache extension as the
+    /// `graphql_response_cache_hits` metric.
+    ///
+    /// This is a heuristic too, as async-graphql has no response cache of its own. A response is
+    /// counted as a cache hit when both:
+    /// - executing it took less than the threshold, i.e. execution was short-circuited rather
+    ///   than resolving any fields
+    /// - its extensions contain `"cacheHit": true`, which the cache extension has to add to the
+    ///   responses it serves
+    ///
+    /// The cache extension has to be registered after this one, otherwise it returns the cached
+    /// response before this extension's `execute` hook is reached.
+    #[cfg(feature = "metrics")]
+    pub fn response_cache_hit_threshold(mut self, threshold: Duration) -> Self {
+        self.response_cache_hit_threshold = Some(threshold);
+        self
+    }
+
+    /// Record the time taken to resolve each field as the `graphql_type_resolve_duration` metric,
+    /// in microseconds, labelled only by the `parent_type` of the field.
+    ///
+    /// This answers which types are expensive to resolve with far fewer series than the per-path
+    /// durations. Unlike those it measures each resolver on its own, rather than the time since
+    /// the start of the request.
+    #[cfg(feature = "metrics")]
+    pub fn record_type_resolve_duration(mut self, enabled: bool) -> Self {
+        self.record_type_resolve_duration = enabled;
+        self
+    }
+
+    /// Record the "self time" of each field as the `graphql_field_self_duration` metric, in
+    /// microseconds, labelled by the `field_name` & `parent_type` of the field.
+    ///
+    /// A field's duration includes resolving the fields selected within it, so the self time is
+    /// its duration less the durations of its child fields, i.e. the time spent in its own
+    /// resolver. It's computed from the field timings once the request has executed.
+    ///
+    /// async-graphql resolves the fields of a query concurrently, so the durations of the children
+    /// can overlap, and together add up to more than the time their parent took. Their sum is
+    /// subtracted as is, so the self time of a field with concurrent children is an under-estimate
+    /// (it bottoms out at zero), while for mutations, whose root fields are resolved one after
+    /// another, & fields with a single child it's exact.
+    #[cfg(feature = "metrics")]
+    pub fn record_field_self_duration(mut self, enabled: bool) -> Self {
+        self.record_field_self_duration = enabled;
+        self
+    }
+
+    /// Record the number of distinct types whose fields each request resolved as the
+    /// `graphql_types_touched` metric, once the request has executed.
+    ///
+    /// The types are the `parent_type`s of the resolved fields, so the count grows with how many
+    /// kinds of object a query joins across, which tends to track its fan-out to the backing
+    /// services better than the number of fields does. The types are gathered into a set as the
+    /// fields resolve, so it's off by default.
+    #[cfg(feature = "metrics")]
+    pub fn record_types_touched(mut self, enabled: bool) -> Self {
+        self.record_types_touched = enabled;
+        self
+    }
+
+    /// Record how long the `parse`, `validation` & `execute` phases waited to be scheduled by the
+    /// async runtime as the `graphql_phase_schedule_delay` metric by `phase`, in microseconds.
+    ///
+    /// This is the time from when the phase's span is created until the phase is first polled,
+    /// plus the time from each wake-up of the phase until it's polled again, so it grows when the
+    /// runtime is starved, rather than when the phase itself is slow. async-graphql parses &
+    /// validates synchronously, so only `execute` is ever woken. The time the request's task
+    /// spends busy elsewhere before returning to the phase is included too, whereas the lateness
+    /// of timers & I/O on a starved runtime isn't, as they're woken late by the runtime's driver
+    /// rather than polled late. Every poll of a phase is intercepted to timestamp its wake-ups,
+    /// so it's off by default.
+    #[cfg(feature = "metrics")]
+    pub fn record_phase_schedule_delay(mut self, enabled: bool) -> Self {
+        self.record_phase_schedule_delay = enabled;
+        self
+    }
+
+    /// Hold back the per-field measurements (the field durations, list sizes & errors) until the
+    /// operation has executed, then record them together.
+    ///
+    /// By default each resolved field records its measurements as it completes, which across a
+    /// wide query means a call into the metrics SDK per field, from every one of the executor's
+    /// threads at once. Some SDKs take a lock on each call, so this contends with every other
+    /// request being resolved at the same time. When batched, the counters are summed & recorded
+    /// once per set of labels, and each duration recorder is bound to a set of labels once for
+    /// all of its values.
+    ///
+    /// The measurements only show up once the operation is complete though, so this is off by
+    /// default to keep the live metrics accurate. The fields of a subscription's events are
+    /// always recorded immediately, as the subscription may never complete.
+    #[cfg(feature = "metrics")]
+    pub fn batch_field_metrics(mut self, enabled: bool) -> Self {
+        self.batch_field_metrics = enabled;
+        self
+    }
+
+    /// Compare the complexity estimated for each operation with the number of fields actually
+    /// resolved, recording the ratio of the two as the `graphql_complexity_estimate_error` metric.
+    ///
+    /// Both are also recorded on the `execute` span, as the `estimated_complexity` &
+    /// `resolved_fields` fields. A ratio well above 1 means the operation did more work than its
+    /// complexity suggested, e.g. because a list's cost doesn't account for its length, which is
+    /// where the cost functions need tuning.
+    ///
+    /// async-graphql computes the complexity whether or not a limit is set, but doesn't tell
+    /// extensions whether one is, so this should only be enabled alongside
+    /// `SchemaBuilder::limit_complexity`. Operations estimated at zero aren't recorded.
+    pub fn record_complexity_estimate_error(mut self, enabled: bool) -> Self {
+        self.record_complexity_estimate_error = enabled;
+        self
+    }
+
+    /// Flag the responses which resolved more fields than the limit, counting them by
+    /// `operation_kind` as the `graphql_oversized_responses` metric and emitting a `WARN`
+    /// "oversized response" event within the `execute` span.
+    ///
+    /// This only monitors the responses, it doesn't stop them from being returned. Unlike
+    /// `SchemaBuilder::limit_complexity` & `limit_depth`, which reject a document before it's
+    /// executed going by its statically estimated cost, this counts the fields which were
+    /// actually resolved, so a field selected within a list counts once per element. The size
+    /// of the serialized response isn't known to the extension, as the host serializes it after
+    /// the extension has returned, so the number of fields stands in for it.
+    pub fn response_field_limit(mut self, limit: usize) -> Self {
+        self.response_field_limit = Some(limit);
+        self
+    }
+
+    /// Record the time taken by each of the `parse`, `validation` & `execute` phases in a single
+    /// `graphql_phase_duration_seconds` metric by `phase`, in seconds.
+    ///
+    /// One instrument split by a label is easy to query, as the phases can be compared or summed
+    /// in a single expression, but each combination of the other labels has a series per phase
+    /// within the one metric, so a dashboard or alert which only cares about one phase has to
+    /// filter it out of three times as many series. This is also the only metric of the time
+    /// taken by validation, which includes async-graphql's complexity analysis. It's off by
+    /// default.
+    #[cfg(feature = "metrics")]
+    pub fn record_phase_duration(mut self, enabled: bool) -> Self {
+        self.record_phase_duration = enabled;
+        self
+    }
+
+    /// Record the number of variables validated for each request as the
+    /// `graphql_variables_validated` metric, and count the variables which fail to coerce to the
+    /// type of their argument as the `graphql_variable_coercion_errors` metric.
+    ///
+    /// Each coercion error is labelled with the `variable` it came from, when the error can be
+    /// traced back to a single variable. The variable names are chosen by the clients, so the
+    /// label is only as bounded as the set of documents they send.
+    #[cfg(feature = "metrics")]
+    pub fn record_variable_validation(mut self, enabled: bool) -> Self {
+        self.record_variable_validation = enabled;
+        self
+    }
+
+    /// Record the validation rule which a document broke, as the `validation_rule` field of the
+    /// `validation` span, and count the documents which fail validation by `validation_rule` as
+    /// the `graphql_validation_errors` metric.
+    ///
+    /// A document breaking several rules is recorded under the rule of its first error. The rules
+    /// are classified into coarse categories such as `unknown_field` or `fragment_cycle`, by
+    /// [`classify_validation_error`](crate::classify_validation_error) unless
+    /// [`validation_rule_classifier`](Self::validation_rule_classifier) is set, so the label only
+    /// takes a handful of values.
+    pub fn record_validation_rule(mut self, enabled: bool) -> Self {
+        self.record_validation_rule = enabled;
+        self
+    }
+
+    /// Classify the validation errors recorded by
+    /// [`record_validation_rule`](Self::record_validation_rule) with the provided closure, e.g.
+    /// to recognise the errors of custom validation rules:
+    ///
+    /// ```rust
+    /// use std::sync::Arc;
+    /// use async_graphql::ServerError;
+    /// use async_graphql_telemetry_extension::{classify_validation_error, OpenTelemetryConfig};
+    ///
+    /// let config = OpenTelemetryConfig::default()
+    ///     .record_validation_rule(true)
+    ///     .validation_rule_classifier(Arc::new(|err: &ServerError| {
+    ///         if err.message.starts_with("Deprecated field") {
+    ///             "deprecated_field"
+    ///         } else {
+    ///             classify_validation_error(err)
+    ///         }
+    ///     }));
+    /// ```
+    pub fn validation_rule_classifier(mut self, classifier: Arc<ClassifyValidationError>) -> Self {
+        self.validation_rule_classifier = Some(ValidationRuleClassifier(classifier));
+        self
+    }
+
+    /// Log the spans & metrics the extension would produce, rather than producing them.
+    ///
+    /// Each span is logged as a DEBUG event with its name & fields, and each measurement as a
+    /// DEBUG event with the metric's name, the value & the full set of labels (after the static
+    /// labels & renames are applied), under the `async_graphql::graphql::dry_run` target. The
+    /// labels are built exactly as they would be otherwise, so the logs show the cardinality &
+    /// naming the metrics would have in production.
+    ///
+    /// The other events (e.g. the field errors) are still emitted, without a span to belong to.
+    pub fn dry_run(mut self, enabled: bool) -> Self {
+        self.dry_run = enabled;
+        self
+    }
+
+    /// Record the [signature](crate::query_signature) of each operation as the `query_signature`
+    /// field of the `request` span, and as a label of the `graphql_requests` metric.
+    ///
+    /// The signature groups the requests for the same shape of operation, whatever its literals
+    /// & aliases, so it has far fewer values than the query text. It's still as bounded as the set
+    /// of documents the clients send though, so it's only suitable as a label when the clients are
+    /// known, e.g. when the queries are persisted.
+    pub fn record_query_signature(mut self, enabled: bool) -> Self {
+        self.record_query_signature = enabled;
+        self
+    }
+
+    /// Record the number of items returned by each list field as the `graphql_list_field_size`
+    /// metric, labelled by the `field_name`, to help catch lists which grow without bound.
+    ///
+    /// This inspects the resolved value of every field, so it's off by default.
+    #[cfg(feature = "metrics")]
+    pub fn record_list_sizes(mut self, enabled: bool) -> Self {
+        self.record_list_sizes = enabled;
+        self
+    }
+
+    /// Emit a warning event when a single poll of a field's resolver takes longer than the
+    /// threshold, as the resolver may be blocking the async runtime.
+    ///
+    /// This is a heuristic aimed at catching accidental `block_on` calls or heavy CPU work in
+    /// resolvers, which stall every other task on the executor thread. A slow resolver that
+    /// spends its time awaiting isn't flagged, as only the time spent inside a single poll
+    /// counts (excluding the polls of its child fields). Checking adds the cost of timing
+    /// every poll, so it's off by default.
+    pub fn blocking_poll_threshold(mut self, threshold: Duration) -> Self {
+        self.blocking_poll_threshold = Some(threshold);
+        self
+    }
+
+    /// Cap the time the extension spends on its own bookkeeping for the fields of each request.
+    ///
+    /// Once the time spent by the `resolve` hook around the resolvers adds up to more than the
+    /// budget, the rest of the request's fields are resolved without any instrumentation: no
+    /// `field` spans or events, no per-field metrics & no tracing data. The request-level
+    /// metrics are still recorded, and the request is counted by the
+    /// `graphql_instrumentation_degraded` metric, so a pathologically wide query can't have its
+    /// latency dominated by the extension itself.
+    pub fn instrumentation_budget(mut self, budget: Duration) -> Self {
+        self.instrumentation_budget = Some(budget);
+        self
+    }
+
+    /// Record the time each request spent in the extension's own code as the
+    /// `graphql_instrumentation_overhead` metric by `operation_kind`, in microseconds, to quantify
+    /// the cost of the instrumentation on real traffic.
+    ///
+    /// The time is summed across every hook, from when the hook is entered until it hands on to
+    /// the next one, and from when the next one returns until the hook does, so the time spent by
+    /// async-graphql & the resolvers is excluded. A request's own spans are recorded by the
+    /// subscriber as they're created, entered & closed, so the time the subscriber takes to
+    /// record them is mostly counted too, whereas exporting them in the background isn't. Timing
+    /// the hooks adds a couple of clock reads of its own to each of them (including the `resolve`
+    /// hook of every field), so it's off by default. A request's overhead is only recorded once
+    /// its `request` hook completes, so it isn't recorded for subscriptions.
+    #[cfg(feature = "metrics")]
+    pub fn record_instrumentation_overhead(mut self, enabled: bool) -> Self {
+        self.record_instrumentation_overhead = enabled;
+        self
+    }
+
+    /// Report the requests which take longer than the threshold, from the start of the request
+    /// to the end of `execute`.
+    ///
+    /// Each slow request is counted by the `graphql_slow_requests` metric, labelled by the
+    /// `operation_kind` & the name of the operation as the `query_name` (slow operations being
+    /// few enough to not add many series), and gets a `WARN` "slow request" event on its
+    /// `request` span listing the slowest of its fields as `slowest_fields`, e.g.
+    /// `getJane=12ms, getJane.details=9ms`. A field's time includes that of its child fields, so
+    /// the list reads from the field which made the request slow down to its culprit. Listing
+    /// the fields means keeping the timing of every field until the request has executed.
+    pub fn slow_request_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_request_threshold = Some(threshold);
+        self
+    }
+
+    /// Record the text of the query as the `source` field of the `parse` span, and in the
+    /// trace-level event emitted while parsing. Defaults to `true`.
+    ///
+    /// Disabling this keeps the query text out of the telemetry entirely, for environments where
+    /// queries may contain data that can't be logged.
+    pub fn record_query_source(mut self, enabled: bool) -> Self {
+        self.omit_query_source = !enabled;
+        self
+    }
+
+    /// Choose how field errors are reported on the trace, defaults to [`ErrorEvents::Tracing`].
+    pub fn error_events(mut self, events: ErrorEvents) -> Self {
+        self.error_events = events;
+        self
+    }
+
+    /// Only emit the `tracing` error event of the first `limit` field errors with the same message
+    /// in each request, e.g. when a resolver fails for every element of a large list.
+    ///
+    /// Once execution has finished, a single `suppressed repeated field errors` warning is emitted
+    /// within the `execute` span for each message which went over the limit, carrying the `error`
+    /// and the number of events `suppressed`. This only throttles the `tracing` events: the error
+    /// metrics still count every error, and OpenTelemetry span events (see
+    /// [`ErrorEvents`]) are still added for each of them.
+    pub fn max_error_logs_per_request(mut self, limit: usize) -> Self {
+        self.max_error_logs_per_request = Some(limit);
+        self
+    }
+
+    /// Override how the path of a field is turned into the `query_name` metric label.
+    ///
+    /// The normalizer is given the segments of the path, e.g. `["users", "0", "name"]`. By default
+    /// list indices are dropped (giving `users.name`), so that every element of a list is recorded
+    /// under the same series, but some schemas need a more aggressive rule to keep the number of
+    /// series down, e.g. truncating to the first couple of segments:
+    ///
+    /// ```rust
+    /// use std::sync::Arc;
+    /// use async_graphql_telemetry_extension::OpenTelemetryConfig;
+    ///
+    /// let config = OpenTelemetryConfig::default()
+    ///     .path_normalizer(Arc::new(|path: &[String]| path[..path.len().min(2)].join(".")));
+    /// ```
+    ///
+    /// The spans always carry the full path.
+    #[cfg(feature = "metrics")]
+    pub fn path_normalizer(mut self, normalizer: Arc<NormalizePath>) -> Self {
+        self.path_normalizer = Some(PathNormalizer(normalizer));
+        self
+    }
+
+    /// Decide the [`Severity`] of each field error, recorded as the `severity` label of the
+    /// `graphql_request_errors` & `graphql_field_errors` counters.
+    ///
+    /// By default this is [`Severity::from_error`], which goes by the `code` of the error's
+    /// extensions. A custom mapping can fall back to it for the errors it doesn't know about:
+    ///
+    /// ```rust
+    /// use std::sync::Arc;
+    /// use async_graphql::ServerError;
+    /// use async_graphql_telemetry_extension::{OpenTelemetryConfig, Severity};
+    ///
+    /// let config = OpenTelemetryConfig::default().error_severity(Arc::new(|err: &ServerError| {
+    ///     if err.message.contains("timed out") {
+    ///         Severity::Warning
+    ///     } else {
+    ///         Severity::from_error(err)
+    ///     }
+    /// }));
+    /// ```
+    #[cfg(feature = "metrics")]
+    pub fn error_severity(mut self, severity: Arc<ClassifyError>) -> Self {
+        self.error_severity = Some(ErrorSeverity(severity));
+        self
+    }
+
+    /// Only record the [`ClientInfo`](crate::ClientInfo) of the listed clients on the request
+    /// counter.
+    ///
+    /// The client info is usually taken straight from request headers, so without an allowlist
+    /// any client can create new series just by sending a new name. Requests from clients that
+    /// aren't on the list are recorded with `other` as both their name & version.
+    #[cfg(feature = "metrics")]
+    pub fn client_allowlist<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.client_allowlist = Some(Arc::new(names.into_iter().map(Into::into).collect()));
+        self
+    }
+
+    /// Count the GraphQL-over-HTTP batches with more operations than the limit as the
+    /// `graphql_oversized_batches` metric, to spot clients abusing batching.
+    ///
+    /// The extension only sees one operation of a batch at a time, so the size of the batch has
+    /// to be attached to each of its requests by the host, as a
+    /// [`BatchPosition`](crate::BatchPosition). This only monitors the batches, they're still
+    /// executed whatever their size.
+    #[cfg(feature = "metrics")]
+    pub fn batch_size_limit(mut self, limit: usize) -> Self {
+        self.batch_size_limit = Some(limit);
+        self
+    }
+
+    /// Count the directives applied to each operation (e.g. `@live` in `query @live { .. }`) by
+    /// `directive` as the `graphql_operation_directive_usage` metric, to track the adoption of
+    /// custom operation directives.
+    ///
+    /// The directives are written by the client, so only the listed directives are counted under
+    /// their own name, any others are counted as `other`. The directives are read from the
+    /// document as it's parsed, so an operation whose directives are then rejected during
+    /// validation (as async-graphql 2.x does for any directive the schema doesn't know) is still
+    /// counted. The directives are recorded on the `request` span as `operation_directives`
+    /// whether or not they're counted.
+    #[cfg(feature = "metrics")]
+    pub fn count_operation_directives<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.operation_directive_allowlist =
+            Some(Arc::new(names.into_iter().map(Into::into).collect()));
+        self
+    }
+
+    /// The error codes which mark a field error as an authorization rejection, counted by the
+    /// `graphql_authz_rejections` metric.
+    ///
+    /// A `Guard` which rejects a field surfaces as an error of the field like any other, so the
+    /// rejections are told apart from the resolvers' own failures by the `code` of the error's
+    /// extensions. Defaults to `FORBIDDEN` & `UNAUTHENTICATED`, an empty list stops counting them.
+    #[cfg(feature = "metrics")]
+    pub fn authz_error_codes<I, S>(mut self, codes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.authz_error_codes = Some(Arc::new(codes.into_iter().map(Into::into).collect()));
+        self
+    }
+
+    /// Label the request counter with the version of HTTP the request was received over, as
+    /// `http_protocol`, when the host provides it as an [`HttpProtocol`](crate::HttpProtocol).
+    ///
+    /// The version is always recorded on the `request` span as the `http.protocol` field. It's
+    /// only one of a handful of values, but it's still a label on every request series, so it's
+    /// off by default.
+    #[cfg(feature = "metrics")]
+    pub fn http_protocol_label(mut self, enabled: bool) -> Self {
+        self.http_protocol_label = enabled;
+        self
+    }
+
+    /// Label the request & error counters with the version of the schema, as `schema_version`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::InvalidSchemaVersion`] if the version isn't a short identifier, see
+    /// [`SchemaVersion`] for the rules.
+    #[cfg(feature = "metrics")]
+    pub fn schema_version(mut self, version: impl Into<String>) -> Result<Self, ConfigError> {
+        let version = SchemaVersion(version.into());
+        if !version.is_valid() {
+            return Err(ConfigError::InvalidSchemaVersion(version.0));
+        }
+        self.schema_version = Some(Arc::new(version));
+        Ok(self)
+    }
+
+    /// Override the names of the metric instruments.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ConfigError`] if any of the names don't follow the
+    /// OpenTelemetry instrument naming rules, or if two instruments share a name.
+    #[cfg(feature = "metrics")]
+    pub fn metric_names(mut self, names: MetricNames) -> Result<Self, ConfigError> {
+        names.validate()?;
+        metrics::validate_business_metrics(&self.business_metrics, &names)?;
+        self.metric_names = Arc::new(names);
+        self.instruments = Default::default();
+        Ok(self)
+    }
+
+    /// Record the values accumulated in each request's [`BusinessMetrics`](crate::BusinessMetrics)
+    /// with the instruments described by the specs, replacing any which were set before.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ConfigError`] if any of the names don't follow the OpenTelemetry instrument
+    /// naming rules, or if they're shared with another instrument.
+    #[cfg(feature = "metrics")]
+    pub fn business_metrics(
+        mut self,
+        specs: impl IntoIterator<Item = BusinessMetricSpec>,
+    ) -> Result<Self, ConfigError> {
+        let specs = specs.into_iter().collect::<Arc<[_]>>();
+        metrics::validate_business_metrics(&specs, &self.metric_names)?;
+        self.business_metrics = specs;
+        self.instruments = Default::default();
+        Ok(self)
+    }
+
+    /// Attach the track of the deployment serving the requests (e.g. `stable` or `canary`) to the
+    /// `graphql_requests`, `graphql_request_errors`, `graphql_field_errors` &
+    /// `graphql_request_duration` metrics as the `deployment_track` label, so changes in the
+    /// error rate or latency during a progressive rollout can be attributed to the canary.
+    ///
+    /// The track is set once at startup, so unlike [`static_labels`](Self::static_labels) it
+    /// only adds a series per track to the metrics a rollout is judged by, rather than to every
+    /// metric. It should be one of a handful of values, as each is a new series of each metric.
+    /// Without it the label is omitted.
+    #[cfg(feature = "metrics")]
+    pub fn deployment_track(mut self, track: impl Into<String>) -> Self {
+        self.deployment_track = Some(Arc::new(DEPLOYMENT_TRACK_KEY.string(track.into())));
+        self
+    }
+
+    /// Attach the provided labels to every metric the extension records, e.g. the service or
+    /// deployment the metrics came from.
+    ///
+    /// These should be labels which are the same for the lifetime of the process, as the
+    /// extension's own labels take precedence over them if they share a key. Calling this again
+    /// replaces the previous labels.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::DuplicateLabelName`] if one of the labels has the name another
+    /// label is recorded under with [`label_name`](Self::label_name).
+    #[cfg(feature = "metrics")]
+    pub fn static_labels(
+        mut self,
+        labels: impl IntoIterator<Item = KeyValue>,
+    ) -> Result<Self, ConfigError> {
+        self.explicit_labels = labels.into_iter().collect();
+        self.combine_static_labels()
+    }
+
+    /// Attach labels read from the common deployment environment variables to every metric the
+    /// extension records, as with [`static_labels`](Self::static_labels).
+    ///
+    /// The variables are read once, when this is called, and only those which are set to a
+    /// non-empty value are used:
+    ///
+    /// | Variable            | Label            |
+    /// |---------------------|------------------|
+    /// | `OTEL_SERVICE_NAME` | `service_name`   |
+    /// | `DEPLOYMENT_ENV`    | `deployment_env` |
+    /// | `REGION`            | `region`         |
+    ///
+    /// The labels set with [`static_labels`](Self::static_labels) take precedence over these no
+    /// matter which order the two are called in, so any of the values can be overridden in code.
+    /// Not calling this opts out of reading the environment entirely.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::DuplicateLabelName`] if one of the labels has the name another
+    /// label is recorded under with [`label_name`](Self::label_name).
+    #[cfg(feature = "metrics")]
+    pub fn labels_from_env(mut self) -> Result<Self, ConfigError> {
+        self.env_labels = ENV_LABELS
+            .iter()
+            .filter_map(|(variable, key)| {
+                let value = std::env::var(variable)
+                    .ok()
+                    .filter(|value| !value.is_empty())?;
+                Some(KeyValue::new(*key, value))
+            })
+            .collect();
+        self.combine_static_labels()
+    }
+
+    #[cfg(feature = "metrics")]
+    fn combine_static_labels(mut self) -> Result<Self, ConfigError> {
+        let overridden = |label: &&KeyValue| {
+            self.explicit_labels
+                .iter()
+                .any(|explicit| explicit.key == label.key)
+        };
+        let env_labels = self.env_labels.iter().filter(|label| !overridden(label));
+        self.static_labels = self
+            .explicit_labels
+            .iter()
+            .chain(env_labels)
+            .cloned()
+            .collect();
+        metrics::validate_label_names(&self.label_names, &self.static_labels)?;
+        // The labels are baked into the instruments
+        self.instruments = Default::default();
+        Ok(self)
+    }
+
+    /// Record the `label` of the extension's metrics under a different name, e.g. for backends
+    /// which reserve the label name, or to follow a team's own conventions:
+    ///
+    /// ```rust
+    /// use async_graphql_telemetry_extension::OpenTelemetryConfig;
+    ///
+    /// let config = OpenTelemetryConfig::default()
+    ///     .label_name("query_name", "graphql_operation")
+    ///     .unwrap();
+    /// ```
+    ///
+    /// The static labels are renamed too, and renaming a label again replaces its previous name.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::InvalidLabelName`] if the name doesn't start with an ASCII letter,
+    /// contains anything other than ASCII alphanumerics, `_` or `.`, or is over 255 characters
+    /// long. Returns [`ConfigError::DuplicateLabelName`] if it would leave two labels sharing a
+    /// name, whether they're the extension's labels or static labels, which are checked against
+    /// the renamed labels whichever of the two is set first.
+    #[cfg(feature = "metrics")]
+    pub fn label_name(
+        mut self,
+        label: impl Into<Key>,
+        name: impl Into<Key>,
+    ) -> Result<Self, ConfigError> {
+        let label = label.into();
+        let mut renames = self
+            .label_names
+            .iter()
+            .filter(|(from, _)| *from != label)
+            .cloned()
+            .collect::<Vec<_>>();
+        renames.push((label, name.into()));
+        metrics::validate_label_names(&renames, &self.static_labels)?;
+        self.label_names = renames.into();
+        self.instruments = Default::default();
+        Ok(self)
+    }
+
+    /// Record the metrics with the provided meter, rather than the global meter.
+    ///
+    /// Without a meter the metrics are recorded with the global meter provider. Until a provider
+    /// is installed it's checked for again on every request, so that metrics still flow if the
+    /// provider is installed after the schema has been built, and once one is the instruments are
+    /// created with it once and reused. With a meter the instruments are created once and
+    /// reused.
+    ///
+    /// The temporality the metrics are exported with is decided by the pipeline the meter comes
+    /// from, see [`Temporality`](crate::Temporality).
+    #[cfg(feature = "metrics")]
+    pub fn meter(self, meter: Meter) -> Self {
+        self.shared_meter(Arc::new(meter))
+    }
+
+    /// Record the `graphql_requests`, `graphql_request_errors`, `graphql_field_errors` &
+    /// `graphql_request_duration` metrics into a `prometheus` registry, rather than with the meter.
+    ///
+    /// This is for servers which export their traces with OpenTelemetry, but scrape their metrics
+    /// from a plain `prometheus` registry. Only one backend records each instrument, so these four
+    /// aren't recorded with the meter at all, while the rest of the metrics still are. The
+    /// duration is a histogram with buckets from 1ms, doubling up to ~33s, and every label the
+    /// extension records is registered with each metric, with the labels which don't apply to a
+    /// measurement left empty.
+    ///
+    /// The instruments are registered the first time they're used, under their configured
+    /// [`metric_names`](Self::metric_names). If the registry already has a metric under one of
+    /// the names (or one of the label names isn't valid for Prometheus), a warning is logged and
+    /// the instruments are recorded with the meter instead.
+    #[cfg(feature = "prometheus")]
+    pub fn prometheus_registry(mut self, registry: prometheus::Registry) -> Self {
+        self.prometheus = Some((registry, Default::default()));
+        self.instruments = Default::default();
+        self
+    }
+
+    /// The instruments registered with the Prometheus registry, if one is set
+    #[cfg(feature = "prometheus")]
+    pub(crate) fn prometheus_instruments(&self) -> Option<&CoreInstruments> {
+        let (registry, instruments) = self.prometheus.as_ref()?;
+        instruments
+            .get_or_init(|| {
+                let labels = LabelPolicy::new(self);
+                CoreInstruments::register(registry, &self.metric_names, &labels)
+                    .map_err(|err| {
+                        tracing::warn!(
+                            target: crate::TARGET,
+                            error = %err,
+                            "failed to register the metrics with the Prometheus registry, \
+                             recording them with the meter instead",
+                        )
+                    })
+                    .ok()
+            })
+            .as_ref()
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) fn shared_meter(mut self, meter: Arc<Meter>) -> Self {
+        self.meter = Some(meter);
+        self.instruments = Default::default();
+        self
+    }
+
+    /// Measure the durations with the provided clock, rather than the [`SystemClock`].
+    ///
+    /// This is mostly useful in tests, where a clock which only moves when told to makes the
+    /// recorded durations exact. See [`Clock`] for what it's used for.
+    ///
+    /// [`SystemClock`]: crate::SystemClock
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Compute extra fields to record on each `field` span from the field's [`ResolveInfo`].
+    ///
+    /// `tracing` spans can only record the fields they were declared with, so the returned pairs
+    /// are formatted as `key=value`, separated by spaces, into the single `custom` field of the
+    /// span. They aren't recorded when tail sampling the field spans.
+    ///
+    /// The closure runs for every field resolved, on the hot path of the request, and any
+    /// allocations it makes are paid on every field, so it's best kept to debugging or low
+    /// traffic schemas.
+    ///
+    /// ```rust
+    /// use std::sync::Arc;
+    /// use async_graphql::extensions::ResolveInfo;
+    /// use async_graphql_telemetry_extension::OpenTelemetryConfig;
+    ///
+    /// let config = OpenTelemetryConfig::default().field_span_fields(Arc::new(
+    ///     |info: &ResolveInfo<'_>| vec![("alias", info.alias.unwrap_or_default().to_string())],
+    /// ));
+    /// ```
+    pub fn field_span_fields(mut self, fields: Arc<ComputeSpanFields>) -> Self {
+        self.field_span_fields = Some(FieldSpanFields(fields));
+        self
+    }
+
+    /// Record whether each field's return type is nullable as the `nullable` field of its `field`
+    /// span.
+    ///
+    /// An error in a non-null field nulls out its nearest nullable ancestor, so correlating the
+    /// fields which came back null with their positions in the schema shows how far each error
+    /// spread. It's only a span field, rather than a metric label, so it doesn't add any series.
+    pub fn record_field_nullability(mut self, enabled: bool) -> Self {
+        self.record_field_nullability = enabled;
+        self
+    }
+
+    /// Record the names of the listed fragments on the `request` span, as the `fragments` field
+    /// (e.g. `PersonDetails,PersonName`), when the operation spreads them.
+    ///
+    /// The number of named fragments each operation spreads (including those spread by its
+    /// fragments) is always recorded as the `fragment_count` field, which shows how heavily a
+    /// client composes its queries out of fragments. The names are only recorded for the listed
+    /// fragments, to keep the field's values bounded, with any others recorded as a single
+    /// `other`.
+    pub fn record_fragment_names<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.fragment_name_allowlist = Some(Arc::new(names.into_iter().map(Into::into).collect()));
+        self
+    }
+
+    /// Record the complexity cost each field is assigned in the schema, as the `complexity_cost`
+    /// field of its `field` span, to show which fields the cost model weights heavily.
+    ///
+    /// `ResolveInfo` only names the field & the type it belongs to, so the cost is looked up in
+    /// the schema's registry (which async-graphql exposes to extensions, although it's hidden from
+    /// its docs) under the field's `parent_type` & name. Only constant costs, i.e.
+    /// `#[graphql(complexity = 5)]`, are recorded. Fields without a cost (which async-graphql
+    /// counts as `1`) or whose cost is computed from their arguments & children are recorded
+    /// without the field, as the extension can't evaluate the computation.
+    pub fn record_field_complexity(mut self, enabled: bool) -> Self {
+        self.record_field_complexity = enabled;
+        self
+    }
+
+    /// Record where each field is selected in the query, as the `location` field (`line:column`)
+    /// of its `field` span.
+    ///
+    /// This points from a slow or failing field straight to its selection in the document. The
+    /// elements of a list are located at the list's field, and fields which can't be located in
+    /// the document have no `location`. As with the nullability it's only a span field, so it
+    /// doesn't add any series, but locating the fields means walking the document of every
+    /// request.
+    pub fn record_field_location(mut self, enabled: bool) -> Self {
+        self.record_field_location = enabled;
+        self
+    }
+
+    /// Hand the field timings of each request to the provided closure, in the structure of
+    /// Apollo's `Trace` report, once the request has been executed.
+    ///
+    /// This is for hosts which report traces to Apollo Studio, see the [`apollo`](crate::apollo)
+    /// module for how the trace maps onto the protobuf message. The closure is called on the
+    /// request's task, so it should hand the trace off (e.g. to a channel) rather than send it
+    /// itself.
+    ///
+    /// ```rust
+    /// use std::sync::Arc;
+    /// use async_graphql_telemetry_extension::{apollo::Trace, OpenTelemetryConfig};
+    ///
+    /// let (sender, receiver) = std::sync::mpsc::sync_channel::<Trace>(1024);
+    /// let config = OpenTelemetryConfig::default().apollo_trace_sink(Arc::new(move |trace| {
+    ///     let _ = sender.try_send(trace);
+    /// }));
+    /// ```
+    pub fn apollo_trace_sink(mut self, sink: Arc<ReportTrace>) -> Self {
+        self.apollo_trace_sink = Some(ApolloTraceSink(sink));
+        self
+    }
+
+    /// Submit the tracing data of each request to the provided sink, once the request has been
+    /// executed.
+    ///
+    /// This can be called more than once, to submit the data to several sinks. The sinks are
+    /// submitted to after those enabled by
+    /// [`return_tracing_data_to_client`](Self::return_tracing_data_to_client) &
+    /// [`log_tracing_json`](Self::log_tracing_json), see the [`sink`](crate::sink) module.
+    pub fn tracing_sink(mut self, sink: Arc<dyn TracingSink>) -> Self {
+        self.tracing_sinks = self
+            .tracing_sinks
+            .iter()
+            .cloned()
+            .chain(Some(sink))
+            .collect();
+        self
+    }
+
+    /// The names of the fragments to record on the `request` span, if they're recorded
+    pub(crate) fn fragment_names(&self, fragments: &BTreeSet<&str>) -> Option<String> {
+        let allowlist = self.fragment_name_allowlist.as_ref()?;
+        let (listed, unlisted): (Vec<&str>, Vec<&str>) = fragments
+            .iter()
+            .partition(|fragment| allowlist.contains(**fragment));
+        let mut names = listed;
+        if !unlisted.is_empty() {
+            names.push("other");
+        }
+        Some(names.join(","))
+    }
+
+    /// Whether the serialized size of each request's variables is recorded
+    pub(crate) fn records_variables_bytes(&self) -> bool {
+        #[cfg(feature = "metrics")]
+        return self.record_variables_bytes;
+        #[cfg(not(feature = "metrics"))]
+        false
+    }
+
+    /// Whether the directives applied to each operation are counted
+    pub(crate) fn counts_operation_directives(&self) -> bool {
+        #[cfg(feature = "metrics")]
+        return self.operation_directive_allowlist.is_some();
+        #[cfg(not(feature = "metrics"))]
+        false
+    }
+
+    /// Whether the time each request spends in the extension is recorded
+    pub(crate) fn records_instrumentation_overhead(&self) -> bool {
+        #[cfg(feature = "metrics")]
+        return self.record_instrumentation_overhead;
+        #[cfg(not(feature = "metrics"))]
+        false
+    }
+
+    /// Whether the time the `resolve` hook spends on its own bookkeeping needs to be measured
+    pub(crate) fn times_resolve_bookkeeping(&self) -> bool {
+        self.instrumentation_budget.is_some() || self.records_instrumentation_overhead()
+    }
+
+    /// Whether the fields resolved by each operation need to be counted
+    pub(crate) fn counts_resolved_fields(&self) -> bool {
+        self.record_complexity_estimate_error || self.response_field_limit.is_some()
+    }
+
+    /// Whether the timings of each resolved field need to be collected
+    pub(crate) fn collects_resolve_stats(&self) -> bool {
+        self.keeps_resolve_stats() || self.buffers_field_spans()
+    }
+
+    /// Whether the timings of every resolved field are kept until the request is executed
+    pub(crate) fn keeps_resolve_stats(&self) -> bool {
+        self.reports_resolve_stats()
+            || self.records_field_self_duration()
+            || self.slow_request_threshold.is_some()
+    }
+
+    /// Whether the self time of each field is recorded
+    fn records_field_self_duration(&self) -> bool {
+        #[cfg(feature = "metrics")]
+        return self.record_field_self_duration;
+        #[cfg(not(feature = "metrics"))]
+        false
+    }
+
+    /// Whether the field spans are emitted from their buffered timings at the end of `execute`,
+    /// rather than live
+    pub(crate) fn buffers_field_spans(&self) -> bool {
+        self.tail_sampling_threshold.is_some() || self.field_span_min_duration.is_some()
+    }
+
+    /// Whether the timings of every resolved field are reported once the request is executed
+    pub(crate) fn reports_resolve_stats(&self) -> bool {
+        self.return_tracing_data_to_client
+            || self.log_tracing_json
+            || self.apollo_trace_sink.is_some()
+            || !self.tracing_sinks.is_empty()
+    }
+
+    /// Submits the summary to each of the tracing sinks, including the built-in ones enabled by
+    /// the other options
+    pub(crate) fn submit_tracing_summary(&self, summary: &TracingSummary) {
+        if self.return_tracing_data_to_client {
+            ReturnTracingToClient.submit(summary.clone());
+        }
+        if self.log_tracing_json {
+            LogTracingJson.submit(summary.clone());
+        }
+        for sink in self.tracing_sinks.iter() {
+            sink.submit(summary.clone());
+        }
+    }
+
+    /// Hands the trace to the Apollo trace sink, if one is set
+    pub(crate) fn report_apollo_trace(&self, metrics: &Metrics) {
+        if let Some(ApolloTraceSink(sink)) = &self.apollo_trace_sink {
+            sink(Trace::from_metrics(metrics));
+        }
+    }
+
+    /// The path of the field, as it's recorded on the metric labels
+    #[cfg(feature = "metrics")]
+    pub(crate) fn normalize_path(&self, path_node: &QueryPathNode<'_>) -> String {
+        match &self.path_normalizer {
+            Some(PathNormalizer(normalizer)) => normalizer(&path_node.to_string_vec()),
+            None => collapse_list_indices(path_node),
+        }
+    }
+
+    /// The severity of the field error, as it's recorded on the metric labels
+    #[cfg(feature = "metrics")]
+    pub(crate) fn error_severity_of(&self, err: &ServerError) -> Severity {
+        match &self.error_severity {
+            Some(ErrorSeverity(classify)) => classify(err),
+            None => Severity::from_error(err),
+        }
+    }
+
+    /// The category of the validation rule the error came from
+    pub(crate) fn validation_rule(&self, err: &ServerError) -> &'static str {
+        match &self.validation_rule_classifier {
+            Some(ValidationRuleClassifier(classify)) => classify(err),
+            None => crate::classify_validation_error(err),
+        }
+    }
+
+    /// The extra fields for the field's span, formatted for the `custom` field
+    pub(crate) fn custom_span_fields(&self, info: &ResolveInfo<'_>) -> Option<String> {
+        let FieldSpanFields(compute) = self.field_span_fields.as_ref()?;
+        let fields = compute(info)
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>();
+        Some(fields.join(" "))
+    }
+
+    /// The name & version of the client to record on the metric labels
+    #[cfg(feature = "metrics")]
+    pub(crate) fn client_labels<'a>(&self, client: &'a ClientInfo) -> (&'a str, &'a str) {
+        match &self.client_allowlist {
+            Some(allowlist) if !allowlist.contains(&client.name) => ("other", "other"),
+            _ => (&client.name, &client.version),
+        }
+    }
+
+    /// The name to count the operation directive under, if they're counted
+    #[cfg(feature = "metrics")]
+    pub(crate) fn operation_directive_label<'a>(&self, directive: &'a str) -> Option<&'a str> {
+        let allowlist = self.operation_directive_allowlist.as_ref()?;
+        Some(if allowlist.contains(directive) {
+            directive
+        } else {
+            "other"
+        })
+    }
+
+    /// Whether a field error with the code is an authorization rejection
+    #[cfg(feature = "metrics")]
+    pub(crate) fn is_authz_error(&self, code: &str) -> bool {
+        match &self.authz_error_codes {
+            Some(codes) => codes.contains(code),
+            None => DEFAULT_AUTHZ_ERROR_CODES.contains(&code),
+        }
+    }
+
+    /// The current instant, from the configured clock
+    pub(crate) fn now(&self) -> Instant {
+        match &self.clock {
+            Some(clock) => clock.now(),
+            None => Instant::now(),
+        }
+    }
+
+    /// The instant a phase starts waiting to be scheduled from, if the schedule delay is recorded
+    pub(crate) fn phase_schedule_start(&self) -> Option<Instant> {
+        #[cfg(feature = "metrics")]
+        if self.record_phase_schedule_delay {
+            return Some(self.now());
+        }
+        None
+    }
+
+    /// The current time, from the configured clock
+    pub(crate) fn utc_now(&self) -> DateTime<Utc> {
+        match &self.clock {
+            Some(clock) => clock.utc_now(),
+            None => Utc::now(),
+        }
+    }
+
+    /// The time since the instant, by the configured clock
+    pub(crate) fn elapsed(&self, since: Instant) -> Duration {
+        self.now().saturating_duration_since(since)
+    }
+
+    /// The configured clock, for measuring durations outside of the request
+    pub(crate) fn shared_clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone().unwrap_or_else(|| Arc::new(SystemClock))
+    }
+
+    /// The metric instruments of the provided meter, or of the global meter without one
+    #[cfg(feature = "metrics")]
+    pub(crate) fn instruments(&self) -> &Arc<Instruments> {
+        match &self.meter {
+            Some(meter) => self.instruments.meter(meter, self),
+            None => self.instruments.global(self),
+        }
+    }
+}
+
+/// The environment variables read by `labels_from_env`, and the labels they're recorded as
+#[cfg(feature = "metrics")]
+const ENV_LABELS: [(&str, &str); 3] = [
+    ("OTEL_SERVICE_NAME", "service_name"),
+    ("DEPLOYMENT_ENV", "deployment_env"),
+    ("REGION", "region"),
+];
+
+/// The error codes counted as authorization rejections, unless `authz_error_codes` is set
+#[cfg(feature = "metrics")]
+const DEFAULT_AUTHZ_ERROR_CODES: [&str; 2] = ["FORBIDDEN", "UNAUTHENTICATED"];
+
+#[cfg(feature = "metrics")]
+type NormalizePath = dyn Fn(&[String]) -> String + Send + Sync;
+#[cfg(feature = "metrics")]
+type ClassifyError = dyn Fn(&ServerError) -> Severity + Send + Sync;
+type ClassifyValidationError = dyn Fn(&ServerError) -> &'static str + Send + Sync;
+type ComputeSpanFields = dyn Fn(&ResolveInfo<'_>) -> Vec<(&'static str, String)> + Send + Sync;
+type ReportTrace = dyn Fn(Trace) + Send + Sync;
+
+bitflags::bitflags! {
+    /// The stages of a request to create spans for, see [`OpenTelemetryConfig::stages`].
+    pub struct StageFlags: u8 {
+        /// The root `request` span, or the `subscribe` span of a subscription
+        const REQUEST = 1 << 0;
+        /// The `parse` span
+        const PARSE = 1 << 1;
+        /// The `validation` span
+        const VALIDATION = 1 << 2;
+        /// The `execute` span
+        const EXECUTE = 1 << 3;
+        /// The `field` span of each resolved field
+        const RESOLVE = 1 << 4;
+    }
+}
+
+impl Default for StageFlags {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// How the resolved fields are recorded on the trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FieldRepresentation {
+    /// A `field` span for each resolved field, nested within the span of its parent field.
+    ///
+    /// Each live span records a `resolve_seq`, counting up from `0` in the order the request's
+    /// fields started resolving, which shows how sibling resolvers were actually scheduled. It
+    /// isn't recorded on the spans emitted by tail sampling, where the `start_offset` gives the
+    /// order instead.
+    #[default]
+    Spans,
+    /// A `field` event for each resolved field within the `execute` span, carrying the same
+    /// fields as the span, plus the `duration` it took to resolve (in nanoseconds) and whether it
+    /// resolved to an `error`.
+    ///
+    /// Each event is emitted once the field has resolved, so a large query produces a single
+    /// span with many events rather than thousands of tiny spans, which is far cheaper for
+    /// backends that bill or index by span. The tradeoff is that trace viewers can't draw the
+    /// fields on the timeline or show how they nest (the `path` has to be read instead), and the
+    /// events emitted by resolvers aren't grouped under their field any more.
+    Events,
+}
+
+/// How field errors are reported on the trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorEvents {
+    /// Emit a `tracing` error event within the `field` span.
+    ///
+    /// With the `tracing-opentelemetry` layer installed these are exported as events on the
+    /// OpenTelemetry span of the field, but without a layer which handles events they're
+    /// invisible.
+    #[default]
+    Tracing,
+    /// Add a `graphql.error` event directly to the active OpenTelemetry span, i.e. the span of
+    /// the `opentelemetry::Context` the request is executed within, carrying the `message`,
+    /// `path` & `code` (if set in the error extensions) as attributes.
+    ///
+    /// This is for services which use the OpenTelemetry API directly rather than `tracing`. The
+    /// event is skipped when the request isn't executed within an OpenTelemetry span.
+    OpenTelemetry,
+    /// Both of the above
+    Both,
+}
+
+impl ErrorEvents {
+    pub(crate) fn emits_tracing_events(self) -> bool {
+        matches!(self, Self::Tracing | Self::Both)
+    }
+
+    pub(crate) fn emits_span_events(self) -> bool {
+        matches!(self, Self::OpenTelemetry | Self::Both)
+    }
+}
+
+#[cfg(feature = "metrics")]
+#[derive(Clone)]
+struct PathNormalizer(Arc<NormalizePath>);
+
+#[cfg(feature = "metrics")]
+impl fmt::Debug for PathNormalizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PathNormalizer")
+    }
+}
+
+#[cfg(feature = "metrics")]
+#[derive(Clone)]
+struct ErrorSeverity(Arc<ClassifyError>);
+
+#[cfg(feature = "metrics")]
+impl fmt::Debug for ErrorSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ErrorSeverity")
+    }
+}
+
+#[derive(Clone)]
+struct ValidationRuleClassifier(Arc<ClassifyValidationError>);
+
+impl fmt::Debug for ValidationRuleClassifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ValidationRuleClassifier")
+    }
+}
+
+#[derive(Clone)]
+struct FieldSpanFields(Arc<ComputeSpanFields>);
+
+impl fmt::Debug for FieldSpanFields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FieldSpanFields")
+    }
+}
+
+#[derive(Clone)]
+struct ApolloTraceSink(Arc<ReportTrace>);
+
+impl fmt::Debug for ApolloTraceSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ApolloTraceSink")
+    }
+}
+
+/// Joins the segments of the path, skipping over any list indices
+pub(crate) fn collapse_list_indices(path_node: &QueryPathNode<'_>) -> String {
+    let mut names = std::iter::once(path_node)
+        .chain(path_node.parents())
+        .filter_map(|node| match node.segment {
+            QueryPathSegment::Name(name) => Some(name),
+            QueryPathSegment::Index(_) => None,
+        })
+        .collect::<Vec<_>>();
+    names.reverse();
+    names.join(".")
+}
+
+/// An error caused by an invalid [`OpenTelemetryConfig`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The metric name doesn't follow the OpenTelemetry instrument naming rules
+    InvalidMetricName(String),
+    /// The metric name is used by more than one instrument
+    DuplicateMetricName(String),
+    /// The schema version is too long, or contains characters which aren't allowed
+    InvalidSchemaVersion(String),
+    /// The label name doesn't follow the OpenTelemetry attribute naming rules
+    InvalidLabelName(String),
+    /// The label name is used by more than one label
+    DuplicateLabelName(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMetricName(name) => write!(f, "invalid metric name `{}`", name),
+            Self::DuplicateMetricName(name) => write!(f, "duplicate metric name `{}`", name),
+            Self::InvalidSchemaVersion(version) => {
+                write!(f, "invalid schema version `{}`", version)
+            }
+            Self::InvalidLabelName(name) => write!(f, "invalid label name `{}`", name),
+            Self::DuplicateLabelName(name) => write!(f, "duplicate label name `{}`", name),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
@@ -0,0 +1,579 @@
+//! Data the host can attach to a request to give the extension more context.
+//!
+//! Each of these is read with `ExtensionContext::data_opt`, so they can be
+//! provided either through `Request::data` or the schema data. They're all read
+//! at once, into a [`HostContext`], as the request is prepared, so that's the
+//! full set of inputs the extension accepts for a request. The one exception is
+//! the [`OpenTelemetryConfig`](crate::OpenTelemetryConfig), which is read by
+//! the first hook of the request, since it's needed before the request data is
+//! available, so it has to be provided through the schema data.
+
+#[cfg(feature = "metrics")]
+use std::borrow::Cow;
+#[cfg(feature = "metrics")]
+use std::collections::HashMap;
+#[cfg(feature = "metrics")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "metrics")]
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use async_graphql::extensions::ExtensionContext;
+#[cfg(feature = "metrics")]
+use opentelemetry::metrics::Meter;
+#[cfg(feature = "metrics")]
+use parking_lot::Mutex;
+use tracing::Span;
+
+#[cfg(feature = "metrics")]
+use crate::metrics::{Instruments, WsInstruments};
+
+/// When the request was received by the server, before any queuing.
+///
+/// When provided, the time between this and the start of execution is recorded
+/// as the `graphql_queue_time` metric, so that time spent waiting (e.g. behind a
+/// concurrency limit) can be separated from the time spent processing. It's also
+/// what the `graphql_time_to_first_response` metric measures from, for
+/// subscriptions as well as queries & mutations.
+///
+/// ```rust,ignore
+/// let request = Request::new(query).data(ReceivedAt(Instant::now()));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct ReceivedAt(pub Instant);
+
+/// The client application which sent the request, e.g. taken from the
+/// `apollographql-client-name` & `apollographql-client-version` headers.
+///
+/// When provided, the name & version are attached to the `graphql_requests`
+/// counter as the `client_name` & `client_version` labels, so traffic can be
+/// broken down by client build. See
+/// [`OpenTelemetryConfig::client_allowlist`](crate::OpenTelemetryConfig::client_allowlist)
+/// to limit which names are recorded.
+///
+/// ```rust,ignore
+/// let request = Request::new(query).data(ClientInfo {
+///     name: "ios".to_string(),
+///     version: "4.2.0".to_string(),
+/// });
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// The name of the client application
+    pub name: String,
+    /// The version of the client application
+    pub version: String,
+}
+
+/// The transport the request was received over.
+///
+/// When provided, it's recorded as the `transport` field of the `request` span
+/// and attached to the `graphql_requests` counter as the `transport` label, so
+/// problems with WebSocket connections can be told apart from those over HTTP.
+/// Requests without it are recorded with the `unknown` transport. Subscriptions
+/// are counted before the request data is available, so the
+/// `graphql_subscriptions` counter isn't labelled with it.
+///
+/// ```rust,ignore
+/// let request = Request::new(query).data(Transport::Http);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// An HTTP request, e.g. a `POST` or `GET` to the GraphQL endpoint
+    Http,
+    /// A message over a WebSocket connection, e.g. using `graphql-ws`
+    WebSocket,
+}
+
+impl Transport {
+    /// The name of the transport, as recorded
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Http => "http",
+            Self::WebSocket => "websocket",
+        }
+    }
+}
+
+/// The version of HTTP the request was received over, e.g. `HTTP/2`.
+///
+/// When provided, it's recorded as the `http.protocol` field of the `request`
+/// span, and with
+/// [`OpenTelemetryConfig::http_protocol_label`](crate::OpenTelemetryConfig::http_protocol_label)
+/// attached to the `graphql_requests` counter as the `http_protocol` label, as
+/// one of `1.0`, `1.1`, `2` or `3`. The version is recognised with or without
+/// an `HTTP/` prefix (so the `Debug` output of `http::Version` can be passed
+/// straight through), along with `h2` & `h3`. Anything else is ignored, which
+/// keeps the label bounded.
+///
+/// ```rust,ignore
+/// let request = Request::new(query).data(HttpProtocol(format!("{:?}", req.version())));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpProtocol(pub String);
+
+impl HttpProtocol {
+    /// The version, as recorded, if it's one of the known versions
+    pub(crate) fn version(&self) -> Option<&'static str> {
+        let version = self.0.trim();
+        let version = version
+            .strip_prefix("HTTP/")
+            .or_else(|| version.strip_prefix("http/"))
+            .unwrap_or(version);
+        match version {
+            "1.0" => Some("1.0"),
+            "1.1" => Some("1.1"),
+            "2" | "2.0" | "h2" => Some("2"),
+            "3" | "3.0" | "h3" => Some("3"),
+            _ => None,
+        }
+    }
+}
+
+/// Where the request is within a GraphQL-over-HTTP batch, i.e. a request whose
+/// body is an array of operations.
+///
+/// async-graphql executes each operation of a batch as a request of its own, so
+/// the extension only ever sees one operation at a time, and the batch is only
+/// known to the host's transport layer, which has to attach this to each of the
+/// batch's requests. When provided, it's recorded as the `batch_size` &
+/// `batch_index` fields of each `request` span, and the first request of the
+/// batch records its size as the `graphql_batch_size` metric, so that each
+/// batch is only recorded once. With
+/// [`OpenTelemetryConfig::batch_size_limit`](crate::OpenTelemetryConfig::batch_size_limit)
+/// the batches over the limit are counted by the `graphql_oversized_batches`
+/// metric too.
+///
+/// ```rust,ignore
+/// if let BatchRequest::Batch(requests) = &mut batch {
+///     let size = requests.len();
+///     for (index, request) in requests.iter_mut().enumerate() {
+///         request.data.insert(BatchPosition { index, size });
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchPosition {
+    /// The position of the request within the batch, from `0`
+    pub index: usize,
+    /// The number of operations in the batch
+    pub size: usize,
+}
+
+/// The version (or hash) of the schema serving the request.
+///
+/// When provided, it's attached to the request & error counters as the
+/// `schema_version` label, so that changes in the error rate during a rollout
+/// can be attributed to a schema. A version attached to the request data takes
+/// precedence over one set with
+/// [`OpenTelemetryConfig::schema_version`](crate::OpenTelemetryConfig::schema_version).
+///
+/// To keep the number of series bounded, versions longer than 64 characters
+/// or containing anything other than ASCII alphanumerics, `.`, `_`, `-`, `+` or
+/// `:` are ignored.
+///
+/// ```rust,ignore
+/// let request = Request::new(query).data(SchemaVersion("2021-05-04.1".to_string()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaVersion(pub String);
+
+impl SchemaVersion {
+    #[cfg(feature = "metrics")]
+    pub(crate) fn is_valid(&self) -> bool {
+        (1..=64).contains(&self.0.len())
+            && self
+                .0
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '+' | ':'))
+    }
+}
+
+/// When the host will give up on the request, e.g. the time it was received
+/// plus the server's request timeout.
+///
+/// When provided, a request which is still executing when the deadline passes
+/// is counted by the `graphql_deadline_exceeded` metric and has the
+/// `deadline_exceeded` field of its `request` span set, even if it goes on to
+/// complete. This surfaces requests running close to the timeout before they're
+/// actually cancelled.
+///
+/// ```rust,ignore
+/// let request = Request::new(query).data(Deadline(Instant::now() + Duration::from_secs(10)));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline(pub Instant);
+
+/// The host's own id for the request, e.g. taken from an `x-request-id` header.
+///
+/// When provided, it's recorded as the `request_id` field of every span created
+/// for the request (the `request`, `parse`, `validation`, `execute` & `field`
+/// spans), so the spans can be correlated with the host's logs. This is
+/// independent of the OpenTelemetry trace id. Subscriptions are started before
+/// the request data is available, so their `subscribe` span doesn't have it.
+///
+/// ```rust,ignore
+/// let request = Request::new(query).data(RequestId(header_value.to_string()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+/// Spans the request's root span is linked to, rather than being their child.
+///
+/// Links relate traces which don't nest, e.g. a subscription to the request
+/// which set it up, or an operation to each of the upstream events it fans in.
+/// Each span is added to the `subscribe` span of a subscription (or the
+/// `request` span of a query or mutation) with `Span::follows_from`, which the
+/// `tracing-opentelemetry` layer exports as an OpenTelemetry span link to the
+/// span's context. The spans have to be created with the same subscriber as
+/// the extension's spans, and only need to have been created, not entered or
+/// still open.
+///
+/// ```rust,ignore
+/// let source = tracing::Span::current();
+/// let request = Request::new(subscription).data(SpanLinks(vec![source]));
+/// let stream = schema.execute_stream(request);
+/// ```
+///
+/// `tracing-opentelemetry` only links to the context of a `tracing` span, so a
+/// context extracted from an upstream event has to be given a span of its own,
+/// e.g. one created with the context as its parent.
+#[derive(Debug, Clone, Default)]
+pub struct SpanLinks(pub Vec<Span>);
+
+/// The OpenTelemetry context the request was propagated with, e.g. extracted
+/// from the `traceparent` header with a `TraceContextPropagator`.
+///
+/// When provided, it's set as the parent of the `request` span (or the
+/// `subscribe` span of a subscription) in place of the context the request is
+/// executed within, and its sampled flag is the upstream sampling decision
+/// [`OpenTelemetryConfig::ignore_upstream_sampling`](crate::OpenTelemetryConfig::ignore_upstream_sampling)
+/// refers to. This saves the host executing each request within the extracted
+/// context itself.
+///
+/// ```rust,ignore
+/// let parent = TraceContextPropagator::new().extract(&HeaderExtractor(&headers));
+/// let request = Request::new(query).data(TraceParent(parent));
+/// ```
+#[derive(Debug, Clone)]
+pub struct TraceParent(pub opentelemetry::Context);
+
+/// The meter to record the request's metrics with, for servers where each
+/// tenant (or runtime) has its own meter provider.
+///
+/// This takes precedence over both the global meter and the one set with
+/// [`OpenTelemetryConfig::meter`](crate::OpenTelemetryConfig::meter), but it
+/// only applies to requests it's attached to. Because the instruments can't be
+/// shared between requests, they're created from the meter for every request
+/// carrying it, costing a lookup in the meter's instrument registry for each of
+/// them. Subscriptions are counted before the request data is available, so
+/// they're always recorded with the configured or global meter.
+///
+/// ```rust,ignore
+/// let request = Request::new(query).data(TenantMeter(tenant.meter()));
+/// ```
+#[cfg(feature = "metrics")]
+#[derive(Debug)]
+pub struct TenantMeter(pub Meter);
+
+/// The number of batches the request's DataLoaders dispatched, and the number
+/// of keys they loaded, fed in by the host.
+///
+/// async-graphql's `DataLoader` doesn't expose any statistics, and a `Loader`
+/// has no access to the request, so the counts have to be recorded by the
+/// loader itself. The stats are shared between clones: attach one clone to the
+/// request data and give another to the request's loader, which calls
+/// [`record_batch`](Self::record_batch) from its `load` implementation. Once the
+/// request has been executed the counts are added to the
+/// `graphql_dataloader_batches` & `graphql_dataloader_loads` metrics, so that
+/// `loads / batches` gives the average batch size.
+///
+/// ```rust,ignore
+/// let stats = DataLoaderStats::default();
+/// let loader = DataLoader::new(UserLoader { pool, stats: stats.clone() });
+/// let request = Request::new(query).data(loader).data(stats);
+///
+/// #[async_trait::async_trait]
+/// impl Loader<UserId> for UserLoader {
+///     async fn load(&self, keys: &[UserId]) -> Result<HashMap<UserId, User>, Self::Error> {
+///         self.stats.record_batch(keys.len());
+///         // ..
+///     }
+/// }
+/// ```
+///
+/// The counts are only read at the end of the request, so a loader shared
+/// between requests (e.g. one in the schema data) can't attribute its batches
+/// to them.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Default)]
+pub struct DataLoaderStats(Arc<DataLoaderCounts>);
+
+#[cfg(feature = "metrics")]
+#[derive(Debug, Default)]
+struct DataLoaderCounts {
+    batches: AtomicU64,
+    loads: AtomicU64,
+}
+
+#[cfg(feature = "metrics")]
+impl DataLoaderStats {
+    /// Records a batch of `keys` keys being loaded
+    pub fn record_batch(&self, keys: usize) {
+        self.0.batches.fetch_add(1, Ordering::Relaxed);
+        self.0.loads.fetch_add(keys as u64, Ordering::Relaxed);
+    }
+
+    /// The number of batches & keys loaded so far
+    pub(crate) fn counts(&self) -> (u64, u64) {
+        (
+            self.0.batches.load(Ordering::Relaxed),
+            self.0.loads.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// The number of times the request's resolvers retried, fed in by the host.
+///
+/// Retries happen within a resolver, which the extension can't see into, so a
+/// resolver which retries has to count its own attempts. The request data is
+/// looked up for a `RetryStats` (this exact type, by its `TypeId`, the same as
+/// any other `Context::data`), and it's shared between clones: attach one to
+/// the request data and have each resolver call
+/// [`record_retry`](Self::record_retry) (or
+/// [`record_attempts`](Self::record_attempts) once it's done) through
+/// `ctx.data_unchecked::<RetryStats>()`. Once the request has been executed the
+/// count is added to the `graphql_resolver_retries` metric, so that a storm of
+/// retries shows up even when they eventually succeed.
+///
+/// ```rust,ignore
+/// let request = Request::new(query).data(RetryStats::default());
+///
+/// async fn user(&self, ctx: &Context<'_>, id: UserId) -> Result<User> {
+///     let stats = ctx.data_unchecked::<RetryStats>();
+///     loop {
+///         match self.client.get(id).await {
+///             Err(err) if err.is_transient() => stats.record_retry(),
+///             result => return result,
+///         }
+///     }
+/// }
+/// ```
+///
+/// Like [`DataLoaderStats`], the count is only read at the end of the request,
+/// so each request needs a `RetryStats` of its own: one in the schema data would
+/// have its running total added again by every request.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Default)]
+pub struct RetryStats(Arc<AtomicU64>);
+
+#[cfg(feature = "metrics")]
+impl RetryStats {
+    /// Records a single retry
+    pub fn record_retry(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the retries of a resolver which made `attempts` attempts, i.e. one fewer retries
+    pub fn record_attempts(&self, attempts: u64) {
+        self.0
+            .fetch_add(attempts.saturating_sub(1), Ordering::Relaxed);
+    }
+
+    /// The number of retries recorded so far
+    pub(crate) fn retries(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A WebSocket connection, for the connection-level metrics.
+///
+/// async-graphql's connection lifecycle isn't visible to extensions, which only
+/// see the operations sent over the connection, so the host creates one of
+/// these for each connection and attaches it to the connection's data, e.g. the
+/// `Data` returned by the `on_connection_init` callback of `GraphQLWebSocket`.
+/// Every operation sent over the connection then sees it, and:
+/// - the connection is counted by the `graphql_ws_connections_active` metric
+///   from its first operation, until the connection's data is dropped when it
+///   closes
+/// - each operation is counted by the `graphql_ws_messages` metric, along with
+///   any other messages the host reports with [`message`](Self::message)
+///
+/// ```rust,ignore
+/// GraphQLWebSocket::new(stream, schema, protocol).on_connection_init(|_| async {
+///     let mut data = Data::default();
+///     data.insert(WsConnection::default());
+///     Ok(data)
+/// })
+/// ```
+///
+/// A connection which closes without sending an operation is never counted.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Default)]
+pub struct WsConnection(Arc<WsConnectionState>);
+
+#[cfg(feature = "metrics")]
+#[derive(Debug, Default)]
+struct WsConnectionState {
+    /// The instruments the connection is recorded with, once the extension has seen it
+    instruments: OnceLock<WsInstruments>,
+    /// The messages reported before the extension saw the connection
+    pending_messages: AtomicU64,
+}
+
+#[cfg(feature = "metrics")]
+impl WsConnection {
+    /// Records a message the extension doesn't see itself, e.g. a ping
+    pub fn message(&self) {
+        match self.0.instruments.get() {
+            Some(instruments) => instruments.messages.add(1, &[]),
+            None => {
+                self.0.pending_messages.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Records an operation sent over the connection, counting the connection as active the
+    /// first time
+    pub(crate) fn record_operation(&self, instruments: &Instruments) {
+        let instruments = self.0.instruments.get_or_init(|| {
+            instruments.ws_connections_active.add(1, &[]);
+            WsInstruments {
+                active: instruments.ws_connections_active.clone(),
+                messages: instruments.ws_messages.clone(),
+            }
+        });
+        let pending = self.0.pending_messages.swap(0, Ordering::Relaxed);
+        instruments.messages.add(1 + pending, &[]);
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Drop for WsConnectionState {
+    fn drop(&mut self) {
+        if let Some(instruments) = self.instruments.get() {
+            instruments.active.add(-1, &[]);
+        }
+    }
+}
+
+/// The business metrics accumulated by the request's resolvers, e.g. the value
+/// of the orders placed by a mutation.
+///
+/// Attach a fresh accumulator to each request, and have the resolvers
+/// [`add`](Self::add) their values to it under the keys of the
+/// [`BusinessMetricSpec`](crate::BusinessMetricSpec)s set with
+/// [`OpenTelemetryConfig::business_metrics`](crate::OpenTelemetryConfig::business_metrics).
+/// Once the request has been executed the accumulator is drained: the total for
+/// each key is recorded by its instrument & as a `business_metric` event on the
+/// `request` span, so the values can be correlated with the rest of the trace.
+///
+/// ```rust,ignore
+/// let request = Request::new(query).data(BusinessMetrics::default());
+///
+/// async fn place_order(&self, ctx: &Context<'_>, order: NewOrder) -> Result<Order> {
+///     let order = self.orders.place(order).await?;
+///     if let Some(metrics) = ctx.data_opt::<BusinessMetrics>() {
+///         metrics.add("order_value", order.total);
+///     }
+///     Ok(order)
+/// }
+/// ```
+///
+/// Values added under the same key are summed, keys without a spec are
+/// discarded, and nothing is recorded for a key which wasn't added to, so value
+/// recorders aren't skewed by requests which didn't touch them. Values added
+/// after execution has finished (e.g. by a spawned task) are lost, and an
+/// accumulator in the schema data would be shared by every request, so it
+/// should only ever be attached to the request data.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Default)]
+pub struct BusinessMetrics(Arc<Mutex<HashMap<Cow<'static, str>, f64>>>);
+
+#[cfg(feature = "metrics")]
+impl BusinessMetrics {
+    /// Adds the value to the request's total for the key
+    pub fn add(&self, key: impl Into<Cow<'static, str>>, value: f64) {
+        *self.0.lock().entry(key.into()).or_default() += value;
+    }
+
+    /// Takes the totals accumulated so far
+    pub(crate) fn drain(&self) -> HashMap<Cow<'static, str>, f64> {
+        std::mem::take(&mut *self.0.lock())
+    }
+}
+
+/// Everything the host attached to a request for the extension, each of the
+/// types in this module read from the request's `ExtensionContext` once.
+///
+/// The extension resolves this as the request is prepared, and every later hook
+/// of the request works from it, rather than looking the types up again. Hosts'
+/// own extensions can resolve it the same way, e.g. to log what a request was
+/// given. The [`TenantMeter`] is the one type left out, as a meter can't be
+/// cloned, but it's only used while the request is prepared anyway.
+#[derive(Debug, Clone, Default)]
+pub struct HostContext {
+    /// See [`ReceivedAt`]
+    pub received_at: Option<Instant>,
+    /// See [`ClientInfo`]
+    pub client: Option<ClientInfo>,
+    /// See [`Transport`]
+    pub transport: Option<Transport>,
+    /// See [`HttpProtocol`], before it's checked to be a known version
+    pub http_protocol: Option<HttpProtocol>,
+    /// See [`BatchPosition`]
+    pub batch: Option<BatchPosition>,
+    /// See [`SchemaVersion`], before it's checked to be bounded
+    pub schema_version: Option<SchemaVersion>,
+    /// See [`Deadline`]
+    pub deadline: Option<Instant>,
+    /// See [`RequestId`]
+    pub request_id: Option<RequestId>,
+    /// See [`SpanLinks`]
+    pub span_links: Vec<Span>,
+    /// See [`TraceParent`]
+    pub trace_parent: Option<opentelemetry::Context>,
+    /// See [`DataLoaderStats`]
+    #[cfg(feature = "metrics")]
+    pub dataloader_stats: Option<DataLoaderStats>,
+    /// See [`RetryStats`]
+    #[cfg(feature = "metrics")]
+    pub retry_stats: Option<RetryStats>,
+    /// See [`WsConnection`]
+    #[cfg(feature = "metrics")]
+    pub ws_connection: Option<WsConnection>,
+    /// See [`BusinessMetrics`]
+    #[cfg(feature = "metrics")]
+    pub business_metrics: Option<BusinessMetrics>,
+}
+
+impl HostContext {
+    /// Reads each of the types the host may have attached to the request
+    pub fn from_context(ctx: &ExtensionContext<'_>) -> Self {
+        Self {
+            received_at: ctx.data_opt::<ReceivedAt>().map(|ReceivedAt(at)| *at),
+            client: ctx.data_opt::<ClientInfo>().cloned(),
+            transport: ctx.data_opt::<Transport>().copied(),
+            http_protocol: ctx.data_opt::<HttpProtocol>().cloned(),
+            batch: ctx.data_opt::<BatchPosition>().copied(),
+            schema_version: ctx.data_opt::<SchemaVersion>().cloned(),
+            deadline: ctx.data_opt::<Deadline>().map(|Deadline(at)| *at),
+            request_id: ctx.data_opt::<RequestId>().cloned(),
+            span_links: ctx
+                .data_opt::<SpanLinks>()
+                .map(|SpanLinks(links)| links.clone())
+                .unwrap_or_default(),
+            trace_parent: ctx
+                .data_opt::<TraceParent>()
+                .map(|TraceParent(parent)| parent.clone()),
+            #[cfg(feature = "metrics")]
+            dataloader_stats: ctx.data_opt::<DataLoaderStats>().cloned(),
+            #[cfg(feature = "metrics")]
+            retry_stats: ctx.data_opt::<RetryStats>().cloned(),
+            #[cfg(feature = "metrics")]
+            ws_connection: ctx.data_opt::<WsConnection>().cloned(),
+            #[cfg(feature = "metrics")]
+            business_metrics: ctx.data_opt::<BusinessMetrics>().cloned(),
+        }
+    }
+}
@@ -15,7 +15,16 @@
 //!
 //! This extension includes
 //! - Tracing (via [tracing](https://github.com/tokio-rs/tracing))
-//! - High Level Metrics (via [OpenTelemetry](https://github.com/open-telemetry/opentelemetry-rust/tree/main/opentelemetry))
+//! - High Level Metrics (via [OpenTelemetry](https://github.com/open-telemetry/opentelemetry-rust/tree/main/opentelemetry)),
+//!   behind the default `metrics` feature. Without it (`default-features = false, features = ["tracing"]`)
+//!   the metric instruments, their configuration & the host-fed metric types are compiled out, and
+//!   with them the dependency on the OpenTelemetry metrics SDK. The `prometheus` feature records
+//!   the request, error & duration metrics into a `prometheus` registry instead (see
+//!   `OpenTelemetryConfig::prometheus_registry`, or `OpenTelemetry::recommended_with_prometheus`
+//!   for a ready-made setup)
+//! - Apollo Tracing data, optionally returned to the client, logged or handed to a custom sink (see [`sink`])
+//! - Field timings in the structure of Apollo Studio's `Trace` report (see [`apollo`])
+//! - In-memory span & metric collectors for asserting on telemetry in tests (behind the `testing` feature)
 //!
 //! ## Reason for combining the extensions
 //!
@@ -24,6 +33,72 @@
 //! be used. So combining all 3 made the most sense for minimising the space and
 //! computation done while processing requests.
 //!
+//! ## Exemplars
+//!
+//! It would be useful for the request duration samples to carry the trace id of the request as an
+//! exemplar, so that a latency spike could be followed straight to the trace. However the
+//! `opentelemetry` 0.13 metrics API that this crate is built on has no support for exemplars, and
+//! neither does the `prometheus` 0.12 registry behind the `prometheus` feature. So the extension
+//! deliberately has no exemplar feature or flag: one would have nothing to attach the trace id
+//! to, and there's no minimum `opentelemetry` version to document until the crate moves to one
+//! whose SDK can record them. In the meantime the trace id can be returned to the client
+//! alongside the Apollo Tracing data, via
+//! [`OpenTelemetryConfig::return_tracing_data_to_client`].
+//!
+//! ## Complexity analysis
+//!
+//! There's no metric of the time async-graphql spends analysing the complexity of each query.
+//! async-graphql 2.x computes the complexity & depth in the same pass over the document as the
+//! rest of the validation rules, and doesn't tell extensions whether any limits are configured on
+//! the schema, so the cost of the complexity analysis can't be separated from the rest of the
+//! validation. Instead the computed `complexity` & `depth` are recorded on the `validation` span,
+//! so slow validations can be correlated with complex queries, and the whole of the validation
+//! is timed by [`OpenTelemetryConfig::record_phase_duration`].
+//!
+//! ## Naming operations
+//!
+//! The `query_name` label of the field metrics is the path of the field (e.g. `getJane.details`)
+//! rather than the name of the operation, so anonymous operations are recorded under the same
+//! series as named ones and don't add any cardinality. However anonymous operations can't be told
+//! apart in traces (the root span can't be named after them, see
+//! [`OpenTelemetryConfig::root_span_from_operation`]) or by tooling which keys on the operation
+//! name, such as persisted query & response caches, so clients should name every operation. The
+//! `graphql_anonymous_operations` counter records how many operations, by `operation_kind`, are
+//! still executed without a name.
+//!
+//! ## Incremental delivery
+//!
+//! async-graphql 2.x, which this extension is built against, doesn't implement `@defer` or
+//! `@stream`, so every query & mutation produces exactly one payload. There's no per-payload
+//! metric, as it would always be `1`: `graphql_request_duration` is already measured to the final
+//! (only) payload, and `graphql_time_to_first_response` to the first, so the two would only
+//! diverge once payloads can be delivered incrementally. Documents using either directive are
+//! rejected during validation as using an unknown directive, whatever the transport, and are
+//! recorded like any other invalid document, i.e. counted once by `graphql_requests` (and
+//! `graphql_operations_unresolved`) without reaching `execute`. Subscriptions are the only operations with multiple payloads, and they're
+//! counted by `graphql_subscriptions` instead.
+//!
+//! ## Cache control
+//!
+//! The TTL a response advertises through its Cache-Control (the shortest `max_age` of the
+//! `cache_control` hints of the fields it selects) is recorded as the `cache_ttl_seconds` field of
+//! the `request` span, and by the `graphql_cache_ttl` metric by `operation_kind`. Responses
+//! without a TTL, because none of their fields have a hint, aren't recorded. The TTL is read
+//! from the response as it leaves the `execute` hook, so extensions registered before this
+//! one (which wrap it) can still change the Cache-Control afterwards without it being
+//! recorded, whereas changes made by extensions registered after it are.
+//!
+//! ## Shutdown
+//!
+//! Span exporters such as OTLP batch spans in the background, so the last batch is lost unless the
+//! provider is shut down before the process exits. Call [`shutdown_telemetry`] once the server
+//! has stopped, e.g. at the end of `main` or from a `Drop` guard:
+//!
+//! ```rust,ignore
+//! server.await?;
+//! async_graphql_telemetry_extension::shutdown_telemetry();
+//! ```
+//!
 //! ## License
 //!
 //! Anything found within here falls under the same licenses as the main
@@ -31,81 +106,664 @@
 //!
 //! MIT or Apache version 2.0
 
-use opentelemetry::metrics::{Counter, ValueRecorder};
-use opentelemetry::{global, Key, Unit};
+pub mod apollo;
+mod blocking;
+mod bridge;
+mod clock;
+mod config;
+mod context;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "prometheus")]
+mod registry;
+#[cfg(feature = "metrics")]
+mod schedule;
+mod signature;
+pub mod sink;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod types;
+mod validation;
+
+pub use clock::{Clock, SystemClock};
+pub use config::{ConfigError, ErrorEvents, FieldRepresentation, OpenTelemetryConfig, StageFlags};
+pub use context::{
+    BatchPosition, ClientInfo, Deadline, HostContext, HttpProtocol, ReceivedAt, RequestId,
+    SchemaVersion, SpanLinks, TraceParent, Transport,
+};
+#[cfg(feature = "metrics")]
+pub use context::{BusinessMetrics, DataLoaderStats, RetryStats, TenantMeter, WsConnection};
+#[cfg(feature = "metrics")]
+pub use metrics::{BusinessMetricSpec, MetricNames, Severity, Temporality};
+#[cfg(feature = "prometheus")]
+pub use registry::PrometheusExporter;
+pub use signature::query_signature;
+pub use sink::{FieldTiming, ReturnTracingFor, TracingSink, TracingSummary};
+pub use validation::classify_validation_error;
+
+#[cfg(feature = "metrics")]
+use opentelemetry::metrics::{Counter, Meter};
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::{global, KeyValue};
 
 use lazy_static::lazy_static;
 
-use futures_util::stream::BoxStream;
+use futures_util::stream::{BoxStream, StreamExt};
 use futures_util::TryFutureExt;
-use tokio::time::Instant;
-use tracing::{span, Level};
-use tracing_futures::Instrument;
+use parking_lot::Mutex;
+#[cfg(feature = "metrics")]
+use parking_lot::RwLock;
+use tracing::Dispatch;
+use tracing::{span, Level, Span};
+use tracing_futures::{Instrument, WithSubscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 use async_graphql::extensions::{
-    Extension, ExtensionContext, ExtensionFactory, NextExecute, NextParseQuery, NextRequest,
-    NextResolve, NextSubscribe, NextValidation, ResolveInfo,
+    Extension, ExtensionContext, ExtensionFactory, NextExecute, NextParseQuery, NextPrepareRequest,
+    NextRequest, NextResolve, NextSubscribe, NextValidation, ResolveInfo,
+};
+use async_graphql::parser::types::{ExecutableDocument, Selection, SelectionSet};
+use async_graphql::registry::ComplexityType;
+use async_graphql::{
+    QueryPathNode, QueryPathSegment, Request, Response, ServerError, ServerResult,
+    ValidationResult, Value, Variables,
 };
-use async_graphql::parser::types::ExecutableDocument;
-use async_graphql::{Response, ServerError, ServerResult, ValidationResult, Value, Variables};
 
-use std::sync::Arc;
+use serde::Serialize;
 
-lazy_static! {
-    static ref REQUESTS: Counter<u64> = {
-        let meter = global::meter(NAME);
-        let counter = meter
-            .u64_counter("graphql_requests")
-            .with_description("total number of HTTP requests sent to the graphQL server")
-            .init();
-        counter
-    };
-    static ref SUBSCRIPTIONS: Counter<u64> = {
-        let meter = global::meter(NAME);
-        let counter = meter
-            .u64_counter("graphql_subscriptions")
-            .with_description("total number of subscriptions sent to the graphQL server")
-            .init();
-        counter
-    };
-    static ref REQUEST_DURATION: ValueRecorder<u64> = {
-        let meter = global::meter(NAME);
-        let observer = meter
-            .u64_value_recorder("graphql_request_duration")
-            .with_description("duration of successful graphql queries in milliseconds")
-            .with_unit(Unit::new("milliseconds"))
-            .init();
-        observer
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+#[cfg(feature = "metrics")]
+use std::task::Poll;
+use std::time::Instant;
+
+use blocking::with_longest_poll;
+use bridge::active_otel_span;
+#[cfg(feature = "metrics")]
+use metrics::{
+    FieldInstrument, Instruments, Labelled, CLIENT_NAME_KEY, CLIENT_VERSION_KEY, DIRECTIVE_KEY,
+    FIELD_NAME_KEY, HTTP_PROTOCOL_KEY, IS_WRITE_KEY, OPERATION_KIND_KEY, PARENT_TYPE_KEY,
+    PARTIAL_KEY, PHASE_KEY, QUERY_KEY, QUERY_SIGNATURE_KEY, QUERY_TYPE_KEY, RETURN_TYPE_KEY,
+    SCHEMA_VERSION_KEY, SEVERITY_KEY, STATUS_KEY, TRANSPORT_KEY, VALIDATION_RULE_KEY, VARIABLE_KEY,
+};
+#[cfg(feature = "metrics")]
+use schedule::with_schedule_delay;
+#[cfg(feature = "metrics")]
+use types::self_durations;
+#[cfg(feature = "metrics")]
+use types::VariableUses;
+use types::{
+    operation_directives, operation_fragments, FieldLocations, Metrics, Operations, RequestState,
+    ResolveStat,
+};
+
+/// Creates one of the extension's spans, or in a [dry run](OpenTelemetryConfig::dry_run) logs
+/// the span it would have created & returns a disabled span instead.
+macro_rules! extension_span {
+    ($config:expr, parent: $parent:expr, $level:expr, $name:literal $($fields:tt)*) => {
+        if $config.dry_run {
+            extension_span!(@dry_run $name $($fields)*)
+        } else {
+            span!(target: TARGET, parent: $parent, $level, $name $($fields)*)
+        }
     };
-    static ref REQUEST_ERRORS: Counter<u64> = {
-        let meter = global::meter(NAME);
-        let counter = meter
-            .u64_counter("graphql_request_errors")
-            .with_description(
-                "total number of graphQL queries resulting in an error being returned",
-            )
-            .init();
-        counter
+    ($config:expr, $level:expr, $name:literal $($fields:tt)*) => {
+        if $config.dry_run {
+            extension_span!(@dry_run $name $($fields)*)
+        } else {
+            span!(target: TARGET, $level, $name $($fields)*)
+        }
     };
+    (@dry_run $name:literal $($fields:tt)*) => {{
+        tracing::debug!(
+            target: DRY_RUN_TARGET,
+            { span = $name $($fields)* },
+            "would have created span"
+        );
+        Span::none()
+    }};
+}
+
+lazy_static! {
+    static ref DEFAULT_CONFIG: OpenTelemetryConfig = OpenTelemetryConfig::default();
 }
 
 const TARGET: &str = "async_graphql::graphql";
+/// The target of the spans & metrics logged instead of being produced, in a dry run
+const DRY_RUN_TARGET: &str = "async_graphql::graphql::dry_run";
+/// The response extension set by response cache extensions on the responses they serve
+#[cfg(feature = "metrics")]
+const RESPONSE_CACHE_MARKER: &str = "cacheHit";
+#[cfg(feature = "metrics")]
 const NAME: &str = "graphql";
-const QUERY_KEY: Key = Key::from_static_str("query_name");
-const QUERY_TYPE_KEY: Key = Key::from_static_str("query_type");
-const RETURN_TYPE_KEY: Key = Key::from_static_str("return_type");
 
+/// The extension factory, to be registered with `SchemaBuilder::extension`.
+///
+/// Registering the extension more than once (e.g. both `OpenTelemetry` and
+/// [`OpenTelemetry::recommended`]) is safe, but each registration records independently.
+/// async-graphql creates a separate instance of every registered extension for each request, so
+/// their timings & tracing data are never mixed up, but every request, field & error is counted
+/// once per registration and gets a span tree per registration. Where both registrations return
+/// tracing data to the client, the one registered first wins, as it wraps the others.
 pub struct OpenTelemetry;
 pub struct OpenTelemetryExtension {
-    start: Instant,
+    // None of the hooks hold the lock across an await point, so a blocking lock is cheaper than
+    // an async one
+    state: Mutex<RequestState>,
+    max_resolve_depth: AtomicUsize,
+    /// The number of fields resolved by the operation being executed
+    resolved_fields: AtomicUsize,
+    /// Whether any field of the request has been resolved
+    resolved_any: AtomicBool,
+    /// The number of field spans started so far, giving the order the fields started resolving in
+    resolve_seq: AtomicU64,
+    /// Whether the upstream service decided not to sample the trace this request is part of
+    upstream_unsampled: AtomicBool,
+    /// The nanoseconds the `resolve` hook has spent on its own bookkeeping
+    instrumentation_nanos: AtomicU64,
+    /// Whether the bookkeeping has exceeded the instrumentation budget, so the rest of the fields
+    /// are left uninstrumented
+    degraded: AtomicBool,
+    /// The nanoseconds every hook has spent on its own bookkeeping, when the overhead is recorded
+    overhead_nanos: AtomicU64,
+    config: OnceLock<Option<OpenTelemetryConfig>>,
+    /// The config to use when there isn't one in the schema data
+    fallback_config: Option<OpenTelemetryConfig>,
+    /// The instruments the request records with, looked up once for the request rather than
+    /// every time something is recorded, and cleared with the rest of its state
+    #[cfg(feature = "metrics")]
+    instruments: RwLock<Option<Arc<Instruments>>>,
+    /// When the host received the request, if it said, shared with the stream of a subscription
+    /// as it's only known once the request has been prepared within the stream
+    #[cfg(feature = "metrics")]
+    received_at: Arc<Mutex<Option<Instant>>>,
+    /// The subscriber to record the spans with, rather than the default subscriber
+    dispatch: Option<Dispatch>,
 }
 
 impl Default for OpenTelemetryExtension {
     fn default() -> Self {
         Self {
-            start: Instant::now(),
+            state: Mutex::new(RequestState::new(&DEFAULT_CONFIG)),
+            max_resolve_depth: AtomicUsize::new(0),
+            resolved_fields: AtomicUsize::new(0),
+            resolved_any: AtomicBool::new(false),
+            resolve_seq: AtomicU64::new(0),
+            upstream_unsampled: AtomicBool::new(false),
+            instrumentation_nanos: AtomicU64::new(0),
+            overhead_nanos: AtomicU64::new(0),
+            degraded: AtomicBool::new(false),
+            config: OnceLock::new(),
+            fallback_config: None,
+            #[cfg(feature = "metrics")]
+            instruments: RwLock::new(None),
+            #[cfg(feature = "metrics")]
+            received_at: Default::default(),
+            dispatch: None,
+        }
+    }
+}
+
+impl OpenTelemetry {
+    /// The extension with a recommended set of options enabled, for services which want more than
+    /// the defaults without picking through every option:
+    ///
+    /// ```rust,ignore
+    /// let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+    ///     .extension(OpenTelemetry::recommended())
+    ///     .finish();
+    /// ```
+    ///
+    /// On top of the defaults this enables:
+    /// - [`root_span_from_operation`](OpenTelemetryConfig::root_span_from_operation), naming the
+    ///   root span after the operation
+    /// - [`record_resolve_depth`](OpenTelemetryConfig::record_resolve_depth), recording the
+    ///   `graphql_max_resolve_depth` metric
+    /// - [`record_alias_count`](OpenTelemetryConfig::record_alias_count), recording the
+    ///   `graphql_alias_count` metric
+    /// - [`ErrorEvents::Both`], reporting field errors as both `tracing` events & OpenTelemetry
+    ///   span events
+    ///
+    /// These are only a fallback, so if an [`OpenTelemetryConfig`] is registered in the schema data
+    /// then it's used as-is instead, rather than being merged with the recommended options.
+    ///
+    /// async-graphql only lets an extension factory register a single extension, so this doesn't
+    /// install any of async-graphql's own extensions. In particular its `Tracing`, `OpenTelemetry`
+    /// & `ApolloTracing` extensions produce the same spans & tracing data as this extension, so
+    /// they shouldn't be registered alongside it, while the `Analyzer` & `Logger` extensions can be
+    /// added as usual.
+    pub fn recommended() -> RecommendedOpenTelemetry {
+        RecommendedOpenTelemetry {
+            config: Self::recommended_config(),
+        }
+    }
+
+    /// The extension with the [`recommended`](Self::recommended) options, recording its core
+    /// metrics into a new Prometheus registry, which is returned alongside it for serving
+    /// `/metrics`:
+    ///
+    /// ```rust,ignore
+    /// let (extension, exporter) = OpenTelemetry::recommended_with_prometheus();
+    /// let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+    ///     .extension(extension)
+    ///     .finish();
+    /// ```
+    ///
+    /// On top of the recommended options this applies:
+    /// - [`prometheus_registry`](OpenTelemetryConfig::prometheus_registry), recording the
+    ///   `graphql_requests`, `graphql_request_errors`, `graphql_field_errors` &
+    ///   `graphql_request_duration` metrics into the exporter's registry. The rest of the metrics
+    ///   are still recorded with the global meter, as with the other factories.
+    /// - [`labels_from_env`](OpenTelemetryConfig::labels_from_env), labelling every metric with
+    ///   the `service_name`, `deployment_env` & `region` of the deployment, from the environment
+    ///   variables which are set when this is called
+    ///
+    /// As with [`recommended`](Self::recommended) these are only a fallback, so an
+    /// [`OpenTelemetryConfig`] registered in the schema data is used as-is instead, and the exporter
+    /// is left empty unless it's given the registry too.
+    #[cfg(feature = "prometheus")]
+    pub fn recommended_with_prometheus() -> (RecommendedOpenTelemetry, PrometheusExporter) {
+        let registry = prometheus::Registry::new();
+        let extension = RecommendedOpenTelemetry {
+            config: Self::recommended_config()
+                .prometheus_registry(registry.clone())
+                .labels_from_env()
+                // None of the labels are renamed, so none of them can share a name
+                .unwrap(),
+        };
+        (extension, PrometheusExporter::new(registry))
+    }
+
+    fn recommended_config() -> OpenTelemetryConfig {
+        OpenTelemetryConfig::default()
+            .root_span_from_operation(true)
+            .record_resolve_depth(true)
+            .record_alias_count(true)
+            .error_events(ErrorEvents::Both)
+    }
+}
+
+/// The extension with the recommended options enabled, see [`OpenTelemetry::recommended`].
+pub struct RecommendedOpenTelemetry {
+    config: OpenTelemetryConfig,
+}
+
+impl ExtensionFactory for RecommendedOpenTelemetry {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(OpenTelemetryExtension {
+            fallback_config: Some(self.config.clone()),
+            ..Default::default()
+        })
+    }
+}
+
+impl OpenTelemetry {
+    /// The extension, sending its spans & events to the provided dispatcher rather than the
+    /// default `tracing` subscriber, for hosts whose global subscriber is owned by someone else:
+    ///
+    /// ```rust,ignore
+    /// let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+    ///     .extension(OpenTelemetry::isolated(Dispatch::new(subscriber)))
+    ///     .finish();
+    /// ```
+    ///
+    /// Only the spans are isolated, the configuration is still taken from the schema data & the
+    /// metrics are still recorded with the configured (or global) meter, so this pairs with
+    /// `OpenTelemetryConfig::meter` to keep the extension out of the global telemetry state
+    /// entirely. `OpenTelemetry::library` does both at once.
+    ///
+    /// The dispatcher is made the default while the request is being executed, so the events
+    /// emitted by the resolvers are sent to it too. It's set as the thread's default every time
+    /// the request's future (or a subscription's stream) is polled and restored afterwards, which
+    /// costs a couple of thread-local swaps per poll, on top of the spans of a subscriber which
+    /// isn't the global one not benefiting from the global dispatcher's fast path. This is small
+    /// next to the cost of recording the spans, but it's paid by every request, so it's opt-in.
+    pub fn isolated(dispatch: impl Into<Dispatch>) -> IsolatedOpenTelemetry {
+        IsolatedOpenTelemetry {
+            dispatch: dispatch.into(),
+        }
+    }
+}
+
+/// The extension with its own dispatcher, see [`OpenTelemetry::isolated`].
+pub struct IsolatedOpenTelemetry {
+    dispatch: Dispatch,
+}
+
+impl ExtensionFactory for IsolatedOpenTelemetry {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(OpenTelemetryExtension {
+            dispatch: Some(self.dispatch.clone()),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl OpenTelemetry {
+    /// The extension for libraries which build a schema internally, and so shouldn't touch any of
+    /// the global telemetry state of the application embedding them.
+    ///
+    /// In this "library mode" every metric is recorded with the provided meter and every span &
+    /// event is sent to the provided dispatcher, rather than to the global meter provider or the
+    /// default `tracing` subscriber. This also makes the instrumentation fully testable in
+    /// isolation, as nothing leaks into (or is picked up from) other tests running alongside.
+    ///
+    /// ```rust,ignore
+    /// let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+    ///     .extension(
+    ///         OpenTelemetry::library(meter, Dispatch::new(subscriber))
+    ///             .config(OpenTelemetryConfig::default().record_resolve_depth(true)),
+    ///     )
+    ///     .finish();
+    /// ```
+    ///
+    /// Unlike the other factories, the configuration is given to the factory itself, and any
+    /// [`OpenTelemetryConfig`] in the schema data is ignored. A [`TenantMeter`] attached to a
+    /// request still takes precedence over the meter. The dispatcher is entered while the request
+    /// is being executed, so the events emitted by the resolvers are sent to it too.
+    ///
+    /// See `examples/library_mode.rs` for a complete example.
+    pub fn library(meter: Meter, dispatch: impl Into<Dispatch>) -> LibraryOpenTelemetry {
+        let meter = Arc::new(meter);
+        LibraryOpenTelemetry {
+            config: OpenTelemetryConfig::default().shared_meter(meter.clone()),
+            meter,
+            dispatch: dispatch.into(),
+        }
+    }
+}
+
+/// The self-contained extension, see [`OpenTelemetry::library`].
+#[cfg(feature = "metrics")]
+pub struct LibraryOpenTelemetry {
+    config: OpenTelemetryConfig,
+    meter: Arc<Meter>,
+    dispatch: Dispatch,
+}
+
+#[cfg(feature = "metrics")]
+impl LibraryOpenTelemetry {
+    /// Configure the extension, replacing the defaults.
+    ///
+    /// The metrics are still recorded with the meter given to [`OpenTelemetry::library`], even
+    /// if the config has another meter set.
+    pub fn config(mut self, config: OpenTelemetryConfig) -> Self {
+        self.config = config.shared_meter(self.meter.clone());
+        self
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl ExtensionFactory for LibraryOpenTelemetry {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(OpenTelemetryExtension {
+            config: OnceLock::from(Some(self.config.clone())),
+            dispatch: Some(self.dispatch.clone()),
+            ..Default::default()
+        })
+    }
+}
+
+/// Flush & shut down the globally installed telemetry providers.
+///
+/// This shuts down the global tracer provider, which causes its span processors to export any
+/// spans they're still buffering, then replaces it with a no-op provider, so it should only be
+/// called once the server has stopped handling requests.
+///
+/// The `opentelemetry` 0.13 global meter provider can't be shut down, so a push metrics
+/// controller needs to be kept alive until this point and then dropped to export its final
+/// collection.
+pub fn shutdown_telemetry() {
+    global::shutdown_tracer_provider();
+}
+
+/// Adds the time from its creation until it's dropped to the extension's overhead, so every
+/// return from a hook is counted
+struct OverheadTimer<'a> {
+    start: Option<Instant>,
+    config: &'a OpenTelemetryConfig,
+    total: &'a AtomicU64,
+}
+
+impl Drop for OverheadTimer<'_> {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            let spent = self.config.elapsed(start).as_nanos() as u64;
+            self.total.fetch_add(spent, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Counts the subscription as empty once its stream is dropped without having yielded a
+/// response, whether it ended or the client went away before the first one
+#[cfg(feature = "metrics")]
+struct EmptySubscriptionGuard {
+    empty_subscriptions: Labelled<Counter<u64>>,
+    yielded: bool,
+}
+
+#[cfg(feature = "metrics")]
+impl Drop for EmptySubscriptionGuard {
+    fn drop(&mut self) {
+        if !self.yielded {
+            self.empty_subscriptions.add(1, &[]);
+        }
+    }
+}
+
+/// The size of the value once serialized as JSON, computed without buffering the output
+fn serialized_size(value: &impl Serialize) -> usize {
+    struct ByteCounter(usize);
+
+    impl io::Write for ByteCounter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = ByteCounter(0);
+    serde_json::to_writer(&mut counter, value)
+        .map(|_| counter.0)
+        .unwrap_or_default()
+}
+
+/// The complexity cost the schema assigns to the field, if it's a constant
+fn complexity_cost(
+    ctx: &ExtensionContext<'_>,
+    parent_type: &str,
+    field_name: &str,
+) -> Option<usize> {
+    let field = ctx
+        .schema_env
+        .registry
+        .types
+        .get(parent_type)?
+        .field_by_name(field_name)?;
+    match field.compute_complexity.as_ref()? {
+        ComplexityType::Const(cost) => Some(*cost),
+        ComplexityType::Fn(_) => None,
+    }
+}
+
+/// Whether the GraphQL type is nullable, i.e. isn't wrapped in a non-null `!`
+fn is_nullable(return_type: &str) -> bool {
+    !return_type.ends_with('!')
+}
+
+/// Creates a `field` span for each of the buffered field timings, nesting each span under the
+/// span of its parent field.
+///
+/// As the spans are created after the fields have resolved, their own timestamps reflect when
+/// they were emitted, so the real timings are attached as the `start_offset` & `duration` fields
+/// (in nanoseconds).
+fn emit_field_spans(
+    config: &OpenTelemetryConfig,
+    parent: &Span,
+    fields: &BTreeMap<String, ResolveStat>,
+    request_id: Option<&str>,
+) {
+    // The map is ordered by path, so a field's parent will always have been emitted before it
+    let mut spans: BTreeMap<&str, Span> = BTreeMap::new();
+    for (path, stat) in fields {
+        let parent = stat
+            .parent_path()
+            .and_then(|parent_path| spans.get(parent_path.as_str()))
+            .unwrap_or(parent);
+        let span = extension_span!(
+            config,
+            parent: parent,
+            Level::INFO,
+            "field",
+            %path,
+            parent_type = %stat.parent_type,
+            return_type = %stat.return_type,
+            start_offset = stat.start_offset,
+            duration = stat.duration_nanos(),
+            nullable = tracing::field::Empty,
+            location = stat.location.map(tracing::field::display),
+            complexity_cost = stat.complexity_cost,
+            request_id,
+        );
+        if config.record_field_nullability {
+            span.record("nullable", is_nullable(&stat.return_type));
+        }
+        spans.insert(path, span);
+    }
+}
+
+/// Emits an event for each of the buffered fields within the `execute` span, as with
+/// [`emit_field_spans`] but for [`FieldRepresentation::Events`].
+fn emit_field_events(parent: &Span, fields: &BTreeMap<String, ResolveStat>) {
+    for (path, stat) in fields {
+        tracing::info!(
+            target: TARGET,
+            parent: parent,
+            %path,
+            parent_type = %stat.parent_type,
+            return_type = %stat.return_type,
+            start_offset = stat.start_offset,
+            duration = stat.duration_nanos(),
+            "field",
+        );
+    }
+}
+
+/// The number of fields listed by the event of a slow request
+const SLOWEST_FIELDS: usize = 5;
+
+/// The slowest of the fields, slowest first, formatted for the `slowest_fields` field of the
+/// "slow request" event
+fn slowest_fields(resolves: &[ResolveStat]) -> String {
+    let mut durations = resolves
+        .iter()
+        .map(|stat| (stat.duration_nanos().unwrap_or_default(), stat))
+        .collect::<Vec<_>>();
+    durations.sort_by_key(|(nanos, _)| std::cmp::Reverse(*nanos));
+    durations
+        .iter()
+        .take(SLOWEST_FIELDS)
+        .map(|(nanos, stat)| format!("{}={}ms", stat.path.join("."), nanos / 1_000_000))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Whether the OpenTelemetry context the request is executed within belongs to a trace which was
+/// propagated without the sampled flag.
+///
+/// Propagators extract the incoming trace as a remote span context, but it may also have been
+/// used as the parent of an active span by the time the request is executed.
+fn upstream_unsampled(context: &opentelemetry::Context) -> bool {
+    let span_context = if context.has_active_span() {
+        context.span().span_context()
+    } else {
+        match context.remote_span_context() {
+            Some(span_context) => span_context,
+            None => return false,
+        }
+    };
+    span_context.is_valid() && !span_context.is_sampled()
+}
+
+/// The `code` of the error's extensions, if it has one
+fn error_code(err: &ServerError) -> Option<String> {
+    err.extensions
+        .as_ref()
+        .and_then(|extensions| serde_json::to_value(extensions).ok())
+        .and_then(|extensions| match extensions.get("code")? {
+            serde_json::Value::String(code) => Some(code.clone()),
+            code => Some(code.to_string()),
+        })
+}
+
+/// Records the error as an event on the currently active OpenTelemetry span, if there is one
+fn add_error_span_event(path: &str, err: &ServerError) {
+    let context = opentelemetry::Context::current();
+    let span = match active_otel_span(&context) {
+        Some(span) => span,
+        None => return,
+    };
+    let mut attributes = vec![
+        KeyValue::new("message", err.message.clone()),
+        KeyValue::new("path", path.to_string()),
+    ];
+    if let Some(code) = error_code(err) {
+        attributes.push(KeyValue::new("code", code));
+    }
+    span.add_event("graphql.error".to_string(), attributes);
+}
+
+/// The number of fields (ignoring list indices) between the root of the query and this node
+fn resolve_depth(path_node: &QueryPathNode<'_>) -> usize {
+    std::iter::once(path_node)
+        .chain(path_node.parents())
+        .filter(|node| matches!(node.segment, QueryPathSegment::Name(_)))
+        .count()
+}
+
+/// The number of aliased fields in the document, including those within fragment definitions
+///
+/// A fragment is only counted once, no matter how many times it's spread.
+fn selection_counts(document: &ExecutableDocument) -> SelectionCounts {
+    fn count(selection_set: &SelectionSet, counts: &mut SelectionCounts) {
+        for selection in &selection_set.items {
+            match &selection.node {
+                Selection::Field(field) => {
+                    counts.fields += 1;
+                    counts.aliases += usize::from(field.node.alias.is_some());
+                    count(&field.node.selection_set.node, counts);
+                }
+                Selection::InlineFragment(fragment) => {
+                    count(&fragment.node.selection_set.node, counts)
+                }
+                Selection::FragmentSpread(_) => {}
+            }
         }
     }
+
+    let mut counts = SelectionCounts::default();
+    for (_, operation) in document.operations.iter() {
+        count(&operation.node.selection_set.node, &mut counts);
+    }
+    for fragment in document.fragments.values() {
+        count(&fragment.node.selection_set.node, &mut counts);
+    }
+    counts
+}
+
+/// The number of fields selected in a document, and how many of them are aliased. The selections
+/// of a fragment are counted once, no matter how many times it's spread.
+#[derive(Default)]
+struct SelectionCounts {
+    fields: usize,
+    aliases: usize,
 }
 
 impl ExtensionFactory for OpenTelemetry {
@@ -114,13 +772,424 @@ impl ExtensionFactory for OpenTelemetry {
     }
 }
 
+impl OpenTelemetryExtension {
+    /// Counts the field error, returning whether its message has been logged fewer times than the
+    /// configured limit.
+    fn within_error_log_limit(
+        &self,
+        state: &mut RequestState,
+        config: &OpenTelemetryConfig,
+        message: &str,
+    ) -> bool {
+        let limit = match config.max_error_logs_per_request {
+            Some(limit) => limit,
+            None => return true,
+        };
+        let seen = match state.error_logs.get_mut(message) {
+            Some(seen) => seen,
+            None => state.error_logs.entry(message.to_string()).or_default(),
+        };
+        *seen += 1;
+        *seen <= limit
+    }
+
+    /// The configuration registered in the schema data, or the fallback/default if there isn't
+    /// one.
+    ///
+    /// This is looked up by the first hook of the request, and then reused by the rest of them.
+    /// Cloning the config doesn't allocate, as everything it keeps on the heap is behind an `Arc`,
+    /// and the default is never cloned at all.
+    fn config(&self, ctx: &ExtensionContext<'_>) -> &OpenTelemetryConfig {
+        self.config
+            .get_or_init(|| {
+                ctx.data_opt::<OpenTelemetryConfig>()
+                    .or(self.fallback_config.as_ref())
+                    .cloned()
+            })
+            .as_ref()
+            .unwrap_or(&DEFAULT_CONFIG)
+    }
+
+    /// The metric instruments to record this request with.
+    ///
+    /// These are the instruments of the request's [`TenantMeter`] if it has one, otherwise those
+    /// of the config, which are those of the meter provided in the config or of the global meter.
+    /// The global meter's are only kept once a provider is installed, so they're never bound to
+    /// the no-op provider which is installed before then, e.g. when the schema was built. Which
+    /// of them the request records with is looked up the first time it records something.
+    #[cfg(feature = "metrics")]
+    fn instruments(&self, config: &OpenTelemetryConfig) -> Arc<Instruments> {
+        if let Some(instruments) = &*self.instruments.read() {
+            return instruments.clone();
+        }
+        self.instruments
+            .write()
+            .get_or_insert_with(|| config.instruments().clone())
+            .clone()
+    }
+
+    /// Clears everything recorded about the previous request.
+    ///
+    /// async-graphql creates an instance of the extension for every request, but a custom executor
+    /// may run several through the same one (e.g. to retry a request), which mustn't see anything
+    /// left behind by the earlier attempts.
+    fn reset_request_state(&self, config: &OpenTelemetryConfig) {
+        *self.state.lock() = RequestState::new(config);
+        self.max_resolve_depth.store(0, Ordering::Relaxed);
+        self.upstream_unsampled.store(false, Ordering::Relaxed);
+        self.resolved_any.store(false, Ordering::Relaxed);
+        self.instrumentation_nanos.store(0, Ordering::Relaxed);
+        self.overhead_nanos.store(0, Ordering::Relaxed);
+        self.degraded.store(false, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        {
+            *self.instruments.write() = None;
+        }
+        self.reset_execution_state();
+    }
+
+    /// Clears the field timings & counts of the previous execution, keeping what was recorded
+    /// while the request was prepared, parsed & validated.
+    fn reset_execution_state(&self) {
+        {
+            let mut state = self.state.lock();
+            state.metrics.resolves.clear();
+            state.fields.clear();
+            state.error_logs.clear();
+            #[cfg(feature = "metrics")]
+            state.types_touched.clear();
+        }
+        self.max_resolve_depth.store(0, Ordering::Relaxed);
+        self.resolved_fields.store(0, Ordering::Relaxed);
+        self.resolve_seq.store(0, Ordering::Relaxed);
+    }
+
+    /// Records one of the measurements made as each field is resolved, or holds it back until the
+    /// operation has executed when they're batched. It's passed the request's state so a field can
+    /// record all of its measurements under the lock it's already holding.
+    #[cfg(feature = "metrics")]
+    fn record_field(
+        &self,
+        state: &mut RequestState,
+        config: &OpenTelemetryConfig,
+        instrument: FieldInstrument,
+        value: u64,
+        mut labels: Vec<KeyValue>,
+    ) {
+        if let (
+            FieldInstrument::RequestDuration
+            | FieldInstrument::RequestErrors
+            | FieldInstrument::FieldErrors,
+            Some(track),
+        ) = (instrument, &config.deployment_track)
+        {
+            labels.push(KeyValue::clone(track));
+        }
+        if let Some(batch) = &mut state.field_measurements {
+            return batch.push(instrument, value, labels);
+        }
+        self.instruments(config)
+            .record_field(instrument, value, &labels);
+    }
+
+    /// Adds the time since `start` to the bookkeeping of the request, degrading its
+    /// instrumentation the first time the total exceeds the budget
+    fn spend_instrumentation_budget(&self, config: &OpenTelemetryConfig, start: Instant) {
+        let spent = config.elapsed(start).as_nanos() as u64;
+        self.overhead_nanos.fetch_add(spent, Ordering::Relaxed);
+        let budget = match config.instrumentation_budget {
+            Some(budget) => budget,
+            None => return,
+        };
+        let total = self
+            .instrumentation_nanos
+            .fetch_add(spent, Ordering::Relaxed)
+            + spent;
+        if total as u128 > budget.as_nanos() && !self.degraded.swap(true, Ordering::Relaxed) {
+            tracing::debug!(
+                target: TARGET,
+                spent = total,
+                "the instrumentation budget was exceeded, the rest of the fields won't be \
+                 instrumented",
+            );
+            #[cfg(feature = "metrics")]
+            self.instruments(config)
+                .instrumentation_degraded
+                .add(1, &[]);
+        }
+    }
+
+    /// Runs the phase, recording how long it took if that's enabled, and how long it waited to be
+    /// scheduled since `scheduled`, if it was created with the instant to measure from
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    async fn run_phase<F: std::future::Future>(
+        &self,
+        config: &OpenTelemetryConfig,
+        phase: &'static str,
+        scheduled: Option<Instant>,
+        fut: F,
+    ) -> F::Output {
+        #[cfg(feature = "metrics")]
+        let start = config.record_phase_duration.then(|| config.now());
+        let output = match scheduled {
+            #[cfg(feature = "metrics")]
+            Some(scheduled) => {
+                let (output, delay) =
+                    with_schedule_delay(config.shared_clock(), scheduled, fut).await;
+                self.instruments(config)
+                    .phase_schedule_delay
+                    .record(delay.as_micros() as u64, &[PHASE_KEY.string(phase)]);
+                output
+            }
+            _ => fut.await,
+        };
+        #[cfg(feature = "metrics")]
+        if let Some(start) = start {
+            self.instruments(config).phase_duration.record(
+                config.elapsed(start).as_secs_f64(),
+                &[PHASE_KEY.string(phase)],
+            );
+        }
+        output
+    }
+
+    /// Times the extension's own code from now until the timer is dropped, if the overhead is
+    /// recorded
+    fn overhead_timer<'a>(&'a self, config: &'a OpenTelemetryConfig) -> OverheadTimer<'a> {
+        OverheadTimer {
+            start: config
+                .records_instrumentation_overhead()
+                .then(|| config.now()),
+            config,
+            total: &self.overhead_nanos,
+        }
+    }
+
+    /// Runs the closure with the extension's dispatcher as the default, if it has one
+    fn in_dispatch<T>(&self, f: impl FnOnce() -> T) -> T {
+        match &self.dispatch {
+            Some(dispatch) => tracing::dispatcher::with_default(dispatch, f),
+            None => f(),
+        }
+    }
+
+    /// The body of the `request` hook, run within the extension's dispatcher
+    async fn traced_request(&self, ctx: &ExtensionContext<'_>, next: NextRequest<'_>) -> Response {
+        let config = self.config(ctx);
+        self.reset_request_state(config);
+        let overhead = self.overhead_timer(config);
+        if !config.ignore_upstream_sampling
+            && upstream_unsampled(&opentelemetry::Context::current())
+        {
+            self.upstream_unsampled.store(true, Ordering::Relaxed);
+        }
+        let span = if config.stages.contains(StageFlags::REQUEST) {
+            extension_span!(
+                config,
+                Level::INFO,
+                "request",
+                otel.name = tracing::field::Empty,
+                alias_count = tracing::field::Empty,
+                query_signature = tracing::field::Empty,
+                operation_directives = tracing::field::Empty,
+                fragment_count = tracing::field::Empty,
+                fragments = tracing::field::Empty,
+                root_selection_width = tracing::field::Empty,
+                is_write = tracing::field::Empty,
+                deadline_exceeded = tracing::field::Empty,
+                cache_ttl_seconds = tracing::field::Empty,
+                transport = tracing::field::Empty,
+                http.protocol = tracing::field::Empty,
+                batch_size = tracing::field::Empty,
+                batch_index = tracing::field::Empty,
+                request_id = tracing::field::Empty,
+            )
+        } else {
+            Span::none()
+        };
+        self.state.lock().root_span = span.clone();
+        drop(overhead);
+        let resp = next.run(ctx).instrument(span).await;
+        #[cfg(feature = "metrics")]
+        {
+            let overhead = self.overhead_timer(config);
+            self.record_request_metrics(config);
+            drop(overhead);
+            if config.record_instrumentation_overhead {
+                let labels = self
+                    .state
+                    .lock()
+                    .operation_kind
+                    .map(|kind| vec![OPERATION_KIND_KEY.string(kind.to_string())])
+                    .unwrap_or_default();
+                self.instruments(config)
+                    .instrumentation_overhead
+                    .record(self.overhead_nanos.load(Ordering::Relaxed) / 1_000, &labels);
+            }
+        }
+        resp
+    }
+
+    /// Records the metrics of the request once it's complete
+    #[cfg(feature = "metrics")]
+    fn record_request_metrics(&self, config: &OpenTelemetryConfig) {
+        // The request data isn't available to the request hook, so the client is picked up while
+        // the request is being prepared, meaning the request can only be counted once it's
+        // complete
+        let mut labels = Vec::new();
+        let sampled = {
+            let state = self.state.lock();
+            // async-graphql doesn't support incremental delivery, so the first response is the
+            // whole response
+            let time_to_first_response = state
+                .host
+                .received_at
+                .map(|received_at| config.elapsed(received_at))
+                .unwrap_or_else(|| config.elapsed(state.start));
+            let kind_labels = state
+                .operation_kind
+                .map(|kind| OPERATION_KIND_KEY.string(kind.to_string()))
+                .into_iter()
+                .collect::<Vec<_>>();
+            self.instruments(config)
+                .time_to_first_response
+                .record(time_to_first_response.as_millis() as u64, &kind_labels);
+
+            if let Some(client) = &state.host.client {
+                let (name, version) = config.client_labels(client);
+                labels.push(CLIENT_NAME_KEY.string(name.to_string()));
+                labels.push(CLIENT_VERSION_KEY.string(version.to_string()));
+            }
+            if let Some(is_write) = state.is_write() {
+                labels.push(IS_WRITE_KEY.bool(is_write));
+            }
+            if let Some(version) = &state.schema_version {
+                labels.push(SCHEMA_VERSION_KEY.string(version.clone()));
+            }
+            let transport = state.host.transport.map_or("unknown", Transport::as_str);
+            labels.push(TRANSPORT_KEY.string(transport));
+            if config.http_protocol_label {
+                if let Some(version) = state
+                    .host
+                    .http_protocol
+                    .as_ref()
+                    .and_then(HttpProtocol::version)
+                {
+                    labels.push(HTTP_PROTOCOL_KEY.string(version));
+                }
+            }
+            if let Some(signature) = &state.query_signature {
+                labels.push(QUERY_SIGNATURE_KEY.string(signature.clone()));
+            }
+            state.sampled
+        };
+        // e.g. the request failed to parse or validate, so never reached the executor
+        if !self.resolved_any.load(Ordering::Relaxed) {
+            self.instruments(config)
+                .operations_unresolved
+                .add(1, &labels);
+        }
+        if sampled {
+            self.instruments(config).traces_sampled.add(1, &labels);
+        }
+        // Only the metrics a rollout is judged by are split by the deployment's track
+        if let Some(track) = &config.deployment_track {
+            labels.push(KeyValue::clone(track));
+        }
+        self.instruments(config).requests.add(1, &labels);
+    }
+}
+
 #[async_trait::async_trait]
 impl Extension for OpenTelemetryExtension {
     async fn request(&self, ctx: &ExtensionContext<'_>, next: NextRequest<'_>) -> Response {
-        REQUESTS.add(1, &[]);
-        next.run(ctx)
-            .instrument(span!(target: TARGET, Level::INFO, "request"))
-            .await
+        // Every other hook is run within the request, so this enters the dispatcher for them too
+        match &self.dispatch {
+            Some(dispatch) => {
+                self.traced_request(ctx, next)
+                    .with_subscriber(dispatch.clone())
+                    .await
+            }
+            None => self.traced_request(ctx, next).await,
+        }
+    }
+
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> ServerResult<Request> {
+        let config = self.config(ctx);
+        let overhead = self.overhead_timer(config);
+        let host = HostContext::from_context(ctx);
+        #[cfg(feature = "metrics")]
+        if let Some(TenantMeter(meter)) = ctx.data_opt::<TenantMeter>() {
+            // Nothing but the subscription counter is recorded before the request is prepared,
+            // so the rest of the request is recorded with the tenant's instruments
+            *self.instruments.write() = Some(Arc::new(Instruments::new(meter, config)));
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(connection) = &host.ws_connection {
+            connection.record_operation(&self.instruments(config));
+        }
+        {
+            let mut state = self.state.lock();
+            let transport = host.transport.map_or("unknown", Transport::as_str);
+            state.root_span.record("transport", transport);
+            if let Some(version) = host.http_protocol.as_ref().and_then(HttpProtocol::version) {
+                state.root_span.record("http.protocol", version);
+            }
+            if let Some(batch) = host.batch {
+                state.root_span.record("batch_size", batch.size);
+                state.root_span.record("batch_index", batch.index);
+                #[cfg(feature = "metrics")]
+                if batch.index == 0 {
+                    let instruments = self.instruments(config);
+                    instruments.batch_size.record(batch.size as u64, &[]);
+                    if config
+                        .batch_size_limit
+                        .is_some_and(|limit| batch.size > limit)
+                    {
+                        instruments.oversized_batches.add(1, &[]);
+                    }
+                }
+            }
+            state.request_id = host
+                .request_id
+                .as_ref()
+                .map(|RequestId(id)| Arc::from(id.as_str()));
+            if let Some(request_id) = &state.request_id {
+                state.root_span.record("request_id", &**request_id);
+            }
+            for link in &host.span_links {
+                state.root_span.follows_from(link);
+            }
+            if let Some(parent) = &host.trace_parent {
+                state.root_span.set_parent(parent.clone());
+                if !config.ignore_upstream_sampling {
+                    self.upstream_unsampled
+                        .store(upstream_unsampled(parent), Ordering::Relaxed);
+                }
+            }
+            #[cfg(feature = "metrics")]
+            {
+                state.schema_version = host
+                    .schema_version
+                    .as_ref()
+                    .filter(|version| version.is_valid())
+                    .or(config.schema_version.as_deref())
+                    .map(|version| version.0.clone());
+            }
+            state.operation_name = request.operation_name.clone();
+            #[cfg(feature = "metrics")]
+            {
+                *self.received_at.lock() = host.received_at;
+            }
+            state.host = host;
+        }
+        drop(overhead);
+        next.run(ctx, request).await
     }
 
     fn subscribe<'s>(
@@ -129,11 +1198,58 @@ impl Extension for OpenTelemetryExtension {
         stream: BoxStream<'s, Response>,
         next: NextSubscribe<'_>,
     ) -> BoxStream<'s, Response> {
-        SUBSCRIPTIONS.add(1, &[]);
-        Box::pin(
-            next.run(ctx, stream)
-                .instrument(span!(target: TARGET, Level::INFO, "subscribe")),
-        )
+        let config = self.config(ctx);
+        // Subscriptions aren't run through the `request` hook, so this is where they start
+        self.reset_request_state(config);
+        let span = if config.stages.contains(StageFlags::REQUEST) {
+            self.in_dispatch(|| extension_span!(config, Level::INFO, "subscribe"))
+        } else {
+            Span::none()
+        };
+        // The request data is only available once the request is prepared, within the stream,
+        // so the subscription's spans hang off the subscribe span like a request's do off the
+        // request span
+        self.state.lock().root_span = span.clone();
+        let stream = next.run(ctx, stream).instrument(span);
+        #[cfg(feature = "metrics")]
+        let stream = {
+            let instruments = self.instruments(config);
+            instruments.subscriptions.add(1, &[]);
+            let time_to_first_response = instruments.time_to_first_response.clone();
+            let mut empty = EmptySubscriptionGuard {
+                empty_subscriptions: instruments.empty_subscriptions.clone(),
+                yielded: false,
+            };
+            let start = self.state.lock().start;
+            let received_at = self.received_at.clone();
+            let clock = config.shared_clock();
+            let mut stream = Box::pin(stream);
+            futures_util::stream::poll_fn(move |cx| {
+                let item = stream.poll_next_unpin(cx);
+                if let Poll::Ready(Some(_)) = &item {
+                    if !std::mem::replace(&mut empty.yielded, true) {
+                        // The request is prepared by the time the first response is yielded
+                        let start = received_at.lock().unwrap_or(start);
+                        time_to_first_response.record(
+                            clock.now().saturating_duration_since(start).as_millis() as u64,
+                            &[OPERATION_KIND_KEY.string("subscription")],
+                        );
+                    }
+                }
+                item
+            })
+        };
+        match self.dispatch.clone() {
+            // The stream is polled outside of the request, so the dispatcher has to be entered
+            // for each poll
+            Some(dispatch) => {
+                let mut stream = Box::pin(stream);
+                Box::pin(futures_util::stream::poll_fn(move |cx| {
+                    tracing::dispatcher::with_default(&dispatch, || stream.poll_next_unpin(cx))
+                }))
+            }
+            None => Box::pin(stream),
+        }
     }
 
     async fn parse_query(
@@ -143,9 +1259,152 @@ impl Extension for OpenTelemetryExtension {
         variables: &Variables,
         next: NextParseQuery<'_>,
     ) -> ServerResult<ExecutableDocument> {
-        let span = span!(target: TARGET, Level::INFO, "parse", source = query);
-        tracing::trace!(parent: &span, source = query, "parsing received query");
-        next.run(ctx, query, variables).instrument(span).await
+        let config = self.config(ctx);
+        let overhead = self.overhead_timer(config);
+        let request_id = self.state.lock().request_id.clone();
+        let span = if config.stages.contains(StageFlags::PARSE) {
+            extension_span!(
+                config,
+                Level::INFO,
+                "parse",
+                source = tracing::field::Empty,
+                variables_count = variables.len(),
+                variables_bytes = tracing::field::Empty,
+                request_id = request_id.as_deref(),
+            )
+        } else {
+            Span::none()
+        };
+        // The variables are only serialized if their size is recorded
+        let variables_bytes = (!span.is_disabled() || config.records_variables_bytes())
+            .then(|| serialized_size(variables));
+        if let Some(variables_bytes) = variables_bytes {
+            span.record("variables_bytes", variables_bytes);
+        }
+        let scheduled = config.phase_schedule_start();
+        if !config.omit_query_source {
+            span.record("source", query);
+            tracing::trace!(parent: &span, source = query, "parsing received query");
+        }
+        #[cfg(feature = "metrics")]
+        let parse_start = config.now();
+        drop(overhead);
+        let document = self
+            .run_phase(
+                config,
+                "parse",
+                scheduled,
+                next.run(ctx, query, variables).instrument(span),
+            )
+            .await?;
+        let _overhead = self.overhead_timer(config);
+        #[cfg(feature = "metrics")]
+        if let Some(threshold) = config.parse_cache_hit_threshold {
+            // There's no way to tell where the document came from, so anything that comes back
+            // faster than a parse could have produced it is assumed to have been cached
+            if config.elapsed(parse_start) < threshold {
+                self.instruments(config).parse_cache_hits.add(1, &[]);
+            } else {
+                self.instruments(config).parse_cache_misses.add(1, &[]);
+            }
+        }
+        #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+        let aliases = {
+            let mut state = self.state.lock();
+            // The document is only walked for what's recorded from it: the fields of the request
+            // span when it's enabled, and whatever the config turns on
+            let described = !state.root_span.is_disabled();
+            state.operations = Operations::from_document(
+                &document,
+                described || config.record_root_selection_width,
+            );
+            if !state.operations.selects(state.operation_name.as_deref()) {
+                #[cfg(feature = "metrics")]
+                self.instruments(config).operation_name_mismatch.add(1, &[]);
+                tracing::warn!(
+                    target: TARGET,
+                    parent: &state.root_span,
+                    operation_name = state.operation_name.as_deref(),
+                    operations = document.operations.iter().count(),
+                    "operation name doesn't select any of the document's operations",
+                );
+            }
+            if config.record_field_location {
+                state.field_locations = FieldLocations::from_document(&document);
+            }
+            if config.record_query_signature {
+                state.query_signature = query_signature(&document, state.operation_name.as_deref());
+                if let Some(signature) = &state.query_signature {
+                    state
+                        .root_span
+                        .record("query_signature", signature.as_str());
+                }
+            }
+            #[cfg(feature = "metrics")]
+            if config.record_variable_validation {
+                state.variables_count = variables.len();
+                state.variable_uses = VariableUses::from_document(&document);
+            }
+            let counts = (described || config.record_alias_count || config.keeps_resolve_stats())
+                .then(|| selection_counts(&document));
+            if let Some(counts) = &counts {
+                state.root_span.record("alias_count", counts.aliases);
+            }
+            if described {
+                let fragments = operation_fragments(&document, state.operation_name.as_deref());
+                state.root_span.record("fragment_count", fragments.len());
+                if let Some(names) = config.fragment_names(&fragments) {
+                    state.root_span.record("fragments", names.as_str());
+                }
+            }
+            let directives = if described || config.counts_operation_directives() {
+                operation_directives(&document, state.operation_name.as_deref())
+            } else {
+                Vec::new()
+            };
+            if !directives.is_empty() {
+                state
+                    .root_span
+                    .record("operation_directives", directives.join(",").as_str());
+            }
+            #[cfg(feature = "metrics")]
+            for directive in &directives {
+                if let Some(directive) = config.operation_directive_label(directive) {
+                    self.instruments(config)
+                        .operation_directive_usage
+                        .add(1, &[DIRECTIVE_KEY.string(directive.to_string())]);
+                }
+            }
+            // Every selected field is resolved at least once (unless it's skipped), so reserving
+            // them up front saves growing the timings one reallocation at a time in a wide query.
+            // The buffered field spans are kept in path order, so they can't be preallocated.
+            if let (true, Some(counts)) = (config.keeps_resolve_stats(), &counts) {
+                state.metrics.resolves.reserve(counts.fields);
+            }
+            counts.map(|counts| counts.aliases)
+        };
+        #[cfg(feature = "metrics")]
+        if config.record_variables_bytes {
+            // The operation being executed is only known once the document has been parsed
+            let labels = {
+                let state = self.state.lock();
+                state
+                    .operations
+                    .kind(state.operation_name.as_deref())
+                    .map(|kind| vec![OPERATION_KIND_KEY.string(kind.to_string())])
+                    .unwrap_or_default()
+            };
+            self.instruments(config)
+                .variables_bytes
+                .record(variables_bytes.unwrap_or_default() as u64, &labels);
+        }
+        #[cfg(feature = "metrics")]
+        if let (true, Some(aliases)) = (config.record_alias_count, aliases) {
+            self.instruments(config)
+                .alias_count
+                .record(aliases as u64, &[]);
+        }
+        Ok(document)
     }
 
     async fn validation(
@@ -153,8 +1412,72 @@ impl Extension for OpenTelemetryExtension {
         ctx: &ExtensionContext<'_>,
         next: NextValidation<'_>,
     ) -> Result<ValidationResult, Vec<ServerError>> {
-        let span = span!(target: TARGET, Level::INFO, "validation");
-        next.run(ctx).instrument(span).await
+        let config = self.config(ctx);
+        let overhead = self.overhead_timer(config);
+        let request_id = self.state.lock().request_id.clone();
+        let span = if config.stages.contains(StageFlags::VALIDATION) {
+            extension_span!(
+                config,
+                Level::INFO,
+                "validation",
+                complexity = tracing::field::Empty,
+                depth = tracing::field::Empty,
+                validation_rule = tracing::field::Empty,
+                request_id = request_id.as_deref(),
+            )
+        } else {
+            Span::none()
+        };
+        let scheduled = config.phase_schedule_start();
+        drop(overhead);
+        let result = self
+            .run_phase(
+                config,
+                "validation",
+                scheduled,
+                next.run(ctx).instrument(span.clone()),
+            )
+            .await;
+        let _overhead = self.overhead_timer(config);
+        if let Ok(result) = &result {
+            span.record("complexity", result.complexity);
+            span.record("depth", result.depth);
+            if config.record_complexity_estimate_error {
+                self.state.lock().estimated_complexity = Some(result.complexity);
+            }
+        }
+        if let (true, Err(errors)) = (config.record_validation_rule, &result) {
+            if let Some(err) = errors.first() {
+                let rule = config.validation_rule(err);
+                span.record("validation_rule", rule);
+                #[cfg(feature = "metrics")]
+                self.instruments(config)
+                    .validation_errors
+                    .add(1, &[VALIDATION_RULE_KEY.string(rule)]);
+            }
+        }
+        #[cfg(feature = "metrics")]
+        if config.record_variable_validation {
+            let state = self.state.lock();
+            let instruments = self.instruments(config);
+            instruments
+                .variables_validated
+                .record(state.variables_count as u64, &[]);
+            if let Err(errors) = &result {
+                for variables in errors
+                    .iter()
+                    .filter_map(|err| state.variable_uses.coercion_error(err))
+                {
+                    match variables {
+                        [variable] => instruments
+                            .variable_coercion_errors
+                            .add(1, &[VARIABLE_KEY.string(variable.clone())]),
+                        _ => instruments.variable_coercion_errors.add(1, &[]),
+                    }
+                }
+            }
+        }
+        result
     }
 
     async fn execute(
@@ -163,46 +1486,626 @@ impl Extension for OpenTelemetryExtension {
         operation_name: Option<&str>,
         next: NextExecute<'_>,
     ) -> Response {
-        let span = span!(target: TARGET, Level::INFO, "execute");
-        next.run(ctx, operation_name).instrument(span).await
-    }
-
-    async fn resolve(
-        &self,
-        ctx: &ExtensionContext<'_>,
-        info: ResolveInfo<'_>,
-        next: NextResolve<'_>,
-    ) -> ServerResult<Option<Value>> {
-        let path = info.path_node.to_string();
-        let parent_type = info.parent_type.to_string();
-        let return_type = info.return_type.to_string();
-        let span = span!(
-            target: TARGET,
-            Level::INFO,
-            "field",
-            %path,
-            %parent_type,
-            %return_type
-        );
-        let result = next.run(ctx, info)
-            .instrument(span)
+        self.reset_execution_state();
+        let config = self.config(ctx);
+        let overhead = self.overhead_timer(config);
+        #[cfg(feature = "metrics")]
+        if config.batch_field_metrics {
+            self.state.lock().field_measurements = Some(Default::default());
+        }
+        #[cfg(feature = "metrics")]
+        let received_at = self.state.lock().host.received_at;
+        #[cfg(feature = "metrics")]
+        if let Some(received_at) = received_at {
+            self.instruments(config)
+                .queue_time
+                .record(config.elapsed(received_at).as_millis() as u64, &[]);
+        }
+        {
+            let mut state = self.state.lock();
+            state.operation_kind = state.operations.kind(operation_name);
+            // The width is computed from the parsed document, but it's only known which of the
+            // operations is being executed once execution starts
+            if let Some(width) = state.operations.root_selection_width(operation_name) {
+                state.root_span.record("root_selection_width", width);
+                #[cfg(feature = "metrics")]
+                if config.record_root_selection_width {
+                    self.instruments(config)
+                        .root_selection_width
+                        .record(width as u64, &[]);
+                }
+            }
+            if let Some(is_write) = state.is_write() {
+                state.root_span.record("is_write", is_write);
+            }
+            // Buffered field spans are only decided on once the request has been executed
+            state.sampled = !config.buffers_field_spans()
+                && config.stages.contains(StageFlags::RESOLVE)
+                && !self.upstream_unsampled.load(Ordering::Relaxed);
+            if let (true, Some(kind), Some(name)) = (
+                config.root_span_from_operation,
+                state.operation_kind,
+                operation_name,
+            ) {
+                let span_name = format!("{} {}", kind, name);
+                state.root_span.record("otel.name", span_name.as_str());
+            }
+            #[cfg(feature = "metrics")]
+            if operation_name.is_none() {
+                let labels = state
+                    .operation_kind
+                    .map(|kind| vec![OPERATION_KIND_KEY.string(kind.to_string())])
+                    .unwrap_or_default();
+                self.instruments(config)
+                    .anonymous_operations
+                    .add(1, &labels);
+            }
+        }
+        let request_id = self.state.lock().request_id.clone();
+        let span = if config.stages.contains(StageFlags::EXECUTE) {
+            extension_span!(
+                config,
+                Level::INFO,
+                "execute",
+                estimated_complexity = tracing::field::Empty,
+                resolved_fields = tracing::field::Empty,
+                request_id = request_id.as_deref(),
+            )
+        } else {
+            Span::none()
+        };
+        let scheduled = config.phase_schedule_start();
+        if config.collects_resolve_stats() {
+            self.state.lock().metrics.start_time = config.utc_now();
+        }
+
+        #[cfg(feature = "metrics")]
+        let execute_start = config.now();
+        drop(overhead);
+        let resp = self
+            .run_phase(
+                config,
+                "execute",
+                scheduled,
+                next.run(ctx, operation_name).instrument(span.clone()),
+            )
+            .await;
+        let _overhead = self.overhead_timer(config);
+        #[cfg(feature = "metrics")]
+        if let Some(batch) = self.state.lock().field_measurements.take() {
+            self.instruments(config).record_batch(batch);
+        }
+        if let Some(limit) = config.max_error_logs_per_request {
+            let state = self.state.lock();
+            for (message, seen) in &state.error_logs {
+                if *seen > limit {
+                    tracing::warn!(
+                        target: TARGET,
+                        parent: &span,
+                        error = %message,
+                        suppressed = seen - limit,
+                        "suppressed repeated field errors",
+                    );
+                }
+            }
+        }
+        #[cfg(feature = "metrics")]
+        let (dataloader_stats, retry_stats, business_metrics) = {
+            let host = &self.state.lock().host;
+            (
+                host.dataloader_stats.clone(),
+                host.retry_stats.clone(),
+                host.business_metrics.clone(),
+            )
+        };
+        #[cfg(feature = "metrics")]
+        if let Some(stats) = dataloader_stats {
+            let (batches, loads) = stats.counts();
+            self.instruments(config)
+                .dataloader_batches
+                .add(batches, &[]);
+            self.instruments(config).dataloader_loads.add(loads, &[]);
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(stats) = retry_stats {
+            self.instruments(config)
+                .resolver_retries
+                .add(stats.retries(), &[]);
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(business) = business_metrics {
+            let state = self.state.lock();
+            let labels = state
+                .operation_kind
+                .map(|kind| vec![OPERATION_KIND_KEY.string(kind.to_string())])
+                .unwrap_or_default();
+            let totals = business.drain();
+            for (key, instrument) in &self.instruments(config).business {
+                if let Some(&value) = totals.get(key) {
+                    instrument.record(value, &labels);
+                    tracing::info!(
+                        target: TARGET,
+                        parent: &state.root_span,
+                        key = %key,
+                        value,
+                        "business_metric",
+                    );
+                }
+            }
+        }
+        let deadline = self.state.lock().host.deadline;
+        if let Some(deadline) = deadline {
+            let exceeded = config.now() > deadline;
+            let state = self.state.lock();
+            state.root_span.record("deadline_exceeded", exceeded);
+            #[cfg(feature = "metrics")]
+            if exceeded {
+                let labels = state
+                    .operation_kind
+                    .map(|kind| vec![OPERATION_KIND_KEY.string(kind.to_string())])
+                    .unwrap_or_default();
+                self.instruments(config).deadline_exceeded.add(1, &labels);
+            }
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(threshold) = config.response_cache_hit_threshold {
+            let cache_hit = matches!(
+                resp.extensions.get(RESPONSE_CACHE_MARKER),
+                Some(Value::Boolean(true))
+            );
+            if cache_hit && config.elapsed(execute_start) < threshold {
+                self.instruments(config).response_cache_hits.add(1, &[]);
+            }
+        }
+        if let Some(threshold) = config.slow_request_threshold {
+            let state = self.state.lock();
+            let duration = config.elapsed(state.start);
+            if duration > threshold {
+                #[cfg(feature = "metrics")]
+                {
+                    let mut labels = state
+                        .operation_kind
+                        .map(|kind| vec![OPERATION_KIND_KEY.string(kind.to_string())])
+                        .unwrap_or_default();
+                    if let Some(name) = operation_name {
+                        labels.push(QUERY_KEY.string(name.to_string()));
+                    }
+                    self.instruments(config).slow_requests.add(1, &labels);
+                }
+                tracing::warn!(
+                    target: TARGET,
+                    parent: &state.root_span,
+                    operation_name,
+                    duration_ms = duration.as_millis() as u64,
+                    slowest_fields = %slowest_fields(&state.metrics.resolves),
+                    "slow request",
+                );
+            }
+        }
+        // The max age is the shortest of the fields' cache hints, and `0` when none of the fields
+        // have one, in which case no Cache-Control is advertised at all
+        if resp.cache_control.max_age > 0 {
+            let ttl = resp.cache_control.max_age as u64;
+            let state = self.state.lock();
+            state.root_span.record("cache_ttl_seconds", ttl);
+            #[cfg(feature = "metrics")]
+            {
+                let labels = state
+                    .operation_kind
+                    .map(|kind| vec![OPERATION_KIND_KEY.string(kind.to_string())])
+                    .unwrap_or_default();
+                self.instruments(config).cache_ttl.record(ttl, &labels);
+            }
+        }
+        #[cfg(feature = "metrics")]
+        if !resp.errors.is_empty() {
+            // Errors alongside data mean only some of the fields failed, whereas null data means
+            // an error propagated all the way up to the root
+            let mut labels = vec![PARTIAL_KEY.bool(resp.data != Value::Null)];
+            {
+                let state = self.state.lock();
+                if let Some(is_write) = state.is_write() {
+                    labels.push(IS_WRITE_KEY.bool(is_write));
+                }
+                if let Some(version) = &state.schema_version {
+                    labels.push(SCHEMA_VERSION_KEY.string(version.clone()));
+                }
+            }
+            self.instruments(config).response_errors.add(1, &labels);
+        }
+
+        let emit_buffered = match config.tail_sampling_threshold {
+            Some(threshold) => config.elapsed(self.state.lock().start) > threshold,
+            None => config.field_span_min_duration.is_some(),
+        };
+        if emit_buffered
+            && config.stages.contains(StageFlags::RESOLVE)
+            && !self.upstream_unsampled.load(Ordering::Relaxed)
+        {
+            let mut state = self.state.lock();
+            state.sampled = true;
+            match config.field_representation {
+                FieldRepresentation::Spans => {
+                    emit_field_spans(config, &span, &state.fields, state.request_id.as_deref())
+                }
+                FieldRepresentation::Events => emit_field_events(&span, &state.fields),
+            }
+        }
+
+        #[cfg(feature = "metrics")]
+        if config.record_types_touched {
+            let touched = self.state.lock().types_touched.len();
+            self.instruments(config)
+                .types_touched
+                .record(touched as u64, &[]);
+        }
+        #[cfg(feature = "metrics")]
+        if config.record_resolve_depth {
+            self.instruments(config)
+                .max_resolve_depth
+                .record(self.max_resolve_depth.load(Ordering::Relaxed) as u64, &[]);
+        }
+        if let Some(limit) = config.response_field_limit {
+            let resolved = self.resolved_fields.load(Ordering::Relaxed);
+            if resolved > limit {
+                #[cfg(feature = "metrics")]
+                {
+                    let labels = self
+                        .state
+                        .lock()
+                        .operation_kind
+                        .map(|kind| vec![OPERATION_KIND_KEY.string(kind.to_string())])
+                        .unwrap_or_default();
+                    self.instruments(config).oversized_responses.add(1, &labels);
+                }
+                tracing::warn!(
+                    target: TARGET,
+                    parent: &span,
+                    resolved_fields = resolved,
+                    limit,
+                    "oversized response",
+                );
+            }
+        }
+        let estimated_complexity = self.state.lock().estimated_complexity;
+        if let Some(estimate) = estimated_complexity.filter(|estimate| *estimate > 0) {
+            let resolved = self.resolved_fields.load(Ordering::Relaxed);
+            span.record("estimated_complexity", estimate);
+            span.record("resolved_fields", resolved);
+            #[cfg(feature = "metrics")]
+            self.instruments(config)
+                .complexity_estimate_error
+                .record(resolved as f64 / estimate as f64, &[]);
+        }
+        #[cfg(feature = "metrics")]
+        if config.record_field_self_duration {
+            let state = self.state.lock();
+            let instruments = self.instruments(config);
+            for (stat, self_duration) in self_durations(&state.metrics.resolves) {
+                instruments.field_self_duration.record(
+                    self_duration as u64 / 1_000,
+                    &[
+                        FIELD_NAME_KEY.string(stat.field_name.clone()),
+                        PARENT_TYPE_KEY.string(stat.parent_type.clone()),
+                    ],
+                );
+            }
+        }
+        if !config.reports_resolve_stats() {
+            return resp;
+        }
+
+        let summary = {
+            let mut guard = self.state.lock();
+            let state = &mut *guard;
+            state.metrics.end_time = config.utc_now();
+            // Only read now, as the host's trace parent is set on the span once the request has
+            // been prepared, which changes the trace it's exported in
+            state.metrics.set_span_context(&state.root_span);
+            state.metrics.resolves.sort_by_key(|stat| stat.start_offset);
+            config.report_apollo_trace(&state.metrics);
+            let operation_name = state.operations.name(state.operation_name.as_deref());
+            TracingSummary::new(
+                std::mem::replace(&mut state.metrics, Metrics::new(config.utc_now())),
+                state.root_span.clone(),
+                operation_name,
+            )
+        };
+        config.submit_tracing_summary(&summary);
+        if !summary.is_returned() {
+            return resp;
+        }
+        match async_graphql::to_value(&summary) {
+            Ok(tracing) => resp.extension("tracing", tracing),
+            Err(err) => {
+                tracing::warn!(target: TARGET, error = %err, "failed to serialize tracing data");
+                resp
+            }
+        }
+    }
+
+    async fn resolve(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        info: ResolveInfo<'_>,
+        next: NextResolve<'_>,
+    ) -> ServerResult<Option<Value>> {
+        let path_node = info.path_node;
+        #[cfg(feature = "metrics")]
+        let field_name = info.name;
+        let parent_type = info.parent_type;
+        let return_type = info.return_type;
+        let config = self.config(ctx);
+        self.resolved_any.store(true, Ordering::Relaxed);
+        if self.degraded.load(Ordering::Relaxed) {
+            return next.run(ctx, info).await;
+        }
+        let budget_start = config.times_resolve_bookkeeping().then(|| config.now());
+        if config.record_resolve_depth {
+            self.max_resolve_depth
+                .fetch_max(resolve_depth(info.path_node), Ordering::Relaxed);
+        }
+        // The elements of a list are resolved too, but they aren't fields
+        if config.counts_resolved_fields() && matches!(path_node.segment, QueryPathSegment::Name(_))
+        {
+            self.resolved_fields.fetch_add(1, Ordering::Relaxed);
+        }
+        // Everything the field needs from the request is read under a single lock, which isn't
+        // taken again until the field has resolved
+        let (execution_start, request_id, location) = {
+            #[cfg_attr(not(feature = "metrics"), allow(unused_mut))]
+            let mut state = self.state.lock();
+            // The elements of a list are resolved with the list as their parent, which isn't a
+            // type of the schema
+            #[cfg(feature = "metrics")]
+            if config.record_types_touched
+                && matches!(path_node.segment, QueryPathSegment::Name(_))
+                && !state.types_touched.contains(parent_type)
+            {
+                state.types_touched.insert(parent_type.to_string());
+            }
+            let location = if config.record_field_location {
+                state.field_locations.get(
+                    state.operation_name.as_deref(),
+                    &config::collapse_list_indices(path_node),
+                )
+            } else {
+                None
+            };
+            (state.metrics.start_time, state.request_id.clone(), location)
+        };
+        let complexity_cost = if config.record_field_complexity {
+            complexity_cost(ctx, parent_type, info.name)
+        } else {
+            None
+        };
+        let stat = if config.collects_resolve_stats() {
+            let start_time = config.utc_now();
+            Some(ResolveStat {
+                path: info.path_node.to_string_vec(),
+                field_name: info.path_node.field_name().to_string(),
+                parent_type: parent_type.to_string(),
+                return_type: return_type.to_string(),
+                start_offset: (start_time - execution_start)
+                    .num_nanoseconds()
+                    .unwrap_or_default(),
+                start_time,
+                end_time: start_time,
+                location,
+                complexity_cost,
+            })
+        } else {
+            None
+        };
+        // The fields' details are emitted at the end of execution instead when they're buffered
+        let live_field_detail = !config.buffers_field_spans()
+            && config.stages.contains(StageFlags::RESOLVE)
+            && !self.upstream_unsampled.load(Ordering::Relaxed);
+        let field_event =
+            if live_field_detail && config.field_representation == FieldRepresentation::Events {
+                Some((config.now(), config.custom_span_fields(&info)))
+            } else {
+                None
+            };
+        let span =
+            if !live_field_detail || config.field_representation == FieldRepresentation::Events {
+                Span::none()
+            } else {
+                let span = extension_span!(
+                    config,
+                    Level::INFO,
+                    "field",
+                    path = %path_node,
+                    %parent_type,
+                    %return_type,
+                    resolve_seq = self.resolve_seq.fetch_add(1, Ordering::Relaxed),
+                    custom = tracing::field::Empty,
+                    nullable = tracing::field::Empty,
+                    location = location.map(tracing::field::display),
+                    complexity_cost,
+                    request_id = request_id.as_deref(),
+                );
+                if let Some(custom) = config.custom_span_fields(&info) {
+                    span.record("custom", custom.as_str());
+                }
+                if config.record_field_nullability {
+                    span.record("nullable", is_nullable(return_type));
+                }
+                span
+            };
+        // Nothing is worked out for the field's metrics if they'd be discarded anyway, e.g. until a
+        // global meter provider is installed
+        #[cfg(feature = "metrics")]
+        let records_metrics = !self.instruments(config).discards;
+        #[cfg(feature = "metrics")]
+        let resolve_start =
+            (records_metrics && config.record_type_resolve_duration).then(|| config.now());
+        #[cfg(feature = "metrics")]
+        let mut labels = Vec::with_capacity(4);
+        #[cfg(feature = "metrics")]
+        if records_metrics {
+            labels.push(QUERY_KEY.string(config.normalize_path(path_node)));
+            labels.push(QUERY_TYPE_KEY.string(parent_type.to_string()));
+            if config.return_type_as_label {
+                labels.push(RETURN_TYPE_KEY.string(return_type.to_string()));
+            }
+        }
+        let resolve = next.run(ctx, info)
+            .instrument(span.clone())
             .map_err(|err| {
-                REQUEST_ERRORS.add(1, &[QUERY_KEY.string(path.clone()), QUERY_TYPE_KEY.string(parent_type.clone()), RETURN_TYPE_KEY.string(return_type.clone())]);
-                tracing::error!(target: TARGET, error = %err.message, extensions = ?&err.extensions);
+                // An errored field takes the lock once more, for everything its error records
+                let log = {
+                    let mut state = self.state.lock();
+                    #[cfg(feature = "metrics")]
+                    if records_metrics {
+                        let severity = SEVERITY_KEY.string(config.error_severity_of(&err).as_str());
+                        let mut request_labels = labels.clone();
+                        request_labels.push(severity.clone());
+                        // Keyed by the field rather than the path, so errors from the same
+                        // resolver aggregate across queries
+                        let mut field_labels = vec![
+                            FIELD_NAME_KEY.string(field_name.to_string()),
+                            PARENT_TYPE_KEY.string(parent_type.to_string()),
+                            severity,
+                        ];
+                        if let Some(is_write) = state.is_write() {
+                            request_labels.push(IS_WRITE_KEY.bool(is_write));
+                        }
+                        if let Some(version) = &state.schema_version {
+                            request_labels.push(SCHEMA_VERSION_KEY.string(version.clone()));
+                            field_labels.push(SCHEMA_VERSION_KEY.string(version.clone()));
+                        }
+                        self.record_field(
+                            &mut state,
+                            config,
+                            FieldInstrument::RequestErrors,
+                            1,
+                            request_labels,
+                        );
+                        if error_code(&err).is_some_and(|code| config.is_authz_error(&code)) {
+                            // Also counted on their own, so denials can be told apart from
+                            // resolvers failing
+                            self.record_field(
+                                &mut state,
+                                config,
+                                FieldInstrument::AuthzRejections,
+                                1,
+                                field_labels.clone(),
+                            );
+                        }
+                        self.record_field(
+                            &mut state,
+                            config,
+                            FieldInstrument::FieldErrors,
+                            1,
+                            field_labels,
+                        );
+                    }
+                    config.error_events.emits_tracing_events()
+                        && self.within_error_log_limit(&mut state, config, &err.message)
+                };
+                if log {
+                    tracing::error!(target: TARGET, error = %err.message, extensions = ?&err.extensions);
+                }
+                if config.error_events.emits_span_events() {
+                    add_error_span_event(&path_node.to_string(), &err);
+                }
                 err
-            })
-            .await;
-        let duration = Instant::now() - self.start;
-        // This cast should be fine, because if this request duration overflows an u64, we have
-        // bigger issues
-        REQUEST_DURATION.record(
-            duration.as_millis() as u64,
-            &[
-                QUERY_KEY.string(path),
-                QUERY_TYPE_KEY.string(parent_type),
-                RETURN_TYPE_KEY.string(return_type),
-            ],
-        );
+            });
+        if let Some(budget_start) = budget_start {
+            self.spend_instrumentation_budget(config, budget_start);
+        }
+        let result = match config.blocking_poll_threshold {
+            Some(threshold) => {
+                let (result, longest_poll) =
+                    with_longest_poll(config.shared_clock(), resolve).await;
+                if longest_poll > threshold {
+                    span.in_scope(|| {
+                        tracing::warn!(
+                            target: TARGET,
+                            path = %path_node,
+                            poll_duration = longest_poll.as_micros() as u64,
+                            "resolving the field held the executor for {:?}, the resolver may be \
+                             blocking the async runtime",
+                            longest_poll,
+                        )
+                    });
+                }
+                result
+            }
+            None => resolve.await,
+        };
+        let budget_start = budget_start.map(|_| config.now());
+        {
+            // Everything the field records into the request is recorded under a single lock
+            let mut state = self.state.lock();
+            if let Some(mut stat) = stat {
+                stat.end_time = config.utc_now();
+                let min_duration = config.field_span_min_duration.unwrap_or_default();
+                let slow_enough =
+                    stat.duration_nanos().unwrap_or_default() as u128 >= min_duration.as_nanos();
+                if config.buffers_field_spans() && slow_enough {
+                    state.fields.insert(path_node.to_string(), stat.clone());
+                }
+                if config.keeps_resolve_stats() {
+                    state.metrics.resolves.push(stat);
+                }
+            }
+            #[cfg(feature = "metrics")]
+            if records_metrics && config.record_list_sizes {
+                if let Ok(Some(Value::List(items))) = &result {
+                    self.record_field(
+                        &mut state,
+                        config,
+                        FieldInstrument::ListFieldSize,
+                        items.len() as u64,
+                        vec![FIELD_NAME_KEY.string(field_name.to_string())],
+                    );
+                }
+            }
+            #[cfg(feature = "metrics")]
+            if let Some(resolve_start) = resolve_start {
+                self.record_field(
+                    &mut state,
+                    config,
+                    FieldInstrument::TypeResolveDuration,
+                    config.elapsed(resolve_start).as_micros() as u64,
+                    vec![PARENT_TYPE_KEY.string(parent_type.to_string())],
+                );
+            }
+            #[cfg(feature = "metrics")]
+            if records_metrics {
+                let duration = config.elapsed(state.start);
+                // Errored fields are recorded too, otherwise the durations would be skewed
+                // towards the fields which resolved successfully
+                let status = if result.is_ok() { "ok" } else { "error" };
+                labels.push(STATUS_KEY.string(status));
+                // This cast should be fine, because if this request duration overflows an u64,
+                // we have bigger issues
+                self.record_field(
+                    &mut state,
+                    config,
+                    FieldInstrument::RequestDuration,
+                    duration.as_millis() as u64,
+                    labels,
+                );
+            }
+        }
+        if let Some((event_start, custom)) = field_event {
+            tracing::info!(
+                target: TARGET,
+                path = %path_node,
+                %parent_type,
+                %return_type,
+                custom = custom.as_deref(),
+                duration = config.elapsed(event_start).as_nanos() as u64,
+                error = result.is_err(),
+                "field",
+            );
+        }
+        if let Some(budget_start) = budget_start {
+            self.spend_instrumentation_budget(config, budget_start);
+        }
         result
     }
 }
@@ -211,9 +2114,24 @@ impl Extension for OpenTelemetryExtension {
 mod tests {
     use super::*;
     use async_graphql::*;
+    #[cfg(feature = "metrics")]
+    use testing::MetricsCollector;
+    use testing::SpanCollector;
+    use tracing_subscriber::layer::SubscriberExt;
 
     struct QueryRoot;
 
+    #[cfg(feature = "metrics")]
+    struct MutationRoot;
+
+    #[cfg(feature = "metrics")]
+    #[Object]
+    impl MutationRoot {
+        pub async fn rename(&self, name: String) -> String {
+            name
+        }
+    }
+
     #[Object]
     impl QueryRoot {
         pub async fn get_jane(&self) -> Query {
@@ -224,6 +2142,111 @@ mod tests {
                 },
             }
         }
+
+        pub async fn people(&self) -> Vec<SubQuery> {
+            vec![
+                SubQuery {
+                    name: "Jane".to_owned(),
+                },
+                SubQuery {
+                    name: "John".to_owned(),
+                },
+            ]
+        }
+
+        pub async fn coded_error(&self) -> Result<i32> {
+            Err(Error::new("forbidden").extend_with(|_, e| e.set("code", "FORBIDDEN")))
+        }
+
+        pub async fn blocking(&self) -> i32 {
+            std::thread::sleep(std::time::Duration::from_millis(30));
+            1
+        }
+
+        // A nullable field which reports an error, leaving the rest of the response intact
+        pub async fn optional_error(&self, ctx: &Context<'_>) -> Option<i32> {
+            ctx.add_error(ServerError::new("failed to resolve", None));
+            None
+        }
+
+        pub async fn slow_error(&self) -> Result<i32> {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            Err("failed to resolve".into())
+        }
+
+        pub async fn greet(&self, name: String, times: i32) -> String {
+            format!("Hello {}", name).repeat(times as usize)
+        }
+
+        pub async fn slow_person(&self) -> SlowPerson {
+            SlowPerson
+        }
+
+        // Takes exactly 250ms by the schema's mock clock
+        pub async fn tick(&self, ctx: &Context<'_>) -> bool {
+            ctx.data_unchecked::<testing::MockClock>()
+                .advance(std::time::Duration::from_millis(250));
+            true
+        }
+
+        #[graphql(complexity = 10)]
+        pub async fn expensive(&self) -> i32 {
+            10
+        }
+
+        // Yields once, and the schema's mock clock moves on 100ms before it's polled again
+        pub async fn starved(&self, ctx: &Context<'_>) -> bool {
+            let clock = ctx.data_unchecked::<testing::MockClock>();
+            let mut yielded = false;
+            std::future::poll_fn(|cx| {
+                if yielded {
+                    return std::task::Poll::Ready(true);
+                }
+                yielded = true;
+                cx.waker().wake_by_ref();
+                clock.advance(std::time::Duration::from_millis(100));
+                std::task::Poll::Pending
+            })
+            .await
+        }
+    }
+
+    /// Hands out the same instance for every request, as an executor retrying requests could,
+    /// which also lets a test look at what the instance has kept
+    #[derive(Clone, Default)]
+    struct Shared(Arc<OpenTelemetryExtension>);
+
+    impl ExtensionFactory for Shared {
+        fn create(&self) -> Arc<dyn Extension> {
+            self.0.clone()
+        }
+    }
+
+    struct SubscriptionRoot;
+
+    #[Subscription]
+    impl SubscriptionRoot {
+        async fn count(&self) -> impl futures_util::Stream<Item = i32> {
+            futures_util::stream::iter(0..3)
+        }
+
+        async fn nothing(&self) -> impl futures_util::Stream<Item = i32> {
+            futures_util::stream::empty()
+        }
+
+        async fn never(&self) -> impl futures_util::Stream<Item = i32> {
+            futures_util::stream::pending()
+        }
+    }
+
+    struct SlowPerson;
+
+    #[Object]
+    impl SlowPerson {
+        pub async fn name(&self) -> &str {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            "Jane"
+        }
     }
 
     #[derive(SimpleObject)]
@@ -257,4 +2280,3219 @@ mod tests {
         let request = Request::new(query);
         schema.execute(request).await;
     }
+
+    const JANE_QUERY: &str = "{ getJane { id details { name } } }";
+
+    fn tracing_schema() -> Schema<QueryRoot, EmptyMutation, EmptySubscription> {
+        Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().return_tracing_data_to_client(true))
+            .finish()
+    }
+
+    #[tokio::test]
+    async fn tracing_data_is_returned_without_otel_ids_when_bridge_is_absent() {
+        let response = tracing_schema().execute(JANE_QUERY).await;
+        let tracing = serde_json::to_value(&response.extensions["tracing"]).unwrap();
+
+        assert_eq!(tracing["version"], 1);
+        assert_eq!(
+            tracing["execution"]["resolvers"].as_array().unwrap().len(),
+            4
+        );
+        assert!(tracing.get("traceId").is_none());
+        assert!(tracing.get("spanId").is_none());
+    }
+
+    #[tokio::test]
+    async fn tracing_data_includes_root_span_otel_ids() {
+        use opentelemetry::trace::TracerProvider as _;
+
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder().build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.get_tracer("test", None)));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let response = tracing_schema().execute(JANE_QUERY).await;
+        let tracing = serde_json::to_value(&response.extensions["tracing"]).unwrap();
+
+        assert_eq!(tracing["traceId"].as_str().unwrap().len(), 32);
+        assert_eq!(tracing["spanId"].as_str().unwrap().len(), 16);
+    }
+
+    fn traced_request(parent_trace_id: u128) -> Request {
+        use opentelemetry::trace::{SpanContext, SpanId, TraceId, TraceState};
+
+        let parent = opentelemetry::Context::new().with_remote_span_context(SpanContext::new(
+            TraceId::from_u128(parent_trace_id),
+            SpanId::from_u64(9),
+            opentelemetry::trace::TRACE_FLAG_SAMPLED,
+            true,
+            TraceState::default(),
+        ));
+        Request::new(JANE_QUERY).data(TraceParent(parent))
+    }
+
+    #[tokio::test]
+    async fn tracing_data_is_in_the_trace_of_the_host_parent_when_bridge_is_present() {
+        use opentelemetry::trace::TracerProvider as _;
+
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder().build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.get_tracer("test", None)));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let response = tracing_schema().execute(traced_request(7)).await;
+        let tracing = serde_json::to_value(&response.extensions["tracing"]).unwrap();
+
+        assert_eq!(tracing["traceId"], format!("{:032x}", 7));
+        assert_ne!(tracing["spanId"], format!("{:016x}", 9));
+    }
+
+    #[tokio::test]
+    async fn tracing_data_omits_the_host_parent_trace_when_bridge_is_absent() {
+        let response = tracing_schema().execute(traced_request(7)).await;
+        let tracing = serde_json::to_value(&response.extensions["tracing"]).unwrap();
+
+        assert!(tracing.get("traceId").is_none());
+        assert!(tracing.get("spanId").is_none());
+    }
+
+    #[tokio::test]
+    async fn tracing_data_is_not_returned_by_default() {
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .finish();
+        let response = schema.execute(JANE_QUERY).await;
+        assert!(!response.extensions.contains_key("tracing"));
+    }
+
+    fn collect_spans() -> (SpanCollector, tracing::subscriber::DefaultGuard) {
+        let collector = SpanCollector::default();
+        let guard = tracing::subscriber::set_default(
+            tracing_subscriber::registry().with(collector.clone()),
+        );
+        (collector, guard)
+    }
+
+    #[tokio::test]
+    async fn parse_span_records_the_shape_of_the_variables() {
+        let (collector, _guard) = collect_spans();
+
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .finish();
+        let variables = serde_json::json!({ "name": "Jane", "times": 2 });
+        let request = Request::new(
+            "query Greet($name: String!, $times: Int!) { greet(name: $name, times: $times) }",
+        )
+        .variables(Variables::from_json(variables.clone()));
+        let response = schema.execute(request).await;
+        assert!(response.errors.is_empty());
+
+        let spans = collector.spans();
+        let parse = spans.span("parse").unwrap();
+        assert_eq!(parse.field("variables_count"), Some("2"));
+        assert_eq!(
+            parse.field("variables_bytes"),
+            Some(
+                serde_json::to_string(&variables)
+                    .unwrap()
+                    .len()
+                    .to_string()
+                    .as_str()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn field_spans_are_nested_within_the_execute_and_request_spans() {
+        let (collector, _guard) = collect_spans();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .finish();
+        schema.execute(JANE_QUERY).await;
+
+        let spans = collector.spans();
+        let request = spans.span("request").unwrap();
+        let execute = spans.span("execute").unwrap();
+        let field = spans.field("getJane.details.name").unwrap();
+        assert!(spans.is_descendant_of(execute, request));
+        assert!(spans.is_descendant_of(field, execute));
+        assert!(spans.is_descendant_of(field, spans.field("getJane.details").unwrap()));
+    }
+
+    #[tokio::test]
+    async fn field_spans_are_only_emitted_for_requests_over_the_threshold() {
+        async fn collect_field_spans(threshold: std::time::Duration) -> testing::SpanTree {
+            let (collector, _guard) = collect_spans();
+            let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+                .extension(OpenTelemetry)
+                .data(OpenTelemetryConfig::default().tail_sampling_threshold(threshold))
+                .finish();
+            schema.execute(JANE_QUERY).await;
+            collector.spans()
+        }
+
+        let spans = collect_field_spans(std::time::Duration::ZERO).await;
+        assert_eq!(spans.spans_named("field").count(), 4);
+        assert!(spans
+            .spans_named("field")
+            .all(|span| span.field("duration").is_some()));
+        let name = spans.field("getJane.details.name").unwrap();
+        assert_eq!(name.field("parent_type"), Some("SubQuery"));
+        assert_eq!(
+            spans.parent(name).unwrap().field("path"),
+            Some("getJane.details")
+        );
+        assert!(spans.is_descendant_of(name, spans.span("execute").unwrap()));
+
+        let spans = collect_field_spans(std::time::Duration::from_secs(3600)).await;
+        assert_eq!(spans.spans_named("field").count(), 0);
+    }
+
+    #[tokio::test]
+    async fn root_span_is_named_after_the_operation() {
+        let (collector, _guard) = collect_spans();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().root_span_from_operation(true))
+            .finish();
+        schema.execute("query GetJane { getJane { id } }").await;
+        schema.execute(JANE_QUERY).await;
+
+        let spans = collector.spans();
+        let requests = spans.spans_named("request").collect::<Vec<_>>();
+        assert_eq!(requests[0].field("otel.name"), Some("query GetJane"));
+        assert_eq!(requests[1].field("otel.name"), None);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn metrics_are_recorded_with_the_provided_meter() {
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+            .finish();
+
+        schema.execute(JANE_QUERY).await;
+        schema.execute(JANE_QUERY).await;
+
+        assert_eq!(metrics.sum("graphql_requests"), 2.0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn metrics_flow_when_the_global_provider_is_installed_after_the_schema() {
+        let names = MetricNames::default().requests("late_provider_requests");
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().metric_names(names).unwrap())
+            .finish();
+        schema.execute(JANE_QUERY).await;
+
+        let metrics = MetricsCollector::default();
+        opentelemetry::global::set_meter_provider(metrics.provider());
+        schema.execute(JANE_QUERY).await;
+
+        assert_eq!(metrics.sum("late_provider_requests"), 1.0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn the_global_instruments_are_created_once_a_provider_is_installed() {
+        let metrics = MetricsCollector::default();
+        opentelemetry::global::set_meter_provider(metrics.provider());
+        let config = OpenTelemetryConfig::default();
+
+        assert!(std::ptr::eq(config.instruments(), config.instruments()));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn aliases_are_counted_on_the_request_span() {
+        let (collector, _guard) = collect_spans();
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .record_alias_count(true)
+                    .meter(metrics.meter()),
+            )
+            .finish();
+        let query = r#"
+            query {
+                a: greet(name: "a", times: 1)
+                b: greet(name: "b", times: 1)
+                getJane { ...Jane }
+            }
+            fragment Jane on Query { id named: details { name } }
+        "#;
+        schema.execute(query).await;
+
+        let spans = collector.spans();
+        assert_eq!(
+            spans.span("request").unwrap().field("alias_count"),
+            Some("3")
+        );
+        assert_eq!(metrics.sum("graphql_alias_count"), 3.0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn durations_are_recorded_for_errored_fields() {
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+            .finish();
+        schema.execute("{ slowError getJane { id } }").await;
+
+        let durations = metrics.named("graphql_request_duration");
+        let status = |path: &str| {
+            let metric = durations
+                .iter()
+                .find(|metric| metric.labels["query_name"] == path)
+                .unwrap();
+            (metric.labels["status"].clone(), metric.sum)
+        };
+        let (slow_error_status, slow_error_duration) = status("slowError");
+        assert_eq!(slow_error_status, "error");
+        assert!(slow_error_duration >= 20.0);
+        assert_eq!(status("getJane.id").0, "ok");
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn list_indices_are_dropped_from_the_path_label() {
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+            .finish();
+        schema.execute("{ people { name } }").await;
+
+        let durations = metrics.named("graphql_request_duration");
+        let names = durations
+            .iter()
+            .find(|metric| metric.labels["query_name"] == "people.name")
+            .unwrap();
+        assert_eq!(names.count, Some(2));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn path_label_uses_the_provided_normalizer() {
+        let metrics = MetricsCollector::default();
+        let config = OpenTelemetryConfig::default()
+            .meter(metrics.meter())
+            .path_normalizer(Arc::new(|path: &[String]| path[0].clone()));
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(config)
+            .finish();
+        schema.execute(JANE_QUERY).await;
+
+        let durations = metrics.named("graphql_request_duration");
+        assert!(durations
+            .iter()
+            .all(|metric| metric.labels["query_name"] == "getJane"));
+        let count: u64 = durations.iter().filter_map(|metric| metric.count).sum();
+        assert_eq!(count, 4);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn requests_are_counted_by_client() {
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(metrics.meter())
+                    .client_allowlist(vec!["ios"]),
+            )
+            .finish();
+        let client = |name: &str| ClientInfo {
+            name: name.to_string(),
+            version: "1.0.0".to_string(),
+        };
+        schema
+            .execute(Request::new(JANE_QUERY).data(client("ios")))
+            .await;
+        schema
+            .execute(Request::new(JANE_QUERY).data(client("scraper")))
+            .await;
+        schema.execute(JANE_QUERY).await;
+
+        let requests = metrics.named("graphql_requests");
+        let count = |name: Option<&str>, version: Option<&str>| {
+            requests
+                .iter()
+                .find(|metric| {
+                    metric.labels.get("client_name").map(String::as_str) == name
+                        && metric.labels.get("client_version").map(String::as_str) == version
+                })
+                .map(|metric| metric.sum)
+        };
+        assert_eq!(count(Some("ios"), Some("1.0.0")), Some(1.0));
+        assert_eq!(count(Some("other"), Some("other")), Some(1.0));
+        assert_eq!(count(None, None), Some(1.0));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn parses_are_classified_by_the_cache_hit_threshold() {
+        async fn parse_cache_metrics(threshold: std::time::Duration) -> (f64, f64) {
+            let metrics = MetricsCollector::default();
+            let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+                .extension(OpenTelemetry)
+                .data(
+                    OpenTelemetryConfig::default()
+                        .meter(metrics.meter())
+                        .parse_cache_hit_threshold(threshold),
+                )
+                .finish();
+            schema.execute(JANE_QUERY).await;
+            (
+                metrics.sum("graphql_parse_cache_hits"),
+                metrics.sum("graphql_parse_cache_misses"),
+            )
+        }
+
+        assert_eq!(
+            parse_cache_metrics(std::time::Duration::from_secs(3600)).await,
+            (1.0, 0.0)
+        );
+        assert_eq!(
+            parse_cache_metrics(std::time::Duration::ZERO).await,
+            (0.0, 1.0)
+        );
+    }
+
+    #[tokio::test]
+    async fn errors_are_recorded_as_events_on_the_active_otel_span() {
+        use opentelemetry::trace::{FutureExt, Tracer, TracerProvider as _};
+
+        let exporter = testing::SpanExportCollector::default();
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let cx = opentelemetry::Context::current_with_span(
+            provider.get_tracer("test", None).start("http_request"),
+        );
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().error_events(ErrorEvents::OpenTelemetry))
+            .finish();
+        schema
+            .execute("{ codedError }")
+            .with_context(cx.clone())
+            .await;
+        cx.span().end();
+
+        let spans = exporter.spans();
+        let event = spans[0].message_events.iter().next().unwrap();
+        assert_eq!(event.name, "graphql.error");
+        let attribute = |key: &'static str| {
+            event
+                .attributes
+                .iter()
+                .find(|attribute| attribute.key == opentelemetry::Key::from_static_str(key))
+                .map(|attribute| attribute.value.to_string())
+        };
+        assert_eq!(attribute("message").as_deref(), Some("forbidden"));
+        assert_eq!(attribute("path").as_deref(), Some("codedError"));
+        assert_eq!(attribute("code").as_deref(), Some("FORBIDDEN"));
+    }
+
+    #[tokio::test]
+    async fn errors_are_not_recorded_as_otel_events_without_an_active_otel_span() {
+        use opentelemetry::trace::{FutureExt, TracerProvider as _};
+
+        let exporter = testing::SpanExportCollector::default();
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.get_tracer("test", None)));
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().error_events(ErrorEvents::OpenTelemetry))
+            .finish();
+        let response = schema
+            .execute("{ codedError }")
+            .with_context(opentelemetry::Context::new())
+            .await;
+        drop(_guard);
+
+        assert_eq!(response.errors.len(), 1);
+        let spans = exporter.spans();
+        assert!(!spans.is_empty());
+        assert!(spans
+            .iter()
+            .flat_map(|span| span.message_events.iter())
+            .all(|event| event.name != "graphql.error"));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn requests_are_tagged_with_whether_they_write() {
+        let (collector, _guard) = collect_spans();
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+            .finish();
+        schema.execute(JANE_QUERY).await;
+        schema.execute(r#"mutation { rename(name: "Jo") }"#).await;
+
+        let spans = collector.spans();
+        let requests = spans.spans_named("request").collect::<Vec<_>>();
+        assert_eq!(requests[0].field("is_write"), Some("false"));
+        assert_eq!(requests[1].field("is_write"), Some("true"));
+
+        let requests = metrics.named("graphql_requests");
+        let count = |is_write: &str| {
+            requests
+                .iter()
+                .find(|metric| metric.labels["is_write"] == is_write)
+                .map(|metric| metric.sum)
+        };
+        assert_eq!(count("false"), Some(1.0));
+        assert_eq!(count("true"), Some(1.0));
+    }
+
+    #[tokio::test]
+    async fn query_source_can_be_left_off_the_parse_span() {
+        let (collector, _guard) = collect_spans();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().record_query_source(false))
+            .finish();
+        schema.execute(JANE_QUERY).await;
+        tracing_schema().execute(JANE_QUERY).await;
+
+        let spans = collector.spans();
+        let parses = spans.spans_named("parse").collect::<Vec<_>>();
+        assert_eq!(parses[0].field("source"), None);
+        assert_eq!(parses[1].field("source"), Some(JANE_QUERY));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn resolve_durations_are_recorded_by_type() {
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(metrics.meter())
+                    .record_type_resolve_duration(true),
+            )
+            .finish();
+        schema.execute("{ slowError getJane { id } }").await;
+
+        let durations = metrics.named("graphql_type_resolve_duration");
+        let by_type = |parent_type: &str| {
+            durations
+                .iter()
+                .find(|metric| metric.labels["parent_type"] == parent_type)
+                .unwrap()
+        };
+        assert_eq!(durations[0].labels.len(), 1);
+        assert_eq!(by_type("QueryRoot").count, Some(2));
+        assert!(by_type("QueryRoot").sum >= 20_000.0);
+        assert_eq!(by_type("Query").count, Some(1));
+    }
+
+    #[tokio::test]
+    async fn blocking_resolvers_are_warned_about() {
+        let (collector, _guard) = collect_spans();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .blocking_poll_threshold(std::time::Duration::from_millis(10)),
+            )
+            .finish();
+        schema
+            .execute("{ blocking slowError getJane { id } }")
+            .await;
+
+        let spans = collector.spans();
+        let warnings = spans
+            .events()
+            .iter()
+            .filter(|event| event.level == Level::WARN)
+            .collect::<Vec<_>>();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].field("path"), Some("blocking"));
+        assert_eq!(
+            spans.event_span(warnings[0]).unwrap().field("path"),
+            Some("blocking")
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn request_and_error_counters_are_labelled_with_the_schema_version() {
+        let metrics = MetricsCollector::default();
+        let config = OpenTelemetryConfig::default()
+            .meter(metrics.meter())
+            .schema_version("v1")
+            .unwrap();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(config)
+            .finish();
+        schema.execute("{ codedError }").await;
+        schema
+            .execute(Request::new("{ codedError }").data(SchemaVersion("v2".to_string())))
+            .await;
+        schema
+            .execute(Request::new("{ codedError }").data(SchemaVersion("not valid".to_string())))
+            .await;
+
+        for name in &[
+            "graphql_requests",
+            "graphql_request_errors",
+            "graphql_field_errors",
+        ] {
+            let count = |version: &str| {
+                metrics
+                    .named(name)
+                    .iter()
+                    .filter(|metric| metric.labels["schema_version"] == version)
+                    .map(|metric| metric.sum)
+                    .sum::<f64>()
+            };
+            assert_eq!(count("v1"), 2.0, "{}", name);
+            assert_eq!(count("v2"), 1.0, "{}", name);
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn schema_version_must_be_bounded() {
+        assert!(OpenTelemetryConfig::default()
+            .schema_version("2021-05-04.1+abc")
+            .is_ok());
+        assert_eq!(
+            OpenTelemetryConfig::default()
+                .schema_version("a".repeat(65))
+                .unwrap_err(),
+            ConfigError::InvalidSchemaVersion("a".repeat(65))
+        );
+        assert!(OpenTelemetryConfig::default()
+            .schema_version("version one")
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn recommended_options_are_only_a_fallback() {
+        let (collector, _guard) = collect_spans();
+        let query = "query GetJane { getJane { id } }";
+        Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry::recommended())
+            .finish()
+            .execute(query)
+            .await;
+        Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry::recommended())
+            .data(OpenTelemetryConfig::default())
+            .finish()
+            .execute(query)
+            .await;
+
+        let spans = collector.spans();
+        let requests = spans.spans_named("request").collect::<Vec<_>>();
+        assert_eq!(requests[0].field("otel.name"), Some("query GetJane"));
+        assert_eq!(requests[1].field("otel.name"), None);
+    }
+
+    #[tokio::test]
+    async fn validation_records_the_query_complexity() {
+        let (collector, _guard) = collect_spans();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .finish();
+        schema.execute(JANE_QUERY).await;
+
+        let spans = collector.spans();
+        let validation = spans.span("validation").unwrap();
+        assert_eq!(validation.field("complexity"), Some("4"));
+        assert_eq!(validation.field("depth"), Some("3"));
+    }
+
+    #[tokio::test]
+    async fn custom_fields_are_recorded_on_the_field_spans() {
+        let (collector, _guard) = collect_spans();
+        let config =
+            OpenTelemetryConfig::default().field_span_fields(Arc::new(|info: &ResolveInfo<'_>| {
+                vec![
+                    ("name", info.name.to_string()),
+                    ("alias", info.alias.unwrap_or("none").to_string()),
+                ]
+            }));
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(config)
+            .finish();
+        schema.execute("{ jane: getJane { id } }").await;
+
+        let spans = collector.spans();
+        assert_eq!(
+            spans.field("jane").unwrap().field("custom"),
+            Some("name=getJane alias=jane")
+        );
+        assert_eq!(
+            spans.field("jane.id").unwrap().field("custom"),
+            Some("name=id alias=none")
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn time_to_first_response_is_recorded_by_operation_kind() {
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+            .finish();
+        let received_at = std::time::Instant::now() - std::time::Duration::from_secs(1);
+        schema
+            .execute(Request::new(JANE_QUERY).data(ReceivedAt(received_at)))
+            .await;
+        schema.execute(r#"mutation { rename(name: "Jo") }"#).await;
+        schema
+            .execute_stream(Request::new("subscription { count }").data(ReceivedAt(received_at)))
+            .collect::<Vec<_>>()
+            .await;
+
+        let recorded = metrics.named("graphql_time_to_first_response");
+        let by_kind = |kind: &str| {
+            recorded
+                .iter()
+                .find(|metric| metric.labels["operation_kind"] == kind)
+                .unwrap()
+        };
+        assert!(by_kind("query").sum >= 1000.0);
+        assert_eq!(by_kind("mutation").count, Some(1));
+        assert!(by_kind("subscription").sum >= 1000.0);
+        assert_eq!(by_kind("subscription").count, Some(1));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn list_sizes_are_recorded_by_field() {
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(metrics.meter())
+                    .record_list_sizes(true),
+            )
+            .finish();
+        schema.execute("{ people { name } getJane { id } }").await;
+
+        let sizes = metrics.named("graphql_list_field_size");
+        assert_eq!(sizes.len(), 1);
+        assert_eq!(sizes[0].labels["field_name"], "people");
+        assert_eq!((sizes[0].sum, sizes[0].count), (2.0, Some(1)));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn metrics_are_recorded_with_the_tenant_meter() {
+        let shared = MetricsCollector::default();
+        let tenant = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(shared.meter()))
+            .finish();
+        schema
+            .execute(Request::new(JANE_QUERY).data(TenantMeter(tenant.meter())))
+            .await;
+        schema.execute(JANE_QUERY).await;
+
+        assert_eq!(tenant.sum("graphql_requests"), 1.0);
+        assert!(!tenant.named("graphql_request_duration").is_empty());
+        assert_eq!(shared.sum("graphql_requests"), 1.0);
+    }
+
+    #[tokio::test]
+    async fn apollo_trace_is_handed_to_the_sink() {
+        let traces = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let sink = traces.clone();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .apollo_trace_sink(Arc::new(move |trace| sink.lock().push(trace))),
+            )
+            .finish();
+        let response = schema
+            .execute("{ jane: getJane { id details { name } } people { name } }")
+            .await;
+        assert!(!response.extensions.contains_key("tracing"));
+
+        let traces = traces.lock();
+        assert_eq!(traces.len(), 1);
+        let trace = &traces[0];
+        assert!(trace.start_time <= trace.end_time);
+        let root = &trace.root;
+        assert_eq!(root.response_name, None);
+        let names = |node: &apollo::Node| {
+            node.child
+                .iter()
+                .map(|child| child.response_name.clone().unwrap())
+                .collect::<std::collections::HashSet<_>>()
+        };
+        assert_eq!(
+            names(root),
+            ["jane", "people"]
+                .iter()
+                .map(|name| name.to_string())
+                .collect()
+        );
+
+        let jane = root
+            .child
+            .iter()
+            .find(|child| child.response_name.as_deref() == Some("jane"))
+            .unwrap();
+        assert_eq!(jane.parent_type, "QueryRoot");
+        assert_eq!(jane.r#type, "Query!");
+        assert!(jane.start_time <= jane.end_time);
+        assert!(jane.end_time <= trace.duration_ns);
+        assert_eq!(
+            names(jane),
+            ["id", "details"]
+                .iter()
+                .map(|name| name.to_string())
+                .collect()
+        );
+
+        let people = root
+            .child
+            .iter()
+            .find(|child| child.response_name.as_deref() == Some("people"))
+            .unwrap();
+        let indices = people
+            .child
+            .iter()
+            .map(|element| element.index)
+            .collect::<Vec<_>>();
+        assert_eq!(indices, vec![Some(0), Some(1)]);
+        for element in &people.child {
+            assert_eq!(element.response_name, None);
+            assert_eq!(
+                names(element),
+                std::iter::once("name".to_string()).collect()
+            );
+            assert_eq!(element.child[0].parent_type, "SubQuery");
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn field_spans_are_skipped_when_upstream_did_not_sample() {
+        use opentelemetry::trace::{FutureExt, SpanContext, SpanId, TraceId, TraceState};
+
+        let upstream = |flags| {
+            opentelemetry::Context::new().with_remote_span_context(SpanContext::new(
+                TraceId::from_u128(1),
+                SpanId::from_u64(1),
+                flags,
+                true,
+                TraceState::default(),
+            ))
+        };
+        for (ignore_upstream_sampling, flags, expect_field_spans) in [
+            (false, opentelemetry::trace::TRACE_FLAG_NOT_SAMPLED, false),
+            (false, opentelemetry::trace::TRACE_FLAG_SAMPLED, true),
+            (true, opentelemetry::trace::TRACE_FLAG_NOT_SAMPLED, true),
+        ] {
+            let (collector, _guard) = collect_spans();
+            let metrics = MetricsCollector::default();
+            let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+                .extension(OpenTelemetry)
+                .data(
+                    OpenTelemetryConfig::default()
+                        .meter(metrics.meter())
+                        .ignore_upstream_sampling(ignore_upstream_sampling),
+                )
+                .finish();
+            schema
+                .execute(JANE_QUERY)
+                .with_context(upstream(flags))
+                .await;
+
+            let spans = collector.spans();
+            assert!(spans.spans_named("request").next().is_some());
+            assert_eq!(
+                spans.spans_named("field").next().is_some(),
+                expect_field_spans
+            );
+            assert_eq!(metrics.sum("graphql_requests"), 1.0);
+            assert!(!metrics.named("graphql_request_duration").is_empty());
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn responses_served_from_a_response_cache_are_counted() {
+        struct ResponseCache;
+
+        impl ExtensionFactory for ResponseCache {
+            fn create(&self) -> Arc<dyn Extension> {
+                Arc::new(ResponseCacheExtension)
+            }
+        }
+
+        struct ResponseCacheExtension;
+
+        #[async_trait::async_trait]
+        impl Extension for ResponseCacheExtension {
+            async fn execute(
+                &self,
+                ctx: &ExtensionContext<'_>,
+                operation_name: Option<&str>,
+                next: NextExecute<'_>,
+            ) -> Response {
+                if operation_name == Some("Cached") {
+                    Response::new(Value::Null)
+                        .extension(RESPONSE_CACHE_MARKER, Value::Boolean(true))
+                } else {
+                    next.run(ctx, operation_name).await
+                }
+            }
+        }
+
+        async fn response_cache_hits(threshold: std::time::Duration) -> f64 {
+            let metrics = MetricsCollector::default();
+            let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+                .extension(OpenTelemetry)
+                .extension(ResponseCache)
+                .data(
+                    OpenTelemetryConfig::default()
+                        .meter(metrics.meter())
+                        .response_cache_hit_threshold(threshold),
+                )
+                .finish();
+            schema.execute("query Cached { getJane { id } }").await;
+            schema.execute("query Uncached { getJane { id } }").await;
+            metrics.sum("graphql_response_cache_hits")
+        }
+
+        assert_eq!(
+            response_cache_hits(std::time::Duration::from_secs(3600)).await,
+            1.0
+        );
+        assert_eq!(response_cache_hits(std::time::Duration::ZERO).await, 0.0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn response_errors_are_labelled_by_whether_data_was_returned() {
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+            .finish();
+        let response = schema.execute("{ getJane { id } codedError }").await;
+        assert_eq!(response.data, Value::Null);
+        let response = schema.execute("{ getJane { id } optionalError }").await;
+        assert_ne!(response.data, Value::Null);
+        assert_eq!(response.errors.len(), 1);
+        schema.execute(JANE_QUERY).await;
+
+        let errors = metrics.named("graphql_response_errors");
+        let count = |partial: &str| {
+            errors
+                .iter()
+                .find(|metric| metric.labels["partial"] == partial)
+                .map(|metric| metric.sum)
+        };
+        assert_eq!(count("false"), Some(1.0));
+        assert_eq!(count("true"), Some(1.0));
+        assert_eq!(metrics.sum("graphql_response_errors"), 2.0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn each_registration_records_independently() {
+        let (collector, _guard) = collect_spans();
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(metrics.meter())
+                    .return_tracing_data_to_client(true),
+            )
+            .finish();
+        let response = schema.execute(JANE_QUERY).await;
+        assert!(response.errors.is_empty());
+
+        let tracing = serde_json::to_value(&response.extensions["tracing"]).unwrap();
+        let resolvers = tracing["execution"]["resolvers"].as_array().unwrap();
+        assert_eq!(resolvers.len(), 4);
+        let paths = resolvers
+            .iter()
+            .map(|resolver| resolver["path"].clone())
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(paths.len(), 4);
+
+        assert_eq!(metrics.sum("graphql_requests"), 2.0);
+        let spans = collector.spans();
+        assert_eq!(spans.spans_named("request").count(), 2);
+        assert_eq!(spans.spans_named("field").count(), 8);
+    }
+
+    #[tokio::test]
+    async fn spans_are_only_created_for_the_enabled_stages() {
+        let (collector, _guard) = collect_spans();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().stages(StageFlags::REQUEST | StageFlags::EXECUTE))
+            .finish();
+        let response = schema.execute(JANE_QUERY).await;
+        assert!(response.errors.is_empty());
+
+        let spans = collector.spans();
+        assert_eq!(spans.spans_named("request").count(), 1);
+        assert_eq!(spans.spans_named("execute").count(), 1);
+        for name in &["parse", "validation", "field"] {
+            assert_eq!(spans.spans_named(name).count(), 0, "{}", name);
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn anonymous_operations_are_counted() {
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+            .finish();
+        schema.execute("query GetJane { getJane { id } }").await;
+        schema.execute(JANE_QUERY).await;
+        schema.execute(r#"mutation { rename(name: "Jo") }"#).await;
+
+        let anonymous = metrics.named("graphql_anonymous_operations");
+        let count = |kind: &str| {
+            anonymous
+                .iter()
+                .find(|metric| metric.labels["operation_kind"] == kind)
+                .map(|metric| metric.sum)
+        };
+        assert_eq!(count("query"), Some(1.0));
+        assert_eq!(count("mutation"), Some(1.0));
+        assert_eq!(metrics.sum("graphql_anonymous_operations"), 2.0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn requests_which_overrun_their_deadline_are_counted() {
+        let (collector, _guard) = collect_spans();
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+            .finish();
+        let deadline = |after: std::time::Duration| Deadline(std::time::Instant::now() + after);
+        schema
+            .execute(
+                Request::new("{ slowError }").data(deadline(std::time::Duration::from_millis(5))),
+            )
+            .await;
+        schema
+            .execute(Request::new(JANE_QUERY).data(deadline(std::time::Duration::from_secs(3600))))
+            .await;
+        schema.execute(JANE_QUERY).await;
+
+        assert_eq!(metrics.sum("graphql_deadline_exceeded"), 1.0);
+        let spans = collector.spans();
+        let requests = spans.spans_named("request").collect::<Vec<_>>();
+        assert_eq!(requests[0].field("deadline_exceeded"), Some("true"));
+        assert_eq!(requests[1].field("deadline_exceeded"), Some("false"));
+        assert_eq!(requests[2].field("deadline_exceeded"), None);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn library_mode_records_with_the_provided_meter_and_dispatcher() {
+        let (default_spans, _guard) = collect_spans();
+        let library_spans = SpanCollector::default();
+        let metrics = MetricsCollector::default();
+        let ignored_metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry::library(
+                metrics.meter(),
+                tracing_subscriber::registry().with(library_spans.clone()),
+            ))
+            .data(OpenTelemetryConfig::default().meter(ignored_metrics.meter()))
+            .finish();
+        let response = schema.execute("{ getJane { id } optionalError }").await;
+        assert_eq!(response.errors.len(), 1);
+
+        let spans = library_spans.spans();
+        assert_eq!(spans.spans_named("request").count(), 1);
+        assert_eq!(spans.spans_named("field").count(), 3);
+        assert!(default_spans.spans().all().is_empty());
+        assert_eq!(metrics.sum("graphql_requests"), 1.0);
+        assert!(ignored_metrics.metrics().is_empty());
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn root_selection_width_is_recorded() {
+        let (collector, _guard) = collect_spans();
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(metrics.meter())
+                    .record_root_selection_width(true),
+            )
+            .finish();
+        let query = r#"
+            query Wide {
+                getJane { id }
+                people { name }
+                jane: getJane { id }
+                ... on QueryRoot { people { name } greet(name: "Jo", times: 1) }
+                ...Roots
+            }
+            query Narrow { getJane { id } }
+            fragment Roots on QueryRoot { optionalError }
+        "#;
+        schema
+            .execute(Request::new(query).operation_name("Wide"))
+            .await;
+        schema
+            .execute(Request::new(query).operation_name("Narrow"))
+            .await;
+
+        let spans = collector.spans();
+        let requests = spans.spans_named("request").collect::<Vec<_>>();
+        // `people` is selected twice but merged into one field, while `jane` is a field of its own
+        assert_eq!(requests[0].field("root_selection_width"), Some("5"));
+        assert_eq!(requests[1].field("root_selection_width"), Some("1"));
+        assert_eq!(metrics.sum("graphql_root_selection_width"), 6.0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn static_labels_are_attached_to_every_metric() {
+        // No other test reads these variables
+        std::env::set_var("DEPLOYMENT_ENV", "staging");
+        std::env::set_var("REGION", "eu-west-1");
+        std::env::remove_var("OTEL_SERVICE_NAME");
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .static_labels(vec![KeyValue::new("region", "us-east-1")])
+                    .unwrap()
+                    .labels_from_env()
+                    .unwrap()
+                    .meter(metrics.meter()),
+            )
+            .finish();
+        schema.execute("{ codedError }").await;
+
+        let collected = metrics.metrics();
+        assert!(!collected.is_empty());
+        for metric in collected {
+            assert_eq!(
+                metric.labels["deployment_env"], "staging",
+                "{}",
+                metric.name
+            );
+            assert_eq!(metric.labels["region"], "us-east-1", "{}", metric.name);
+            assert!(!metric.labels.contains_key("service_name"));
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn dataloader_stats_fed_in_by_the_host_are_recorded() {
+        struct BatchingQuery;
+
+        #[Object]
+        impl BatchingQuery {
+            // Stands in for a field resolved through a DataLoader, which batched its keys
+            async fn friends(&self, ctx: &Context<'_>) -> i32 {
+                ctx.data_unchecked::<DataLoaderStats>().record_batch(3);
+                3
+            }
+        }
+
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(BatchingQuery, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+            .finish();
+        schema
+            .execute(Request::new("{ friends alias: friends }").data(DataLoaderStats::default()))
+            .await;
+
+        assert_eq!(metrics.sum("graphql_dataloader_batches"), 2.0);
+        assert_eq!(metrics.sum("graphql_dataloader_loads"), 6.0);
+    }
+
+    #[tokio::test]
+    async fn fields_can_be_recorded_as_events_within_the_execute_span() {
+        let (collector, _guard) = collect_spans();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().field_representation(FieldRepresentation::Events))
+            .finish();
+        schema
+            .execute("{ getJane { id details { name } } optionalError }")
+            .await;
+
+        let spans = collector.spans();
+        assert_eq!(spans.spans_named("field").count(), 0);
+        let fields = spans
+            .events()
+            .iter()
+            .filter(|event| event.field("message") == Some("field"))
+            .collect::<Vec<_>>();
+        assert_eq!(fields.len(), 5);
+        for event in &fields {
+            assert_eq!(spans.event_span(event).unwrap().name, "execute");
+            assert!(event.field("duration").is_some());
+        }
+        let error = fields
+            .iter()
+            .find(|event| event.field("path") == Some("optionalError"))
+            .unwrap();
+        assert_eq!(error.field("parent_type"), Some("QueryRoot"));
+        assert_eq!(error.field("error"), Some("false"));
+        let name = fields
+            .iter()
+            .find(|event| event.field("path") == Some("getJane.details.name"))
+            .unwrap();
+        assert_eq!(name.field("return_type"), Some("String!"));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn business_metrics_accumulated_by_resolvers_are_recorded() {
+        struct Shop;
+
+        #[Object]
+        impl Shop {
+            async fn order(&self, ctx: &Context<'_>, value: f64) -> f64 {
+                let metrics = ctx.data_unchecked::<BusinessMetrics>();
+                metrics.add("order_value", value);
+                metrics.add("untracked", 1.0);
+                value
+            }
+        }
+
+        let collector = MetricsCollector::default();
+        let config = OpenTelemetryConfig::default()
+            .meter(collector.meter())
+            .business_metrics(vec![
+                BusinessMetricSpec::value_recorder("order_value", "shop_order_value"),
+                BusinessMetricSpec::counter("items_sold", "shop_items_sold"),
+            ])
+            .unwrap();
+        let schema = Schema::build(Shop, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(config)
+            .finish();
+        for _ in 0..2 {
+            let request = Request::new("{ a: order(value: 2.5) b: order(value: 4) }")
+                .data(BusinessMetrics::default());
+            assert!(schema.execute(request).await.is_ok());
+        }
+
+        let recorded = collector.named("shop_order_value");
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].sum, 13.0);
+        assert_eq!(recorded[0].count, Some(2));
+        assert_eq!(recorded[0].labels["operation_kind"], "query");
+        assert!(collector.named("shop_items_sold").is_empty());
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn sampled_requests_are_counted_alongside_the_total() {
+        async fn sampled_and_total(config: OpenTelemetryConfig) -> (f64, f64) {
+            let metrics = MetricsCollector::default();
+            let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+                .extension(OpenTelemetry)
+                .data(config.meter(metrics.meter()))
+                .finish();
+            schema.execute(JANE_QUERY).await;
+            schema.execute(JANE_QUERY).await;
+            (
+                metrics.sum("graphql_traces_sampled"),
+                metrics.sum("graphql_requests"),
+            )
+        }
+
+        let config = OpenTelemetryConfig::default();
+        assert_eq!(sampled_and_total(config).await, (2.0, 2.0));
+        let config =
+            OpenTelemetryConfig::default().tail_sampling_threshold(std::time::Duration::ZERO);
+        assert_eq!(sampled_and_total(config).await, (2.0, 2.0));
+        let config = OpenTelemetryConfig::default()
+            .tail_sampling_threshold(std::time::Duration::from_secs(3600));
+        assert_eq!(sampled_and_total(config).await, (0.0, 2.0));
+        let config = OpenTelemetryConfig::default().stages(StageFlags::all() - StageFlags::RESOLVE);
+        assert_eq!(sampled_and_total(config).await, (0.0, 2.0));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn labels_are_recorded_under_their_configured_names() {
+        let metrics = MetricsCollector::default();
+        let config = OpenTelemetryConfig::default()
+            .meter(metrics.meter())
+            .static_labels(vec![KeyValue::new("region", "us-east-1")])
+            .unwrap()
+            .label_name("query_name", "graphql_operation")
+            .unwrap()
+            .label_name("region", "cloud.region")
+            .unwrap();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(config)
+            .finish();
+        schema.execute(JANE_QUERY).await;
+
+        let durations = metrics.named("graphql_request_duration");
+        assert!(durations.iter().all(|metric| {
+            metric.labels["cloud.region"] == "us-east-1"
+                && !metric.labels.contains_key("region")
+                && !metric.labels.contains_key("query_name")
+        }));
+        assert!(durations
+            .iter()
+            .any(|metric| metric.labels["graphql_operation"] == "getJane"));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn static_labels_cant_take_the_name_of_a_renamed_label() {
+        let renamed = || OpenTelemetryConfig::default().label_name("region", "zone");
+        let labels = || {
+            vec![
+                KeyValue::new("region", "us-east-1"),
+                KeyValue::new("zone", "us-east-1a"),
+            ]
+        };
+        let duplicate = ConfigError::DuplicateLabelName("zone".to_string());
+        // Caught whichever of the two is set first
+        assert_eq!(
+            renamed().unwrap().static_labels(labels()).err(),
+            Some(duplicate.clone())
+        );
+        let config = OpenTelemetryConfig::default()
+            .static_labels(labels())
+            .unwrap();
+        assert_eq!(config.label_name("region", "zone").err(), Some(duplicate));
+    }
+
+    #[tokio::test]
+    async fn field_spans_record_the_order_they_started_resolving_in() {
+        let (collector, _guard) = collect_spans();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .finish();
+        schema.execute(JANE_QUERY).await;
+
+        let spans = collector.spans();
+        let seq = |path: &str| -> u64 {
+            let span = spans.field(path).unwrap();
+            span.field("resolve_seq").unwrap().parse().unwrap()
+        };
+        let mut all = spans
+            .spans_named("field")
+            .map(|span| span.field("resolve_seq").unwrap().parse::<u64>().unwrap())
+            .collect::<Vec<_>>();
+        all.sort_unstable();
+        assert_eq!(all, vec![0, 1, 2, 3]);
+        assert_eq!(seq("getJane"), 0);
+        assert!(seq("getJane.details") < seq("getJane.details.name"));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn incremental_delivery_is_rejected_as_a_single_request() {
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+            .finish();
+        let resp = schema
+            .execute("{ getJane { id ... @defer { details { name } } } }")
+            .await;
+
+        assert!(resp.errors[0].message.contains("defer"));
+        assert_eq!(metrics.sum("graphql_requests"), 1.0);
+        assert_eq!(metrics.sum("graphql_response_errors"), 0.0);
+    }
+
+    #[tokio::test]
+    async fn only_the_spans_of_slow_fields_are_emitted() {
+        let (collector, _guard) = collect_spans();
+        let config = OpenTelemetryConfig::default()
+            .field_span_min_duration(std::time::Duration::from_millis(10));
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(config)
+            .finish();
+        schema.execute("{ blocking getJane { id } }").await;
+
+        let spans = collector.spans();
+        let fields = spans.spans_named("field").collect::<Vec<_>>();
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].field("path"), Some("blocking"));
+        let duration: i64 = fields[0].field("duration").unwrap().parse().unwrap();
+        assert!(duration >= 10_000_000);
+        assert!(spans.is_descendant_of(fields[0], spans.span("execute").unwrap()));
+    }
+
+    #[tokio::test]
+    async fn tracing_data_can_be_logged_as_json() {
+        let (collector, _guard) = collect_spans();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().log_tracing_json(true))
+            .finish();
+        let resp = schema.execute(JANE_QUERY).await;
+        assert!(!resp.extensions.contains_key("tracing"));
+
+        let spans = collector.spans();
+        let summary = spans
+            .events()
+            .iter()
+            .find(|event| event.field("message") == Some("tracing summary"))
+            .unwrap();
+        assert_eq!(spans.event_span(summary).unwrap().name, "request");
+        let json: serde_json::Value =
+            serde_json::from_str(summary.field("tracing").unwrap()).unwrap();
+        assert_eq!(json["version"], 1);
+        assert_eq!(json["execution"]["resolvers"].as_array().unwrap().len(), 4);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn repeated_error_logs_are_limited_but_still_counted() {
+        struct Root;
+
+        #[Object]
+        impl Root {
+            async fn items(&self) -> Vec<Option<Item>> {
+                (0..10).map(|_| Some(Item)).collect()
+            }
+        }
+
+        struct Item;
+
+        #[Object]
+        impl Item {
+            async fn broken(&self) -> Result<i32> {
+                Err("upstream unavailable".into())
+            }
+        }
+
+        let (collector, _guard) = collect_spans();
+        let metrics = MetricsCollector::default();
+        let config = OpenTelemetryConfig::default()
+            .meter(metrics.meter())
+            .max_error_logs_per_request(3);
+        let schema = Schema::build(Root, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(config)
+            .finish();
+        schema.execute("{ items { broken } }").await;
+
+        let spans = collector.spans();
+        let errors = spans
+            .events()
+            .iter()
+            .filter(|event| event.level == Level::ERROR)
+            .count();
+        assert_eq!(errors, 3);
+        let summary = spans
+            .events()
+            .iter()
+            .find(|event| event.field("message") == Some("suppressed repeated field errors"))
+            .unwrap();
+        assert_eq!(summary.field("error"), Some("upstream unavailable"));
+        assert_eq!(summary.field("suppressed"), Some("7"));
+        assert_eq!(metrics.sum("graphql_field_errors"), 10.0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn requests_are_tagged_with_their_transport() {
+        let (collector, _guard) = collect_spans();
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+            .finish();
+        schema
+            .execute(Request::new(JANE_QUERY).data(Transport::WebSocket))
+            .await;
+        schema.execute(JANE_QUERY).await;
+
+        let spans = collector.spans();
+        let transports = spans
+            .spans_named("request")
+            .map(|span| span.field("transport"))
+            .collect::<Vec<_>>();
+        assert_eq!(transports, vec![Some("websocket"), Some("unknown")]);
+        let requests = metrics.named("graphql_requests");
+        let count = |transport: &str| {
+            requests
+                .iter()
+                .filter(|metric| metric.labels["transport"] == transport)
+                .map(|metric| metric.sum)
+                .sum::<f64>()
+        };
+        assert_eq!(count("websocket"), 1.0);
+        assert_eq!(count("unknown"), 1.0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn an_extension_instance_reused_between_requests_records_each_independently() {
+        let metrics = MetricsCollector::default();
+        let config = OpenTelemetryConfig::default()
+            .meter(metrics.meter())
+            .return_tracing_data_to_client(true)
+            .record_resolve_depth(true);
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(Shared::default())
+            .data(config)
+            .finish();
+
+        let resolvers = |resp: &Response| match &resp.extensions["tracing"] {
+            Value::Object(tracing) => match &tracing["execution"] {
+                Value::Object(execution) => match &execution["resolvers"] {
+                    Value::List(resolvers) => resolvers.len(),
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        let first = schema.execute(JANE_QUERY).await;
+        let second = schema.execute("{ getJane { id } }").await;
+        assert_eq!(resolvers(&first), 4);
+        assert_eq!(resolvers(&second), 2);
+
+        let depths = metrics.named("graphql_max_resolve_depth");
+        assert_eq!(depths[0].count, Some(2));
+        assert_eq!(depths[0].sum, 3.0 + 2.0);
+        assert_eq!(metrics.sum("graphql_requests"), 2.0);
+
+        // Each request is recorded with its own tenant's meter, or the config's without one
+        let tenants = [MetricsCollector::default(), MetricsCollector::default()];
+        for tenant in &tenants {
+            schema
+                .execute(Request::new(JANE_QUERY).data(TenantMeter(tenant.meter())))
+                .await;
+        }
+        schema.execute(JANE_QUERY).await;
+        assert_eq!(tenants[0].sum("graphql_requests"), 1.0);
+        assert_eq!(tenants[1].sum("graphql_requests"), 1.0);
+        assert_eq!(metrics.sum("graphql_requests"), 3.0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn metrics_can_be_exported_as_deltas() {
+        let metrics = MetricsCollector::with_temporality(Temporality::Delta);
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+            .finish();
+
+        schema.execute(JANE_QUERY).await;
+        schema.execute(JANE_QUERY).await;
+        assert_eq!(metrics.sum("graphql_requests"), 2.0);
+        schema.execute(JANE_QUERY).await;
+        assert_eq!(metrics.sum("graphql_requests"), 1.0);
+        assert_eq!(metrics.sum("graphql_requests"), 0.0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn variable_coercion_errors_are_counted_by_variable() {
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(metrics.meter())
+                    .record_variable_validation(true),
+            )
+            .finish();
+        let request = Request::new("query($n: String!, $t: Int!) { greet(name: $n, times: $t) }")
+            .variables(Variables::from_json(serde_json::json!({ "n": 5, "t": 1 })));
+        assert!(schema.execute(request).await.is_err());
+        // An invalid literal isn't a variable's error
+        assert!(schema
+            .execute(r#"{ greet(name: "jane", times: "once") }"#)
+            .await
+            .is_err());
+
+        let validated = &metrics.named("graphql_variables_validated")[0];
+        assert_eq!(validated.count, Some(2));
+        assert_eq!(validated.sum, 2.0);
+        let errors = metrics.named("graphql_variable_coercion_errors");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].labels["variable"], "n");
+        assert_eq!(errors[0].sum, 1.0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn a_dry_run_logs_the_spans_and_metrics_instead_of_producing_them() {
+        let (collector, _guard) = collect_spans();
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(metrics.meter())
+                    .static_labels(vec![KeyValue::new("service", "users")])
+                    .unwrap()
+                    .dry_run(true),
+            )
+            .finish();
+        schema.execute(JANE_QUERY).await;
+
+        assert!(metrics.metrics().is_empty());
+        let spans = collector.spans();
+        assert!(spans.all().is_empty());
+        let logged = |key: &str, value: &str| {
+            spans
+                .events()
+                .iter()
+                .find(|event| event.field(key) == Some(value))
+                .cloned()
+        };
+        assert!(logged("span", "request").is_some());
+        assert!(logged("span", "field").is_some());
+        let requests = logged("metric", "graphql_requests").unwrap();
+        assert_eq!(requests.level, Level::DEBUG);
+        assert!(requests.field("labels").unwrap().contains("service"));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn the_query_signature_is_recorded_on_the_request() {
+        let (collector, _guard) = collect_spans();
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(metrics.meter())
+                    .record_query_signature(true),
+            )
+            .finish();
+        schema
+            .execute(
+                Request::new("query A { a: getJane { id } } query B { getJohn { id } }")
+                    .operation_name("B"),
+            )
+            .await;
+        schema.execute("query B { john: getJohn { id } }").await;
+
+        let spans = collector.spans();
+        assert_eq!(
+            spans.span("request").unwrap().field("query_signature"),
+            Some("query B{getJohn{id}}")
+        );
+        let requests = metrics.named("graphql_requests");
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].labels["query_signature"],
+            "query B{getJohn{id}}"
+        );
+        assert_eq!(requests[0].sum, 2.0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn the_self_duration_of_a_field_excludes_its_children() {
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(metrics.meter())
+                    .record_field_self_duration(true),
+            )
+            .finish();
+        schema.execute("{ slowPerson { name } }").await;
+
+        let durations = metrics.named("graphql_field_self_duration");
+        let self_duration = |field_name: &str| {
+            durations
+                .iter()
+                .find(|metric| metric.labels["field_name"] == field_name)
+                .unwrap()
+                .sum
+        };
+        assert_eq!(durations.len(), 2);
+        assert!(self_duration("name") >= 20_000.0);
+        assert!(self_duration("slowPerson") < 20_000.0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn batched_field_metrics_match_the_immediately_recorded_ones() {
+        let record = |batch: bool| async move {
+            let metrics = MetricsCollector::default();
+            let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+                .extension(OpenTelemetry)
+                .data(
+                    OpenTelemetryConfig::default()
+                        .meter(metrics.meter())
+                        .record_type_resolve_duration(true)
+                        .record_list_sizes(true)
+                        .batch_field_metrics(batch),
+                )
+                .finish();
+            schema
+                .execute("{ codedError people { name } getJane { id } }")
+                .await;
+            let mut recorded = metrics
+                .metrics()
+                .into_iter()
+                .map(|metric| {
+                    let mut labels = metric.labels.into_iter().collect::<Vec<_>>();
+                    labels.sort();
+                    // The durations vary between runs, but not how many of them there are
+                    let timing = metric.name.contains("duration") || metric.name.contains("time");
+                    let sum = match timing {
+                        true => 0,
+                        false => metric.sum as u64,
+                    };
+                    (metric.name, labels, sum, metric.count)
+                })
+                .collect::<Vec<_>>();
+            recorded.sort();
+            recorded
+        };
+        let immediate = record(false).await;
+        let batched = record(true).await;
+        assert!(immediate
+            .iter()
+            .any(|(name, ..)| name == "graphql_field_errors"));
+        assert_eq!(batched, immediate);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn the_complexity_estimate_is_compared_with_the_resolved_fields() {
+        let (collector, _guard) = collect_spans();
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(metrics.meter())
+                    .record_complexity_estimate_error(true),
+            )
+            .limit_complexity(100)
+            .finish();
+        // The list is estimated as a single field, but it resolves a `name` for each person
+        schema.execute("{ people { name } }").await;
+
+        let spans = collector.spans();
+        let execute = spans.span("execute").unwrap();
+        assert_eq!(execute.field("estimated_complexity"), Some("2"));
+        assert_eq!(execute.field("resolved_fields"), Some("3"));
+        let ratios = metrics.named("graphql_complexity_estimate_error");
+        assert_eq!(ratios[0].count, Some(1));
+        assert_eq!(ratios[0].sum, 1.5);
+    }
+
+    #[tokio::test]
+    async fn the_request_id_is_recorded_on_every_span() {
+        let (collector, _guard) = collect_spans();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .finish();
+        schema
+            .execute(Request::new(JANE_QUERY).data(RequestId("req-42".to_string())))
+            .await;
+
+        let spans = collector.spans();
+        assert_eq!(spans.all().len(), 8);
+        for span in spans.all() {
+            assert_eq!(span.field("request_id"), Some("req-42"), "{}", span.name);
+        }
+
+        let (collector, _guard) = collect_spans();
+        schema.execute(JANE_QUERY).await;
+        let spans = collector.spans();
+        assert!(spans
+            .all()
+            .iter()
+            .all(|span| span.field("request_id").is_none()));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn websocket_connections_are_counted_until_they_close() {
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+            .finish();
+        let connection = WsConnection::default();
+        // Reported before the extension has seen the connection, e.g. the `connection_init`
+        connection.message();
+        for _ in 0..2 {
+            schema
+                .execute(Request::new(JANE_QUERY).data(connection.clone()))
+                .await;
+        }
+
+        assert_eq!(metrics.sum("graphql_ws_messages"), 3.0);
+        assert_eq!(metrics.sum("graphql_ws_connections_active"), 1.0);
+        drop(connection);
+        assert_eq!(metrics.sum("graphql_ws_connections_active"), 0.0);
+    }
+
+    #[tokio::test]
+    async fn the_nullability_of_the_return_type_is_recorded_on_the_field_spans() {
+        let (collector, _guard) = collect_spans();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().record_field_nullability(true))
+            .finish();
+        schema
+            .execute("{ optionalError people { name } getJane { id } }")
+            .await;
+
+        let spans = collector.spans();
+        let nullable = |path: &str| spans.field(path).unwrap().field("nullable");
+        assert_eq!(nullable("optionalError"), Some("true"));
+        assert_eq!(nullable("getJane"), Some("false"));
+        assert_eq!(nullable("getJane.id"), Some("false"));
+        assert_eq!(nullable("people"), Some("false"));
+        // Only the outermost type decides, whatever the nullability of a list's elements
+        assert!(is_nullable("[Person!]"));
+        assert!(!is_nullable("[Person]!"));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn durations_are_measured_with_the_configured_clock() {
+        let metrics = MetricsCollector::default();
+        let clock = testing::MockClock::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(metrics.meter())
+                    .clock(Arc::new(clock.clone())),
+            )
+            .data(clock)
+            .finish();
+        schema.execute("{ tick }").await;
+        schema.execute("{ getJane { id } }").await;
+
+        let durations = metrics.named("graphql_request_duration");
+        let duration = |path: &str| {
+            durations
+                .iter()
+                .find(|metric| metric.labels["query_name"] == path)
+                .unwrap()
+                .sum
+        };
+        assert_eq!(duration("tick"), 250.0);
+        assert_eq!(duration("getJane.id"), 0.0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn requests_failing_before_execution_are_counted_as_unresolved() {
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+            .finish();
+
+        schema.execute("{ getJane { id } }").await;
+        assert_eq!(metrics.sum("graphql_operations_unresolved"), 0.0);
+
+        let response = schema.execute("{ getJane { id ").await;
+        assert!(!response.errors.is_empty());
+        assert_eq!(metrics.sum("graphql_operations_unresolved"), 1.0);
+
+        let response = schema.execute("{ getJane { unknown } }").await;
+        assert!(!response.errors.is_empty());
+        assert_eq!(metrics.sum("graphql_operations_unresolved"), 2.0);
+        assert_eq!(metrics.sum("graphql_requests"), 3.0);
+    }
+
+    #[tokio::test]
+    async fn subscriptions_are_linked_to_the_provided_spans() {
+        use opentelemetry::trace::TracerProvider as _;
+
+        let exporter = testing::SpanExportCollector::default();
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.get_tracer("test", None)));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let source = tracing::info_span!("source");
+        let schema = Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot)
+            .extension(OpenTelemetry)
+            .finish();
+        let request = Request::new("subscription { count }").data(SpanLinks(vec![source.clone()]));
+        let responses = schema.execute_stream(request).collect::<Vec<_>>().await;
+        assert_eq!(responses.len(), 3);
+        drop(source);
+
+        let spans = exporter.spans();
+        let span_id = |name: &str| {
+            spans
+                .iter()
+                .find(|span| span.name == name)
+                .unwrap()
+                .span_context
+                .span_id()
+        };
+        let subscribe = spans.iter().find(|span| span.name == "subscribe").unwrap();
+        let links = subscribe
+            .links
+            .iter()
+            .map(|link| link.span_context().span_id())
+            .collect::<Vec<_>>();
+        assert_eq!(links, vec![span_id("source")]);
+        assert_eq!(
+            subscribe.parent_span_id,
+            opentelemetry::trace::SpanId::invalid()
+        );
+    }
+
+    #[tokio::test]
+    async fn tracing_summaries_are_submitted_to_the_configured_sinks() {
+        #[derive(Debug, Default)]
+        struct Collect(Mutex<Vec<TracingSummary>>);
+
+        impl TracingSink for Collect {
+            fn submit(&self, summary: TracingSummary) {
+                if summary.apollo_trace().root.child.len() > 1 {
+                    summary.return_to_client();
+                }
+                self.0.lock().push(summary);
+            }
+        }
+
+        let sink = Arc::new(Collect::default());
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().tracing_sink(sink.clone()))
+            .finish();
+        let response = schema.execute("{ getJane { id } }").await;
+        assert!(!response.extensions.contains_key("tracing"));
+        let response = schema.execute("{ getJane { id } people { name } }").await;
+        let tracing = serde_json::to_value(&response.extensions["tracing"]).unwrap();
+
+        let summaries = sink.0.lock();
+        assert_eq!(summaries.len(), 2);
+        assert!(summaries[1].start_time() <= summaries[1].end_time());
+        assert_eq!(serde_json::to_value(&summaries[1]).unwrap(), tracing);
+        assert_eq!(
+            tracing["execution"]["resolvers"].as_array().unwrap().len(),
+            7
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn resolver_retries_fed_in_by_the_host_are_recorded() {
+        struct RetryingQuery;
+
+        #[Object]
+        impl RetryingQuery {
+            // Stands in for a resolver which succeeded on its third attempt
+            async fn flaky(&self, ctx: &Context<'_>) -> i32 {
+                ctx.data_unchecked::<RetryStats>().record_attempts(3);
+                1
+            }
+
+            async fn retried(&self, ctx: &Context<'_>) -> i32 {
+                ctx.data_unchecked::<RetryStats>().record_retry();
+                1
+            }
+        }
+
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(RetryingQuery, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+            .finish();
+        schema
+            .execute(Request::new("{ flaky retried }").data(RetryStats::default()))
+            .await;
+        schema
+            .execute(Request::new("{ flaky }").data(RetryStats::default()))
+            .await;
+
+        assert_eq!(metrics.sum("graphql_resolver_retries"), 5.0);
+    }
+
+    #[tokio::test]
+    async fn every_input_from_the_host_is_read_as_the_request_is_prepared() {
+        use opentelemetry::trace::{SpanContext, SpanId, TraceId, TraceState, TracerProvider as _};
+
+        #[derive(Clone, Default)]
+        struct Probe(Arc<Mutex<Option<HostContext>>>);
+
+        impl ExtensionFactory for Probe {
+            fn create(&self) -> Arc<dyn Extension> {
+                Arc::new(self.clone())
+            }
+        }
+
+        #[async_trait::async_trait]
+        impl Extension for Probe {
+            async fn prepare_request(
+                &self,
+                ctx: &ExtensionContext<'_>,
+                request: Request,
+                next: NextPrepareRequest<'_>,
+            ) -> ServerResult<Request> {
+                *self.0.lock() = Some(HostContext::from_context(ctx));
+                next.run(ctx, request).await
+            }
+        }
+
+        let exporter = testing::SpanExportCollector::default();
+        let provider = opentelemetry::sdk::trace::TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.get_tracer("test", None)));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let probe = Probe::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .extension(probe.clone())
+            .finish();
+        let now = std::time::Instant::now();
+        let parent = opentelemetry::Context::new().with_remote_span_context(SpanContext::new(
+            TraceId::from_u128(7),
+            SpanId::from_u64(9),
+            opentelemetry::trace::TRACE_FLAG_SAMPLED,
+            true,
+            TraceState::default(),
+        ));
+        let source = tracing::info_span!("source");
+        let request = Request::new("{ getJane { id } }")
+            .data(ReceivedAt(now))
+            .data(ClientInfo {
+                name: "ios".to_string(),
+                version: "4.2.0".to_string(),
+            })
+            .data(Transport::WebSocket)
+            .data(HttpProtocol("HTTP/2.0".to_string()))
+            .data(SchemaVersion("2021-05-04.1".to_string()))
+            .data(Deadline(now + std::time::Duration::from_secs(60)))
+            .data(RequestId("req-1".to_string()))
+            .data(SpanLinks(vec![source.clone()]))
+            .data(TraceParent(parent));
+        #[cfg(feature = "metrics")]
+        let request = request
+            .data(DataLoaderStats::default())
+            .data(RetryStats::default())
+            .data(WsConnection::default())
+            .data(BusinessMetrics::default());
+        let resp = schema.execute(request).await;
+        assert!(resp.errors.is_empty(), "{:?}", resp.errors);
+
+        let host = probe.0.lock().take().unwrap();
+        assert_eq!(host.received_at, Some(now));
+        assert_eq!(host.client.unwrap().name, "ios");
+        assert_eq!(host.transport, Some(Transport::WebSocket));
+        assert_eq!(
+            host.http_protocol,
+            Some(HttpProtocol("HTTP/2.0".to_string()))
+        );
+        assert_eq!(
+            host.schema_version,
+            Some(SchemaVersion("2021-05-04.1".to_string()))
+        );
+        assert!(host.deadline.is_some());
+        assert_eq!(host.request_id, Some(RequestId("req-1".to_string())));
+        assert_eq!(host.span_links.len(), 1);
+        assert!(host.trace_parent.is_some());
+        #[cfg(feature = "metrics")]
+        {
+            assert!(host.dataloader_stats.is_some());
+            assert!(host.retry_stats.is_some());
+            assert!(host.ws_connection.is_some());
+            assert!(host.business_metrics.is_some());
+        }
+
+        // The request span is parented on the propagated context, rather than starting a trace
+        drop(source);
+        let spans = exporter.spans();
+        let request = spans.iter().find(|span| span.name == "request").unwrap();
+        assert_eq!(request.span_context.trace_id(), TraceId::from_u128(7));
+        assert_eq!(request.parent_span_id, SpanId::from_u64(9));
+        assert_eq!(
+            request
+                .attributes
+                .get(&opentelemetry::Key::new("request_id"))
+                .map(|value| value.as_str().into_owned()),
+            Some("req-1".to_string())
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn authorization_rejections_are_counted_by_field() {
+        use async_graphql::guard::Guard;
+
+        struct Deny;
+
+        #[async_trait::async_trait]
+        impl Guard for Deny {
+            async fn check(&self, _ctx: &Context<'_>) -> Result<()> {
+                Err(Error::new("denied").extend_with(|_, e| e.set("code", "UNAUTHENTICATED")))
+            }
+        }
+
+        struct Root;
+
+        #[Object]
+        impl Root {
+            #[graphql(guard(Deny()))]
+            async fn secret(&self) -> i32 {
+                1
+            }
+
+            async fn forbidden(&self) -> Result<i32> {
+                Err(Error::new("forbidden").extend_with(|_, e| e.set("code", "FORBIDDEN")))
+            }
+
+            async fn broken(&self) -> Result<i32> {
+                Err("boom".into())
+            }
+        }
+
+        let rejections = |config: OpenTelemetryConfig| async move {
+            let metrics = MetricsCollector::default();
+            let schema = Schema::build(Root, EmptyMutation, EmptySubscription)
+                .extension(OpenTelemetry)
+                .data(config.meter(metrics.meter()))
+                .finish();
+            schema.execute("{ secret }").await;
+            schema.execute("{ forbidden }").await;
+            schema.execute("{ broken }").await;
+            assert_eq!(metrics.sum("graphql_field_errors"), 3.0);
+            let mut fields = metrics
+                .named("graphql_authz_rejections")
+                .into_iter()
+                .map(|metric| (metric.labels["field_name"].clone(), metric.sum))
+                .collect::<Vec<_>>();
+            fields.sort_by(|a, b| a.0.cmp(&b.0));
+            fields
+        };
+        assert_eq!(
+            rejections(OpenTelemetryConfig::default()).await,
+            vec![("forbidden".to_string(), 1.0), ("secret".to_string(), 1.0)]
+        );
+        assert_eq!(
+            rejections(OpenTelemetryConfig::default().authz_error_codes(["FORBIDDEN"])).await,
+            vec![("forbidden".to_string(), 1.0)]
+        );
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[tokio::test]
+    async fn core_metrics_can_be_recorded_into_a_prometheus_registry() {
+        let registry = prometheus::Registry::new();
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(metrics.meter())
+                    .prometheus_registry(registry.clone()),
+            )
+            .finish();
+        schema.execute("{ getJane { id } }").await;
+        schema
+            .execute(Request::new("{ codedError }").data(Transport::Http))
+            .await;
+
+        let families = registry.gather();
+        let family = |name: &str| families.iter().find(|family| family.get_name() == name);
+        let requests = family("graphql_requests").unwrap();
+        let transports = requests
+            .get_metric()
+            .iter()
+            .map(|metric| {
+                let transport = metric
+                    .get_label()
+                    .iter()
+                    .find(|label| label.get_name() == "transport")
+                    .unwrap();
+                (
+                    transport.get_value().to_string(),
+                    metric.get_counter().get_value(),
+                )
+            })
+            .collect::<std::collections::BTreeMap<_, _>>();
+        assert_eq!(transports["http"], 1.0);
+        assert_eq!(transports["unknown"], 1.0);
+        assert!(family("graphql_request_errors").is_some());
+        assert!(family("graphql_field_errors").is_some());
+        let durations = family("graphql_request_duration").unwrap();
+        let observed = durations
+            .get_metric()
+            .iter()
+            .map(|metric| metric.get_histogram().get_sample_count())
+            .sum::<u64>();
+        assert_eq!(observed, 3);
+
+        // The rest are still recorded with the meter, but the four are only in the registry
+        assert!(metrics.named("graphql_requests").is_empty());
+        assert!(metrics.named("graphql_request_duration").is_empty());
+        assert!(!metrics.named("graphql_time_to_first_response").is_empty());
+
+        // A second registration of the same names falls back to the meter
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(metrics.meter())
+                    .prometheus_registry(registry),
+            )
+            .finish();
+        schema.execute("{ getJane { id } }").await;
+        assert_eq!(metrics.sum("graphql_requests"), 1.0);
+    }
+
+    #[tokio::test]
+    async fn field_spans_record_where_the_field_is_selected() {
+        let query = "query People {
+  people {
+    ...Named
+  }
+  jane: getJane { id }
+}
+fragment Named on SubQuery { name }";
+        let location = |config: OpenTelemetryConfig, buffered: bool| async move {
+            let (collector, _guard) = collect_spans();
+            let config = match buffered {
+                true => config.field_span_min_duration(std::time::Duration::ZERO),
+                false => config,
+            };
+            let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+                .extension(OpenTelemetry)
+                .data(config)
+                .finish();
+            let resp = schema.execute(query).await;
+            assert!(resp.errors.is_empty(), "{:?}", resp.errors);
+            let spans = collector.spans();
+            ["people", "people.1", "people.1.name", "jane", "jane.id"]
+                .iter()
+                .map(|path| {
+                    spans
+                        .field(path)
+                        .and_then(|span| span.field("location"))
+                        .map(str::to_string)
+                })
+                .collect::<Vec<_>>()
+        };
+        let expected = ["2:3", "2:3", "7:30", "5:3", "5:19"]
+            .iter()
+            .map(|location| Some(location.to_string()))
+            .collect::<Vec<_>>();
+        let config = || OpenTelemetryConfig::default().record_field_location(true);
+        assert_eq!(location(config(), false).await, expected);
+        assert_eq!(location(config(), true).await, expected);
+        assert_eq!(
+            location(OpenTelemetryConfig::default(), false).await,
+            vec![None; 5]
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn instrumentation_is_cut_back_once_it_exceeds_its_budget() {
+        let instrumented = |budget: std::time::Duration| async move {
+            let (collector, _guard) = collect_spans();
+            let metrics = MetricsCollector::default();
+            let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+                .extension(OpenTelemetry)
+                .data(
+                    OpenTelemetryConfig::default()
+                        .meter(metrics.meter())
+                        .instrumentation_budget(budget),
+                )
+                .finish();
+            let resp = schema.execute("{ people { name } }").await;
+            assert!(resp.errors.is_empty(), "{:?}", resp.errors);
+            let spans = collector.spans();
+            let fields = ["people", "people.0.name", "people.1.name"]
+                .iter()
+                .filter(|path| spans.field(path).is_some())
+                .count();
+            (
+                fields,
+                metrics.sum("graphql_requests"),
+                metrics.sum("graphql_instrumentation_degraded"),
+            )
+        };
+        assert_eq!(
+            instrumented(std::time::Duration::from_secs(60)).await,
+            (3, 1.0, 0.0)
+        );
+        // Any bookkeeping at all exceeds the budget, so only the first field is instrumented
+        assert_eq!(instrumented(std::time::Duration::ZERO).await, (1, 1.0, 1.0));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn the_advertised_cache_ttl_is_recorded() {
+        struct Root;
+
+        #[Object]
+        impl Root {
+            #[graphql(cache_control(max_age = 60))]
+            async fn hourly(&self) -> i32 {
+                1
+            }
+
+            #[graphql(cache_control(max_age = 30))]
+            async fn half_hourly(&self) -> i32 {
+                2
+            }
+
+            async fn uncached(&self) -> i32 {
+                3
+            }
+        }
+
+        let (collector, _guard) = collect_spans();
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(Root, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+            .finish();
+        schema.execute("{ hourly halfHourly }").await;
+        schema.execute("{ uncached }").await;
+
+        let spans = collector.spans();
+        let ttls = spans
+            .spans_named("request")
+            .map(|span| span.field("cache_ttl_seconds"))
+            .collect::<Vec<_>>();
+        assert_eq!(ttls, vec![Some("30"), None]);
+        let recorded = metrics.named("graphql_cache_ttl");
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].labels["operation_kind"], "query");
+        assert_eq!((recorded[0].sum, recorded[0].count), (30.0, Some(1)));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn errors_are_labelled_by_their_severity() {
+        let severities = |config: OpenTelemetryConfig| async move {
+            let metrics = MetricsCollector::default();
+            let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+                .extension(OpenTelemetry)
+                .data(config.meter(metrics.meter()))
+                .finish();
+            schema.execute("{ codedError }").await;
+            schema.execute("{ optionalError slowError }").await;
+            ["graphql_request_errors", "graphql_field_errors"].map(|name| {
+                let mut severities = metrics
+                    .named(name)
+                    .into_iter()
+                    .map(|metric| (metric.labels["severity"].clone(), metric.sum))
+                    .collect::<Vec<_>>();
+                severities.sort_by(|a, b| a.0.cmp(&b.0));
+                severities
+            })
+        };
+        let default = vec![("error".to_string(), 1.0), ("warning".to_string(), 1.0)];
+        assert_eq!(
+            severities(OpenTelemetryConfig::default()).await,
+            [default.clone(), default]
+        );
+        let custom =
+            OpenTelemetryConfig::default().error_severity(Arc::new(|err: &ServerError| {
+                if err.message == "forbidden" {
+                    Severity::Info
+                } else {
+                    Severity::from_error(err)
+                }
+            }));
+        let custom_severities = vec![("error".to_string(), 1.0), ("info".to_string(), 1.0)];
+        assert_eq!(
+            severities(custom).await,
+            [custom_severities.clone(), custom_severities]
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn slow_requests_are_counted_and_list_their_slowest_fields() {
+        let (collector, _guard) = collect_spans();
+        let metrics = MetricsCollector::default();
+        let clock = testing::MockClock::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(metrics.meter())
+                    .clock(Arc::new(clock.clone()))
+                    .slow_request_threshold(std::time::Duration::from_millis(100)),
+            )
+            .data(clock)
+            .finish();
+        schema.execute("query Slow { getJane { id } tick }").await;
+        schema.execute("query Fast { getJane { id } }").await;
+
+        let slow = metrics.named("graphql_slow_requests");
+        assert_eq!(slow.len(), 1);
+        assert_eq!(slow[0].labels["operation_kind"], "query");
+        assert_eq!(slow[0].labels["query_name"], "Slow");
+        assert_eq!(slow[0].sum, 1.0);
+
+        let spans = collector.spans();
+        let warnings = spans
+            .events()
+            .iter()
+            .filter(|event| event.level == Level::WARN)
+            .collect::<Vec<_>>();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].field("operation_name"), Some("Slow"));
+        assert_eq!(warnings[0].field("duration_ms"), Some("250"));
+        let fields = warnings[0].field("slowest_fields").unwrap();
+        assert!(fields.starts_with("tick=250ms, "), "{}", fields);
+        assert!(fields.contains("getJane.id=0ms"), "{}", fields);
+        assert_eq!(spans.event_span(warnings[0]).unwrap().name, "request");
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn the_distinct_types_resolved_by_each_request_are_recorded() {
+        let touched = |enabled: bool| async move {
+            let metrics = MetricsCollector::default();
+            let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+                .extension(OpenTelemetry)
+                .data(
+                    OpenTelemetryConfig::default()
+                        .meter(metrics.meter())
+                        .record_types_touched(enabled),
+                )
+                .finish();
+            // `QueryRoot`, `Query` & `SubQuery`, however many of their fields are resolved
+            schema
+                .execute("{ getJane { id details { name } } people { name } }")
+                .await;
+            schema.execute("{ getJane { id } }").await;
+            metrics
+                .named("graphql_types_touched")
+                .iter()
+                .map(|metric| (metric.sum, metric.count))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(touched(true).await, vec![(5.0, Some(2))]);
+        assert_eq!(touched(false).await, vec![]);
+    }
+
+    #[tokio::test]
+    async fn the_broken_validation_rule_is_recorded() {
+        let (collector, _guard) = collect_spans();
+        #[cfg(feature = "metrics")]
+        let metrics = MetricsCollector::default();
+        let config = OpenTelemetryConfig::default().record_validation_rule(true);
+        #[cfg(feature = "metrics")]
+        let config = config.meter(metrics.meter());
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(config)
+            .finish();
+        schema.execute("{ nope }").await;
+        schema.execute("{ getJane { nope } }").await;
+        schema
+            .execute("{ getJane { id } } fragment Unused on Query { id }")
+            .await;
+        schema.execute("{ getJane { id } }").await;
+
+        let spans = collector.spans();
+        let rules = spans
+            .spans_named("validation")
+            .map(|span| span.field("validation_rule"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rules,
+            vec![
+                Some("unknown_field"),
+                Some("unknown_field"),
+                Some("unused_fragment"),
+                None
+            ]
+        );
+        #[cfg(feature = "metrics")]
+        {
+            let mut counted = metrics
+                .named("graphql_validation_errors")
+                .into_iter()
+                .map(|metric| (metric.labels["validation_rule"].clone(), metric.sum))
+                .collect::<Vec<_>>();
+            counted.sort_by(|a, b| a.0.cmp(&b.0));
+            assert_eq!(
+                counted,
+                vec![
+                    ("unknown_field".to_string(), 2.0),
+                    ("unused_fragment".to_string(), 1.0)
+                ]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn validation_rules_can_be_classified_by_a_custom_classifier() {
+        let (collector, _guard) = collect_spans();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .record_validation_rule(true)
+                    .validation_rule_classifier(Arc::new(|err: &ServerError| {
+                        match err.message.contains("\"nope\"") {
+                            true => "nope",
+                            false => classify_validation_error(err),
+                        }
+                    })),
+            )
+            .finish();
+        schema.execute("{ nope }").await;
+        schema.execute("{ getJane { id } nah }").await;
+
+        let rules = collector
+            .spans()
+            .spans_named("validation")
+            .map(|span| span.field("validation_rule").map(str::to_string))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rules,
+            vec![Some("nope".to_string()), Some("unknown_field".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn field_timings_are_readable_from_the_summary() {
+        #[derive(Debug, Default)]
+        struct Timings(Mutex<Vec<(String, String, std::time::Duration)>>);
+
+        impl TracingSink for Timings {
+            fn submit(&self, summary: TracingSummary) {
+                let timings = summary.field_timings().map(|timing| {
+                    (
+                        timing.path.join("."),
+                        timing.parent_type.to_string(),
+                        timing.duration,
+                    )
+                });
+                self.0.lock().extend(timings);
+            }
+        }
+
+        let sink = Arc::new(Timings::default());
+        let clock = testing::MockClock::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .clock(Arc::new(clock.clone()))
+                    .tracing_sink(sink.clone()),
+            )
+            .data(clock)
+            .finish();
+        schema.execute("{ tick getJane { id } }").await;
+
+        let mut timings = sink.0.lock().clone();
+        timings.sort();
+        assert_eq!(
+            timings,
+            vec![
+                (
+                    "getJane".to_string(),
+                    "QueryRoot".to_string(),
+                    Default::default()
+                ),
+                (
+                    "getJane.id".to_string(),
+                    "Query".to_string(),
+                    Default::default()
+                ),
+                (
+                    "tick".to_string(),
+                    "QueryRoot".to_string(),
+                    std::time::Duration::from_millis(250)
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_are_tagged_with_a_known_http_protocol() {
+        let (collector, _guard) = collect_spans();
+        #[cfg(feature = "metrics")]
+        let metrics = MetricsCollector::default();
+        let config = OpenTelemetryConfig::default();
+        #[cfg(feature = "metrics")]
+        let config = config.meter(metrics.meter()).http_protocol_label(true);
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(config)
+            .finish();
+        for protocol in ["HTTP/1.1", "HTTP/2.0", "h3", "SPDY/3"] {
+            schema
+                .execute(Request::new(JANE_QUERY).data(HttpProtocol(protocol.to_string())))
+                .await;
+        }
+        schema.execute(JANE_QUERY).await;
+
+        let spans = collector.spans();
+        let protocols = spans
+            .spans_named("request")
+            .map(|span| span.field("http.protocol"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            protocols,
+            vec![Some("1.1"), Some("2"), Some("3"), None, None]
+        );
+        #[cfg(feature = "metrics")]
+        {
+            let mut requests = metrics
+                .named("graphql_requests")
+                .into_iter()
+                .map(|metric| (metric.labels.get("http_protocol").cloned(), metric.sum))
+                .collect::<Vec<_>>();
+            requests.sort_by(|a, b| a.0.cmp(&b.0));
+            assert_eq!(
+                requests,
+                vec![
+                    (None, 2.0),
+                    (Some("1.1".to_string()), 1.0),
+                    (Some("2".to_string()), 1.0),
+                    (Some("3".to_string()), 1.0)
+                ]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn responses_over_the_field_limit_are_flagged() {
+        let (collector, _guard) = collect_spans();
+        #[cfg(feature = "metrics")]
+        let metrics = MetricsCollector::default();
+        let config = OpenTelemetryConfig::default().response_field_limit(4);
+        #[cfg(feature = "metrics")]
+        let config = config.meter(metrics.meter());
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(config)
+            .finish();
+        // The name of each person counts as a field of its own
+        let resp = schema.execute("{ people { name } }").await;
+        assert!(resp.errors.is_empty());
+        let resp = schema.execute("{ people { name } getJane { id } }").await;
+        assert!(resp.errors.is_empty(), "the response is only flagged");
+
+        let spans = collector.spans();
+        let warnings = spans
+            .events()
+            .iter()
+            .filter(|event| event.level == Level::WARN)
+            .collect::<Vec<_>>();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].field("resolved_fields"), Some("5"));
+        assert_eq!(warnings[0].field("limit"), Some("4"));
+        assert_eq!(spans.event_span(warnings[0]).unwrap().name, "execute");
+        #[cfg(feature = "metrics")]
+        {
+            let oversized = metrics.named("graphql_oversized_responses");
+            assert_eq!(oversized.len(), 1);
+            assert_eq!(oversized[0].labels["operation_kind"], "query");
+            assert_eq!(oversized[0].sum, 1.0);
+        }
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[tokio::test]
+    async fn the_recommended_prometheus_setup_serves_the_core_metrics() {
+        let (extension, exporter) = OpenTelemetry::recommended_with_prometheus();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(extension)
+            .finish();
+        schema.execute("query GetJane { getJane { id } }").await;
+        schema.execute("{ codedError }").await;
+
+        let rendered = exporter.render().unwrap();
+        assert!(exporter.content_type().starts_with("text/plain"));
+        for name in [
+            "graphql_requests",
+            "graphql_request_errors",
+            "graphql_field_errors",
+            "graphql_request_duration_bucket",
+        ] {
+            assert!(
+                rendered.lines().any(|line| line.starts_with(name)),
+                "{} missing from\n{}",
+                name,
+                rendered
+            );
+        }
+        assert!(
+            rendered.contains("field_name=\"codedError\""),
+            "{}",
+            rendered
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn the_time_each_phase_waited_to_be_scheduled_is_recorded() {
+        let metrics = MetricsCollector::default();
+        let clock = testing::MockClock::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(metrics.meter())
+                    .clock(Arc::new(clock.clone()))
+                    .record_phase_schedule_delay(true),
+            )
+            .data(clock)
+            .finish();
+        schema.execute("{ getJane { id } starved }").await;
+
+        let delays = metrics.named("graphql_phase_schedule_delay");
+        let delay = |phase: &str| {
+            let delay = delays
+                .iter()
+                .find(|delay| delay.labels["phase"] == phase)
+                .unwrap();
+            (delay.count, delay.sum)
+        };
+        assert_eq!(delays.len(), 3);
+        assert_eq!(delay("parse"), (Some(1), 0.0));
+        assert_eq!(delay("validation"), (Some(1), 0.0));
+        // Only the time between the field being woken & polled again, not the time it was running
+        assert_eq!(delay("execute"), (Some(1), 100_000.0));
+    }
+
+    #[tokio::test]
+    async fn the_directives_of_the_operation_are_recorded() {
+        let (collector, _guard) = collect_spans();
+        #[cfg(feature = "metrics")]
+        let metrics = MetricsCollector::default();
+        let config = OpenTelemetryConfig::default();
+        #[cfg(feature = "metrics")]
+        let config = config
+            .meter(metrics.meter())
+            .count_operation_directives(vec!["live"]);
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(config)
+            .finish();
+        schema
+            .execute("query Live @live @cached(ttl: 5) { getJane { id } }")
+            .await;
+
+        let spans = collector.spans();
+        let request = spans.spans_named("request").next().unwrap();
+        assert_eq!(request.field("operation_directives"), Some("live,cached"));
+
+        #[cfg(feature = "metrics")]
+        {
+            let usage = metrics.named("graphql_operation_directive_usage");
+            assert_eq!(usage.len(), 2);
+            for (directive, sum) in [("live", 1.0), ("other", 1.0)] {
+                let series = usage
+                    .iter()
+                    .find(|series| series.labels["directive"] == directive)
+                    .unwrap();
+                assert_eq!(series.sum, sum);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn isolated_spans_are_sent_to_the_provided_dispatcher() {
+        let (default_spans, _guard) = collect_spans();
+        let isolated_spans = SpanCollector::default();
+        #[cfg(feature = "metrics")]
+        let metrics = MetricsCollector::default();
+        let config = OpenTelemetryConfig::default().record_query_source(false);
+        #[cfg(feature = "metrics")]
+        let config = config.meter(metrics.meter());
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry::isolated(
+                tracing_subscriber::registry().with(isolated_spans.clone()),
+            ))
+            .data(config)
+            .finish();
+        let response = schema.execute("{ getJane { id } optionalError }").await;
+        assert_eq!(response.errors.len(), 1);
+
+        let spans = isolated_spans.spans();
+        assert_eq!(spans.spans_named("request").count(), 1);
+        assert_eq!(spans.spans_named("field").count(), 3);
+        // The config is still taken from the schema data
+        assert_eq!(spans.span("parse").unwrap().field("source"), None);
+        assert!(default_spans.spans().all().is_empty());
+        #[cfg(feature = "metrics")]
+        assert_eq!(metrics.sum("graphql_requests"), 1.0);
+    }
+
+    #[tokio::test]
+    async fn the_size_of_each_batch_is_recorded_once() {
+        let (collector, _guard) = collect_spans();
+        #[cfg(feature = "metrics")]
+        let metrics = MetricsCollector::default();
+        let config = OpenTelemetryConfig::default();
+        #[cfg(feature = "metrics")]
+        let config = config.meter(metrics.meter()).batch_size_limit(2);
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(config)
+            .finish();
+        let batch = |size: usize| {
+            let mut requests = (0..size)
+                .map(|_| Request::new(JANE_QUERY))
+                .collect::<Vec<_>>();
+            for (index, request) in requests.iter_mut().enumerate() {
+                request.data.insert(BatchPosition { index, size });
+            }
+            BatchRequest::Batch(requests)
+        };
+        schema.execute_batch(batch(3)).await;
+        schema.execute_batch(batch(2)).await;
+        schema.execute(JANE_QUERY).await;
+
+        let spans = collector.spans();
+        let requests = spans.spans_named("request").collect::<Vec<_>>();
+        assert_eq!(requests.len(), 6);
+        assert_eq!(requests[2].field("batch_size"), Some("3"));
+        assert_eq!(requests[2].field("batch_index"), Some("2"));
+        assert_eq!(requests[5].field("batch_size"), None);
+
+        #[cfg(feature = "metrics")]
+        {
+            let sizes = metrics.named("graphql_batch_size");
+            assert_eq!(sizes[0].count, Some(2));
+            assert_eq!(sizes[0].sum, 5.0);
+            assert_eq!(metrics.sum("graphql_oversized_batches"), 1.0);
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn the_serialized_size_of_the_variables_is_recorded() {
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(metrics.meter())
+                    .record_variables_bytes(true),
+            )
+            .finish();
+        let variables = serde_json::json!({ "name": "Jane Doe", "times": 3 });
+        let request = Request::new(
+            "query Greet($name: String!, $times: Int!) { greet(name: $name, times: $times) }",
+        )
+        .variables(Variables::from_json(variables.clone()));
+        schema.execute(request).await;
+
+        let sizes = metrics.named("graphql_variables_bytes");
+        assert_eq!(sizes.len(), 1);
+        assert_eq!(sizes[0].labels["operation_kind"], "query");
+        assert_eq!(
+            sizes[0].sum,
+            serde_json::to_string(&variables).unwrap().len() as f64
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn the_duration_of_each_phase_is_recorded_by_phase() {
+        let metrics = MetricsCollector::default();
+        let clock = testing::MockClock::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .meter(metrics.meter())
+                    .clock(Arc::new(clock.clone()))
+                    .record_phase_duration(true),
+            )
+            .data(clock)
+            .finish();
+        schema.execute("{ getJane { id } tick }").await;
+
+        let durations = metrics.named("graphql_phase_duration_seconds");
+        let duration = |phase: &str| {
+            durations
+                .iter()
+                .find(|duration| duration.labels["phase"] == phase)
+                .map(|duration| duration.sum)
+        };
+        assert_eq!(durations.len(), 3);
+        assert_eq!(duration("parse"), Some(0.0));
+        assert_eq!(duration("validation"), Some(0.0));
+        assert_eq!(duration("execute"), Some(0.25));
+    }
+
+    #[tokio::test]
+    async fn field_spans_record_their_complexity_cost() {
+        let cost = |buffered: bool| async move {
+            let (collector, _guard) = collect_spans();
+            let config = OpenTelemetryConfig::default().record_field_complexity(true);
+            let config = if buffered {
+                config.field_span_min_duration(std::time::Duration::ZERO)
+            } else {
+                config
+            };
+            let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+                .extension(OpenTelemetry)
+                .data(config)
+                .finish();
+            schema.execute("{ costly: expensive getJane { id } }").await;
+
+            let spans = collector.spans();
+            let cost = |path: &str| {
+                spans
+                    .field(path)
+                    .and_then(|span| span.field("complexity_cost"))
+                    .map(str::to_string)
+            };
+            (cost("costly"), cost("getJane"))
+        };
+        assert_eq!(cost(false).await, (Some("10".to_string()), None));
+        assert_eq!(cost(true).await, (Some("10".to_string()), None));
+    }
+
+    #[tokio::test]
+    async fn tracing_data_can_be_returned_for_some_of_the_operations() {
+        let returned = |sink: ReturnTracingFor| async move {
+            let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+                .extension(OpenTelemetry)
+                .data(OpenTelemetryConfig::default().tracing_sink(Arc::new(sink)))
+                .finish();
+            let mut returned = Vec::new();
+            for query in [
+                "query Jane { getJane { id } }",
+                "query People { people { name } }",
+                "{ getJane { id } }",
+                "query Jane { getJane { id } }",
+                "query Jane { getJane { id } }",
+                "query Jane { getJane { id } }",
+            ] {
+                let response = schema.execute(query).await;
+                returned.push(response.extensions.contains_key("tracing"));
+            }
+            returned
+        };
+        assert_eq!(
+            returned(ReturnTracingFor::operations(vec!["Jane"])).await,
+            [true, false, false, true, true, true]
+        );
+        assert_eq!(
+            returned(ReturnTracingFor::default().ratio(0.5)).await,
+            [false, true, false, true, false, true]
+        );
+        assert_eq!(
+            returned(ReturnTracingFor::operations(vec!["Jane"]).ratio(0.5)).await,
+            [false, false, false, true, false, true]
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn the_time_spent_in_the_extension_is_recorded_without_the_resolvers() {
+        let (_collector, _guard) = collect_spans();
+        let overhead = |clock: Option<testing::MockClock>| async move {
+            let metrics = MetricsCollector::default();
+            let config = OpenTelemetryConfig::default()
+                .meter(metrics.meter())
+                .record_instrumentation_overhead(true);
+            let schema =
+                Schema::build(QueryRoot, EmptyMutation, EmptySubscription).extension(OpenTelemetry);
+            let schema = match clock {
+                Some(clock) => schema
+                    .data(config.clock(Arc::new(clock.clone())))
+                    .data(clock),
+                None => schema.data(config).data(testing::MockClock::default()),
+            }
+            .finish();
+            schema.execute("{ getJane { id } tick }").await;
+
+            let overhead = metrics.named("graphql_instrumentation_overhead");
+            assert_eq!(overhead.len(), 1);
+            assert_eq!(overhead[0].labels["operation_kind"], "query");
+            assert_eq!(overhead[0].count, Some(1));
+            overhead[0].sum
+        };
+        // The clock only moves within the resolver, which isn't counted
+        assert_eq!(overhead(Some(testing::MockClock::default())).await, 0.0);
+        assert!(overhead(None).await > 0.0);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn the_core_metrics_are_labelled_by_the_deployment_track() {
+        let tracks = |config: OpenTelemetryConfig| async move {
+            let metrics = MetricsCollector::default();
+            let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+                .extension(OpenTelemetry)
+                .data(config.meter(metrics.meter()))
+                .finish();
+            schema.execute("{ getJane { id } codedError }").await;
+            metrics
+                .metrics()
+                .into_iter()
+                .filter(|metric| metric.labels.contains_key("deployment_track"))
+                .map(|metric| (metric.name, metric.labels["deployment_track"].clone()))
+                .collect::<std::collections::BTreeSet<_>>()
+        };
+        let canary = |name: &str| (name.to_string(), "canary".to_string());
+        assert_eq!(
+            tracks(OpenTelemetryConfig::default().deployment_track("canary")).await,
+            std::collections::BTreeSet::from([
+                canary("graphql_field_errors"),
+                canary("graphql_request_duration"),
+                canary("graphql_request_errors"),
+                canary("graphql_requests"),
+            ])
+        );
+        assert!(tracks(OpenTelemetryConfig::default()).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_fragments_spread_by_the_operation_are_recorded() {
+        let (collector, _guard) = collect_spans();
+        let config = OpenTelemetryConfig::default().record_fragment_names(vec!["JaneDetails"]);
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(config)
+            .finish();
+        schema
+            .execute(
+                "query Jane { getJane { ...JaneId ...JaneDetails } }
+                fragment JaneId on Query { id }
+                fragment JaneDetails on Query { details { ...Name } }
+                fragment Name on SubQuery { name }
+                fragment Unused on Query { id }",
+            )
+            .await;
+
+        let spans = collector.spans();
+        let request = spans.spans_named("request").next().unwrap();
+        assert_eq!(request.field("fragment_count"), Some("3"));
+        assert_eq!(request.field("fragments"), Some("JaneDetails,other"));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn subscriptions_which_end_without_any_responses_are_counted() {
+        let metrics = MetricsCollector::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot)
+            .extension(OpenTelemetry)
+            .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+            .finish();
+        let responses = schema
+            .execute_stream("subscription { count }")
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(responses.len(), 3);
+        assert_eq!(metrics.sum("graphql_empty_subscriptions"), 0.0);
+
+        let responses = schema
+            .execute_stream("subscription { nothing }")
+            .collect::<Vec<_>>()
+            .await;
+        assert!(responses.is_empty());
+        assert_eq!(metrics.sum("graphql_subscriptions"), 2.0);
+        assert_eq!(metrics.sum("graphql_empty_subscriptions"), 1.0);
+
+        // The client going away before the first response counts too, once the stream is dropped
+        let mut stream = schema.execute_stream("subscription { never }");
+        assert!(futures_util::poll!(stream.next()).is_pending());
+        assert_eq!(metrics.sum("graphql_empty_subscriptions"), 1.0);
+        drop(stream);
+        assert_eq!(metrics.sum("graphql_subscriptions"), 3.0);
+        assert_eq!(metrics.sum("graphql_empty_subscriptions"), 2.0);
+    }
+
+    #[tokio::test]
+    async fn operation_names_which_dont_select_an_operation_are_recorded() {
+        let (collector, _guard) = collect_spans();
+        #[cfg(feature = "metrics")]
+        let metrics = MetricsCollector::default();
+        let config = OpenTelemetryConfig::default();
+        #[cfg(feature = "metrics")]
+        let config = config.meter(metrics.meter());
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(config)
+            .finish();
+        let document = "query A { getJane { id } } query B { people { name } }";
+        for request in [
+            Request::new(document).operation_name("C"),
+            Request::new(document),
+            Request::new(document).operation_name("B"),
+            Request::new(JANE_QUERY),
+            Request::new(JANE_QUERY).operation_name("A"),
+        ] {
+            schema.execute(request).await;
+        }
+
+        let spans = collector.spans();
+        let warnings = spans
+            .events()
+            .iter()
+            .filter(|event| event.level == Level::WARN)
+            .collect::<Vec<_>>();
+        assert_eq!(warnings.len(), 3);
+        let requested = warnings
+            .iter()
+            .map(|event| event.field("operation_name"))
+            .collect::<Vec<_>>();
+        assert_eq!(requested, vec![Some("C"), None, Some("A")]);
+        assert_eq!(warnings[1].field("operations"), Some("2"));
+        #[cfg(feature = "metrics")]
+        assert_eq!(metrics.sum("graphql_operation_name_mismatch"), 3.0);
+    }
+
+    #[tokio::test]
+    async fn the_field_timings_of_a_subscription_are_reported_and_cleared_per_event() {
+        #[derive(Debug, Default)]
+        struct Collect(Mutex<Vec<TracingSummary>>);
+
+        impl TracingSink for Collect {
+            fn submit(&self, summary: TracingSummary) {
+                self.0.lock().push(summary);
+            }
+        }
+
+        let sink = Arc::new(Collect::default());
+        let extension = Shared::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot)
+            .extension(extension.clone())
+            .data(OpenTelemetryConfig::default().tracing_sink(sink.clone()))
+            .finish();
+        // Each event is executed by the `execute` hook on its own, which hands its timings off to
+        // the sinks, so nothing piles up for as long as the subscription is open
+        let mut stream = schema.execute_stream("subscription { count }");
+        let mut events = 0;
+        while let Some(response) = stream.next().await {
+            assert!(response.is_ok());
+            assert!(extension.0.state.lock().metrics.resolves.is_empty());
+            events += 1;
+        }
+        assert_eq!(events, 3);
+
+        let summaries = sink.0.lock();
+        assert_eq!(summaries.len(), 3);
+        for summary in summaries.iter() {
+            let tracing = serde_json::to_value(summary).unwrap();
+            assert_eq!(
+                tracing["execution"]["resolvers"].as_array().unwrap().len(),
+                1
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn the_buffered_field_spans_of_a_subscription_are_emitted_per_event() {
+        let configs = [
+            OpenTelemetryConfig::default().tail_sampling_threshold(std::time::Duration::ZERO),
+            OpenTelemetryConfig::default().field_span_min_duration(std::time::Duration::ZERO),
+        ];
+        for config in configs {
+            let (collector, _guard) = collect_spans();
+            let schema = Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot)
+                .extension(OpenTelemetry)
+                .data(config)
+                .finish();
+            let responses = schema
+                .execute_stream("subscription { count }")
+                .collect::<Vec<_>>()
+                .await;
+            assert_eq!(responses.len(), 3);
+
+            let spans = collector.spans();
+            let executions = spans.spans_named("execute").collect::<Vec<_>>();
+            assert_eq!(executions.len(), 3);
+            for execute in executions {
+                let fields = spans
+                    .spans_named("field")
+                    .filter(|field| spans.is_descendant_of(field, execute))
+                    .collect::<Vec<_>>();
+                assert_eq!(fields.len(), 1);
+                assert_eq!(fields[0].field("path"), Some("count"));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn blocking_polls_are_measured_with_the_configured_clock() {
+        let (collector, _guard) = collect_spans();
+        let clock = testing::MockClock::default();
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .extension(OpenTelemetry)
+            .data(
+                OpenTelemetryConfig::default()
+                    .clock(Arc::new(clock.clone()))
+                    .blocking_poll_threshold(std::time::Duration::from_millis(200)),
+            )
+            .data(clock)
+            .finish();
+        schema.execute("{ tick getJane { id } }").await;
+
+        let spans = collector.spans();
+        let warnings = spans
+            .events()
+            .iter()
+            .filter(|event| event.level == Level::WARN)
+            .collect::<Vec<_>>();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].field("path"), Some("tick"));
+        assert_eq!(warnings[0].field("poll_duration"), Some("250000"));
+    }
 }
@@ -0,0 +1,1644 @@
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, OnceLock};
+
+use async_graphql::ServerError;
+use opentelemetry::metrics::noop::NoopSyncInstrument;
+use opentelemetry::metrics::{Counter, Measurement, Meter, Number, UpDownCounter, ValueRecorder};
+use opentelemetry::sdk::export::metrics::ExportKindSelector;
+use opentelemetry::{global, Key, KeyValue, Unit};
+
+use crate::{ConfigError, OpenTelemetryConfig, NAME};
+
+pub(crate) const QUERY_KEY: Key = Key::from_static_str("query_name");
+pub(crate) const QUERY_TYPE_KEY: Key = Key::from_static_str("query_type");
+pub(crate) const RETURN_TYPE_KEY: Key = Key::from_static_str("return_type");
+pub(crate) const FIELD_NAME_KEY: Key = Key::from_static_str("field_name");
+pub(crate) const PARENT_TYPE_KEY: Key = Key::from_static_str("parent_type");
+pub(crate) const STATUS_KEY: Key = Key::from_static_str("status");
+pub(crate) const CLIENT_NAME_KEY: Key = Key::from_static_str("client_name");
+pub(crate) const CLIENT_VERSION_KEY: Key = Key::from_static_str("client_version");
+pub(crate) const IS_WRITE_KEY: Key = Key::from_static_str("is_write");
+pub(crate) const SCHEMA_VERSION_KEY: Key = Key::from_static_str("schema_version");
+pub(crate) const OPERATION_KIND_KEY: Key = Key::from_static_str("operation_kind");
+pub(crate) const PARTIAL_KEY: Key = Key::from_static_str("partial");
+pub(crate) const TRANSPORT_KEY: Key = Key::from_static_str("transport");
+pub(crate) const VARIABLE_KEY: Key = Key::from_static_str("variable");
+pub(crate) const QUERY_SIGNATURE_KEY: Key = Key::from_static_str("query_signature");
+pub(crate) const SEVERITY_KEY: Key = Key::from_static_str("severity");
+pub(crate) const VALIDATION_RULE_KEY: Key = Key::from_static_str("validation_rule");
+pub(crate) const HTTP_PROTOCOL_KEY: Key = Key::from_static_str("http_protocol");
+pub(crate) const PHASE_KEY: Key = Key::from_static_str("phase");
+pub(crate) const DIRECTIVE_KEY: Key = Key::from_static_str("directive");
+pub(crate) const DEPLOYMENT_TRACK_KEY: Key = Key::from_static_str("deployment_track");
+/// Every label the extension attaches to its metrics
+pub(crate) static LABEL_KEYS: [Key; 21] = [
+    QUERY_KEY,
+    QUERY_TYPE_KEY,
+    RETURN_TYPE_KEY,
+    FIELD_NAME_KEY,
+    PARENT_TYPE_KEY,
+    STATUS_KEY,
+    CLIENT_NAME_KEY,
+    CLIENT_VERSION_KEY,
+    IS_WRITE_KEY,
+    SCHEMA_VERSION_KEY,
+    OPERATION_KIND_KEY,
+    PARTIAL_KEY,
+    TRANSPORT_KEY,
+    VARIABLE_KEY,
+    QUERY_SIGNATURE_KEY,
+    SEVERITY_KEY,
+    VALIDATION_RULE_KEY,
+    HTTP_PROTOCOL_KEY,
+    PHASE_KEY,
+    DIRECTIVE_KEY,
+    DEPLOYMENT_TRACK_KEY,
+];
+
+/// The names of the metric instruments recorded by the extension.
+///
+/// The defaults are the names this crate has always used, but each instrument
+/// can be renamed to follow a team's own conventions without having to fork
+/// the crate:
+///
+/// ```rust
+/// use async_graphql_telemetry_extension::{MetricNames, OpenTelemetryConfig};
+///
+/// let names = MetricNames::default()
+///     .requests("http_graphql_requests_total")
+///     .request_duration("graphql_request_duration_ms");
+/// let config = OpenTelemetryConfig::default().metric_names(names).unwrap();
+/// ```
+///
+/// The names are validated against the OpenTelemetry instrument naming rules
+/// when they are added to the [`OpenTelemetryConfig`](crate::OpenTelemetryConfig).
+#[derive(Debug, Clone)]
+pub struct MetricNames {
+    pub(crate) requests: Cow<'static, str>,
+    pub(crate) subscriptions: Cow<'static, str>,
+    pub(crate) operation_name_mismatch: Cow<'static, str>,
+    pub(crate) empty_subscriptions: Cow<'static, str>,
+    pub(crate) request_duration: Cow<'static, str>,
+    pub(crate) request_errors: Cow<'static, str>,
+    pub(crate) max_resolve_depth: Cow<'static, str>,
+    pub(crate) queue_time: Cow<'static, str>,
+    pub(crate) field_errors: Cow<'static, str>,
+    pub(crate) alias_count: Cow<'static, str>,
+    pub(crate) parse_cache_hits: Cow<'static, str>,
+    pub(crate) parse_cache_misses: Cow<'static, str>,
+    pub(crate) type_resolve_duration: Cow<'static, str>,
+    pub(crate) time_to_first_response: Cow<'static, str>,
+    pub(crate) list_field_size: Cow<'static, str>,
+    pub(crate) response_cache_hits: Cow<'static, str>,
+    pub(crate) response_errors: Cow<'static, str>,
+    pub(crate) anonymous_operations: Cow<'static, str>,
+    pub(crate) deadline_exceeded: Cow<'static, str>,
+    pub(crate) root_selection_width: Cow<'static, str>,
+    pub(crate) dataloader_batches: Cow<'static, str>,
+    pub(crate) dataloader_loads: Cow<'static, str>,
+    pub(crate) traces_sampled: Cow<'static, str>,
+    pub(crate) variables_validated: Cow<'static, str>,
+    pub(crate) variable_coercion_errors: Cow<'static, str>,
+    pub(crate) field_self_duration: Cow<'static, str>,
+    pub(crate) ws_messages: Cow<'static, str>,
+    pub(crate) ws_connections_active: Cow<'static, str>,
+    pub(crate) complexity_estimate_error: Cow<'static, str>,
+    pub(crate) operations_unresolved: Cow<'static, str>,
+    pub(crate) resolver_retries: Cow<'static, str>,
+    pub(crate) authz_rejections: Cow<'static, str>,
+    pub(crate) instrumentation_degraded: Cow<'static, str>,
+    pub(crate) cache_ttl: Cow<'static, str>,
+    pub(crate) slow_requests: Cow<'static, str>,
+    pub(crate) types_touched: Cow<'static, str>,
+    pub(crate) validation_errors: Cow<'static, str>,
+    pub(crate) oversized_responses: Cow<'static, str>,
+    pub(crate) phase_schedule_delay: Cow<'static, str>,
+    pub(crate) operation_directive_usage: Cow<'static, str>,
+    pub(crate) batch_size: Cow<'static, str>,
+    pub(crate) oversized_batches: Cow<'static, str>,
+    pub(crate) variables_bytes: Cow<'static, str>,
+    pub(crate) phase_duration: Cow<'static, str>,
+    pub(crate) instrumentation_overhead: Cow<'static, str>,
+}
+
+impl Default for MetricNames {
+    fn default() -> Self {
+        Self {
+            requests: Cow::Borrowed("graphql_requests"),
+            subscriptions: Cow::Borrowed("graphql_subscriptions"),
+            operation_name_mismatch: Cow::Borrowed("graphql_operation_name_mismatch"),
+            empty_subscriptions: Cow::Borrowed("graphql_empty_subscriptions"),
+            request_duration: Cow::Borrowed("graphql_request_duration"),
+            request_errors: Cow::Borrowed("graphql_request_errors"),
+            max_resolve_depth: Cow::Borrowed("graphql_max_resolve_depth"),
+            queue_time: Cow::Borrowed("graphql_queue_time"),
+            field_errors: Cow::Borrowed("graphql_field_errors"),
+            alias_count: Cow::Borrowed("graphql_alias_count"),
+            parse_cache_hits: Cow::Borrowed("graphql_parse_cache_hits"),
+            parse_cache_misses: Cow::Borrowed("graphql_parse_cache_misses"),
+            type_resolve_duration: Cow::Borrowed("graphql_type_resolve_duration"),
+            time_to_first_response: Cow::Borrowed("graphql_time_to_first_response"),
+            list_field_size: Cow::Borrowed("graphql_list_field_size"),
+            response_cache_hits: Cow::Borrowed("graphql_response_cache_hits"),
+            response_errors: Cow::Borrowed("graphql_response_errors"),
+            anonymous_operations: Cow::Borrowed("graphql_anonymous_operations"),
+            deadline_exceeded: Cow::Borrowed("graphql_deadline_exceeded"),
+            root_selection_width: Cow::Borrowed("graphql_root_selection_width"),
+            dataloader_batches: Cow::Borrowed("graphql_dataloader_batches"),
+            dataloader_loads: Cow::Borrowed("graphql_dataloader_loads"),
+            traces_sampled: Cow::Borrowed("graphql_traces_sampled"),
+            variables_validated: Cow::Borrowed("graphql_variables_validated"),
+            variable_coercion_errors: Cow::Borrowed("graphql_variable_coercion_errors"),
+            field_self_duration: Cow::Borrowed("graphql_field_self_duration"),
+            ws_messages: Cow::Borrowed("graphql_ws_messages"),
+            ws_connections_active: Cow::Borrowed("graphql_ws_connections_active"),
+            complexity_estimate_error: Cow::Borrowed("graphql_complexity_estimate_error"),
+            operations_unresolved: Cow::Borrowed("graphql_operations_unresolved"),
+            resolver_retries: Cow::Borrowed("graphql_resolver_retries"),
+            authz_rejections: Cow::Borrowed("graphql_authz_rejections"),
+            instrumentation_degraded: Cow::Borrowed("graphql_instrumentation_degraded"),
+            cache_ttl: Cow::Borrowed("graphql_cache_ttl"),
+            slow_requests: Cow::Borrowed("graphql_slow_requests"),
+            types_touched: Cow::Borrowed("graphql_types_touched"),
+            validation_errors: Cow::Borrowed("graphql_validation_errors"),
+            oversized_responses: Cow::Borrowed("graphql_oversized_responses"),
+            phase_schedule_delay: Cow::Borrowed("graphql_phase_schedule_delay"),
+            operation_directive_usage: Cow::Borrowed("graphql_operation_directive_usage"),
+            batch_size: Cow::Borrowed("graphql_batch_size"),
+            oversized_batches: Cow::Borrowed("graphql_oversized_batches"),
+            variables_bytes: Cow::Borrowed("graphql_variables_bytes"),
+            phase_duration: Cow::Borrowed("graphql_phase_duration_seconds"),
+            instrumentation_overhead: Cow::Borrowed("graphql_instrumentation_overhead"),
+        }
+    }
+}
+
+impl MetricNames {
+    /// The name of the counter of requests, defaults to `graphql_requests`
+    pub fn requests(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.requests = name.into();
+        self
+    }
+
+    /// The name of the counter of subscriptions, defaults to `graphql_subscriptions`
+    pub fn subscriptions(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.subscriptions = name.into();
+        self
+    }
+
+    /// The name of the counter of requests whose `operationName` doesn't select one of the
+    /// operations of their document, defaults to `graphql_operation_name_mismatch`.
+    ///
+    /// That's a request naming an operation the document doesn't have, or not naming one when
+    /// the document has several. async-graphql rejects these requests before executing them, so
+    /// they're detected as soon as the document is parsed, which also logs a `WARN` event. A
+    /// request without a name for a document with a single operation isn't a mismatch.
+    pub fn operation_name_mismatch(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.operation_name_mismatch = name.into();
+        self
+    }
+
+    /// The name of the counter of subscriptions whose stream ended without yielding a single
+    /// response, defaults to `graphql_empty_subscriptions`.
+    ///
+    /// A subscription which closes straight away usually means a client or authorization
+    /// problem. Subscriptions the client drops before the first response are counted too, once
+    /// their stream is dropped.
+    pub fn empty_subscriptions(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.empty_subscriptions = name.into();
+        self
+    }
+
+    /// The name of the request duration recorder, defaults to `graphql_request_duration`
+    pub fn request_duration(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.request_duration = name.into();
+        self
+    }
+
+    /// The name of the counter of errors, defaults to `graphql_request_errors`
+    pub fn request_errors(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.request_errors = name.into();
+        self
+    }
+
+    /// The name of the resolve depth recorder, defaults to `graphql_max_resolve_depth`
+    pub fn max_resolve_depth(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.max_resolve_depth = name.into();
+        self
+    }
+
+    /// The name of the queue time recorder, defaults to `graphql_queue_time`
+    pub fn queue_time(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.queue_time = name.into();
+        self
+    }
+
+    /// The name of the counter of errors by field, defaults to `graphql_field_errors`
+    pub fn field_errors(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.field_errors = name.into();
+        self
+    }
+
+    /// The name of the alias count recorder, defaults to `graphql_alias_count`
+    pub fn alias_count(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.alias_count = name.into();
+        self
+    }
+
+    /// The name of the counter of parse cache hits, defaults to `graphql_parse_cache_hits`
+    pub fn parse_cache_hits(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.parse_cache_hits = name.into();
+        self
+    }
+
+    /// The name of the counter of parse cache misses, defaults to `graphql_parse_cache_misses`
+    pub fn parse_cache_misses(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.parse_cache_misses = name.into();
+        self
+    }
+
+    /// The name of the per-type resolve duration recorder, defaults to
+    /// `graphql_type_resolve_duration`
+    pub fn type_resolve_duration(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.type_resolve_duration = name.into();
+        self
+    }
+
+    /// The name of the time to first response recorder, defaults to
+    /// `graphql_time_to_first_response`
+    ///
+    /// This is recorded from the [`ReceivedAt`](crate::ReceivedAt) of the request if it's
+    /// provided, or from the start of the request otherwise, until the first response is
+    /// produced. async-graphql doesn't support incremental delivery (`@defer`/`@stream`), so for
+    /// queries & mutations this is the whole duration of the request, while for subscriptions it's
+    /// the time until the first event.
+    pub fn time_to_first_response(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.time_to_first_response = name.into();
+        self
+    }
+
+    /// The name of the list size recorder, defaults to `graphql_list_field_size`
+    pub fn list_field_size(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.list_field_size = name.into();
+        self
+    }
+
+    /// The name of the counter of responses served from a response cache, defaults to
+    /// `graphql_response_cache_hits`
+    pub fn response_cache_hits(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.response_cache_hits = name.into();
+        self
+    }
+
+    /// The name of the counter of responses containing errors, defaults to
+    /// `graphql_response_errors`
+    pub fn response_errors(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.response_errors = name.into();
+        self
+    }
+
+    /// The name of the counter of anonymous operations, defaults to `graphql_anonymous_operations`
+    pub fn anonymous_operations(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.anonymous_operations = name.into();
+        self
+    }
+
+    /// The name of the counter of requests which overran their [`Deadline`](crate::Deadline),
+    /// defaults to `graphql_deadline_exceeded`
+    pub fn deadline_exceeded(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.deadline_exceeded = name.into();
+        self
+    }
+
+    /// The name of the root selection width recorder, defaults to `graphql_root_selection_width`
+    pub fn root_selection_width(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.root_selection_width = name.into();
+        self
+    }
+
+    /// The name of the counter of DataLoader batches, defaults to `graphql_dataloader_batches`
+    pub fn dataloader_batches(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.dataloader_batches = name.into();
+        self
+    }
+
+    /// The name of the counter of keys loaded by DataLoaders, defaults to
+    /// `graphql_dataloader_loads`
+    pub fn dataloader_loads(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.dataloader_loads = name.into();
+        self
+    }
+
+    /// The name of the counter of requests sampled for full spans, defaults to `graphql_traces_sampled`
+    pub fn traces_sampled(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.traces_sampled = name.into();
+        self
+    }
+
+    /// The name of the histogram of the variables validated for each request, defaults to
+    /// `graphql_variables_validated`
+    pub fn variables_validated(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.variables_validated = name.into();
+        self
+    }
+
+    /// The name of the counter of variables which failed to coerce, defaults to
+    /// `graphql_variable_coercion_errors`
+    pub fn variable_coercion_errors(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.variable_coercion_errors = name.into();
+        self
+    }
+
+    /// The name of the field self time recorder, defaults to `graphql_field_self_duration`
+    pub fn field_self_duration(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.field_self_duration = name.into();
+        self
+    }
+
+    /// The name of the counter of messages received over WebSocket connections, defaults to
+    /// `graphql_ws_messages`
+    pub fn ws_messages(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.ws_messages = name.into();
+        self
+    }
+
+    /// The name of the gauge of open WebSocket connections, defaults to
+    /// `graphql_ws_connections_active`
+    pub fn ws_connections_active(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.ws_connections_active = name.into();
+        self
+    }
+
+    /// The name of the histogram of the actual to estimated complexity ratios, defaults to
+    /// `graphql_complexity_estimate_error`
+    pub fn complexity_estimate_error(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.complexity_estimate_error = name.into();
+        self
+    }
+
+    /// The name of the counter of the requests which ended before any field was resolved,
+    /// defaults to `graphql_operations_unresolved`
+    pub fn operations_unresolved(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.operations_unresolved = name.into();
+        self
+    }
+
+    /// The name of the counter of resolver retries reported through `RetryStats`, defaults to
+    /// `graphql_resolver_retries`
+    pub fn resolver_retries(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.resolver_retries = name.into();
+        self
+    }
+
+    /// The name of the counter of field errors with an authorization error code, defaults to
+    /// `graphql_authz_rejections`
+    pub fn authz_rejections(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.authz_rejections = name.into();
+        self
+    }
+
+    /// The name of the counter of requests whose instrumentation exceeded its budget, defaults to
+    /// `graphql_instrumentation_degraded`
+    pub fn instrumentation_degraded(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.instrumentation_degraded = name.into();
+        self
+    }
+
+    /// The name of the recorder of the cache TTL advertised by each response, defaults to
+    /// `graphql_cache_ttl`
+    pub fn cache_ttl(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.cache_ttl = name.into();
+        self
+    }
+
+    /// The name of the counter of requests over the slow request threshold, defaults to
+    /// `graphql_slow_requests`
+    pub fn slow_requests(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.slow_requests = name.into();
+        self
+    }
+
+    /// The name of the recorder of the distinct types resolved by each request, defaults to
+    /// `graphql_types_touched`
+    pub fn types_touched(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.types_touched = name.into();
+        self
+    }
+
+    /// The name of the counter of documents which failed validation, defaults to
+    /// `graphql_validation_errors`
+    pub fn validation_errors(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.validation_errors = name.into();
+        self
+    }
+
+    /// The name of the counter of responses over the response field limit, defaults to
+    /// `graphql_oversized_responses`
+    pub fn oversized_responses(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.oversized_responses = name.into();
+        self
+    }
+
+    /// The name of the recorder of the time each phase waited to be scheduled, defaults to
+    /// `graphql_phase_schedule_delay`
+    pub fn phase_schedule_delay(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.phase_schedule_delay = name.into();
+        self
+    }
+
+    /// The name of the counter of the directives applied to operations, defaults to
+    /// `graphql_operation_directive_usage`
+    pub fn operation_directive_usage(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.operation_directive_usage = name.into();
+        self
+    }
+
+    /// The name of the recorder of the operations in each batch, defaults to `graphql_batch_size`
+    pub fn batch_size(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.batch_size = name.into();
+        self
+    }
+
+    /// The name of the counter of batches over the batch size limit, defaults to
+    /// `graphql_oversized_batches`
+    pub fn oversized_batches(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.oversized_batches = name.into();
+        self
+    }
+
+    /// The name of the recorder of the serialized size of the variables, defaults to
+    /// `graphql_variables_bytes`
+    pub fn variables_bytes(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.variables_bytes = name.into();
+        self
+    }
+
+    /// The name of the recorder of the duration of each phase, defaults to
+    /// `graphql_phase_duration_seconds`
+    pub fn phase_duration(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.phase_duration = name.into();
+        self
+    }
+
+    /// The name of the recorder of the time spent in the extension's own code, defaults to
+    /// `graphql_instrumentation_overhead`
+    pub fn instrumentation_overhead(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.instrumentation_overhead = name.into();
+        self
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &str> {
+        vec![
+            &self.requests,
+            &self.subscriptions,
+            &self.operation_name_mismatch,
+            &self.empty_subscriptions,
+            &self.request_duration,
+            &self.request_errors,
+            &self.max_resolve_depth,
+            &self.queue_time,
+            &self.field_errors,
+            &self.alias_count,
+            &self.parse_cache_hits,
+            &self.parse_cache_misses,
+            &self.type_resolve_duration,
+            &self.time_to_first_response,
+            &self.list_field_size,
+            &self.response_cache_hits,
+            &self.response_errors,
+            &self.anonymous_operations,
+            &self.deadline_exceeded,
+            &self.root_selection_width,
+            &self.dataloader_batches,
+            &self.dataloader_loads,
+            &self.traces_sampled,
+            &self.variables_validated,
+            &self.variable_coercion_errors,
+            &self.field_self_duration,
+            &self.ws_messages,
+            &self.ws_connections_active,
+            &self.complexity_estimate_error,
+            &self.operations_unresolved,
+            &self.resolver_retries,
+            &self.authz_rejections,
+            &self.instrumentation_degraded,
+            &self.cache_ttl,
+            &self.slow_requests,
+            &self.types_touched,
+            &self.validation_errors,
+            &self.oversized_responses,
+            &self.phase_schedule_delay,
+            &self.operation_directive_usage,
+            &self.batch_size,
+            &self.oversized_batches,
+            &self.variables_bytes,
+            &self.phase_duration,
+            &self.instrumentation_overhead,
+        ]
+        .into_iter()
+        .map(|name| name.as_ref())
+    }
+
+    /// Checks every name follows the OpenTelemetry instrument naming rules and
+    /// that no two instruments share a name.
+    pub(crate) fn validate(&self) -> Result<(), ConfigError> {
+        let mut seen = std::collections::HashSet::new();
+        for name in self.iter() {
+            if !is_valid_instrument_name(name) {
+                return Err(ConfigError::InvalidMetricName(name.to_string()));
+            }
+            if !seen.insert(name) {
+                return Err(ConfigError::DuplicateMetricName(name.to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The aggregation temporality to export the metrics with.
+///
+/// In the `opentelemetry` 0.13 SDK the temporality isn't a property of an instrument, but of the
+/// export pipeline the meter belongs to, so the extension can't choose it for the instruments it
+/// creates. Instead, build the pipeline with the matching selector:
+///
+/// ```rust,ignore
+/// let controller = controllers::push(
+///     selectors::simple::Selector::Exact,
+///     Temporality::Delta.export_kind_selector(),
+///     exporter,
+///     tokio::spawn,
+///     opentelemetry::util::tokio_interval_stream,
+/// )
+/// .build();
+/// let config = OpenTelemetryConfig::default().meter(controller.provider().meter("graphql", None));
+/// ```
+///
+/// Pull-based backends such as Prometheus read the running totals, so they want
+/// [`Cumulative`](Self::Cumulative), which is the default and what the crate has always been used
+/// with. Push-based backends which aggregate the points themselves, such as OTLP into Datadog or
+/// New Relic, generally want [`Delta`](Self::Delta), where each export only carries the
+/// measurements since the previous one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Temporality {
+    /// Every export carries the totals since the process started
+    #[default]
+    Cumulative,
+    /// Every export carries the measurements since the previous export
+    Delta,
+}
+
+impl Temporality {
+    /// The selector to build the export pipeline with
+    pub fn export_kind_selector(self) -> ExportKindSelector {
+        match self {
+            Self::Cumulative => ExportKindSelector::Cumulative,
+            Self::Delta => ExportKindSelector::Delta,
+        }
+    }
+}
+
+/// How alarming an error is, recorded as the `severity` label of the error counters.
+///
+/// The severity of each error is decided by the closure set with
+/// [`OpenTelemetryConfig::error_severity`](crate::OpenTelemetryConfig::error_severity), or else
+/// by [`Severity::from_error`], so that alerts can fire on the errors which need someone's
+/// attention rather than on every error the clients cause themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Expected in normal operation, e.g. a client sending bad input
+    Info,
+    /// Worth keeping an eye on, e.g. clients being denied access
+    Warning,
+    /// Something went wrong on the server
+    Error,
+}
+
+impl Severity {
+    /// The default severity of an error, by the `code` of its extensions:
+    ///
+    /// - `info` for `BAD_USER_INPUT`, `GRAPHQL_PARSE_FAILED`, `GRAPHQL_VALIDATION_FAILED`,
+    ///   `NOT_FOUND` & `PERSISTED_QUERY_NOT_FOUND`
+    /// - `warning` for `FORBIDDEN`, `UNAUTHENTICATED` & `TOO_MANY_REQUESTS`
+    /// - `error` for any other code, or an error without one
+    pub fn from_error(err: &ServerError) -> Self {
+        match crate::error_code(err).as_deref() {
+            Some(
+                "BAD_USER_INPUT"
+                | "GRAPHQL_PARSE_FAILED"
+                | "GRAPHQL_VALIDATION_FAILED"
+                | "NOT_FOUND"
+                | "PERSISTED_QUERY_NOT_FOUND",
+            ) => Self::Info,
+            Some("FORBIDDEN" | "UNAUTHENTICATED" | "TOO_MANY_REQUESTS") => Self::Warning,
+            _ => Self::Error,
+        }
+    }
+
+    /// The value of the `severity` label
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// A metric recorded from the values a request's resolvers added to its
+/// [`BusinessMetrics`](crate::BusinessMetrics), e.g. the value of the orders a mutation placed.
+///
+/// Each spec maps a key of the accumulator onto an instrument, which is recorded once the request
+/// has been executed with the total of the values added under that key, labelled with the
+/// `operation_kind`:
+///
+/// ```rust
+/// use async_graphql_telemetry_extension::{BusinessMetricSpec, OpenTelemetryConfig};
+///
+/// let config = OpenTelemetryConfig::default()
+///     .business_metrics(vec![
+///         BusinessMetricSpec::value_recorder("order_value", "shop_order_value")
+///             .description("total value of the orders placed by each request"),
+///         BusinessMetricSpec::counter("items_sold", "shop_items_sold"),
+///     ])
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct BusinessMetricSpec {
+    pub(crate) key: Cow<'static, str>,
+    pub(crate) name: Cow<'static, str>,
+    kind: BusinessMetricKind,
+    description: Option<Cow<'static, str>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BusinessMetricKind {
+    Counter,
+    ValueRecorder,
+}
+
+impl BusinessMetricSpec {
+    /// A counter named `name`, incremented by each request's total for `key`
+    pub fn counter(key: impl Into<Cow<'static, str>>, name: impl Into<Cow<'static, str>>) -> Self {
+        Self::new(key, name, BusinessMetricKind::Counter)
+    }
+
+    /// A value recorder named `name`, recording each request's total for `key`, so that the
+    /// distribution of the values per request can be seen
+    pub fn value_recorder(
+        key: impl Into<Cow<'static, str>>,
+        name: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        Self::new(key, name, BusinessMetricKind::ValueRecorder)
+    }
+
+    /// The description of the instrument
+    pub fn description(mut self, description: impl Into<Cow<'static, str>>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    fn new(
+        key: impl Into<Cow<'static, str>>,
+        name: impl Into<Cow<'static, str>>,
+        kind: BusinessMetricKind,
+    ) -> Self {
+        Self {
+            key: key.into(),
+            name: name.into(),
+            kind,
+            description: None,
+        }
+    }
+
+    fn instrument(&self, meter: &Meter, labels: &LabelPolicy) -> BusinessInstrument {
+        match self.kind {
+            BusinessMetricKind::Counter => {
+                let builder = meter.f64_counter(self.name.clone());
+                let builder = match &self.description {
+                    Some(description) => builder.with_description(description.clone()),
+                    None => builder,
+                };
+                BusinessInstrument::Counter(builder.init().labelled(labels))
+            }
+            BusinessMetricKind::ValueRecorder => {
+                let builder = meter.f64_value_recorder(self.name.clone());
+                let builder = match &self.description {
+                    Some(description) => builder.with_description(description.clone()),
+                    None => builder,
+                };
+                BusinessInstrument::ValueRecorder(builder.init().labelled(labels))
+            }
+        }
+    }
+}
+
+/// Checks the business metrics follow the naming rules and don't share a name with each other or
+/// with any of the extension's own instruments.
+pub(crate) fn validate_business_metrics(
+    specs: &[BusinessMetricSpec],
+    names: &MetricNames,
+) -> Result<(), ConfigError> {
+    let mut seen = names.iter().collect::<std::collections::HashSet<_>>();
+    for spec in specs {
+        if !is_valid_instrument_name(&spec.name) {
+            return Err(ConfigError::InvalidMetricName(spec.name.to_string()));
+        }
+        if !seen.insert(&spec.name) {
+            return Err(ConfigError::DuplicateMetricName(spec.name.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Checks the renamed labels follow the attribute naming rules, and that no two of the
+/// extension's or the static labels end up with the same name.
+pub(crate) fn validate_label_names(
+    renames: &[(Key, Key)],
+    static_labels: &[KeyValue],
+) -> Result<(), ConfigError> {
+    if let Some((_, name)) = renames
+        .iter()
+        .find(|(_, name)| !is_valid_label_name(name.as_str()))
+    {
+        return Err(ConfigError::InvalidLabelName(name.to_string()));
+    }
+    let labels = LABEL_KEYS
+        .iter()
+        .chain(static_labels.iter().map(|label| &label.key))
+        .collect::<std::collections::HashSet<_>>();
+    let mut seen = std::collections::HashSet::new();
+    for label in labels {
+        let name = renamed(renames, label);
+        if !seen.insert(name) {
+            return Err(ConfigError::DuplicateLabelName(name.to_string()));
+        }
+    }
+    Ok(())
+}
+
+fn renamed<'a>(renames: &'a [(Key, Key)], label: &'a Key) -> &'a Key {
+    renames
+        .iter()
+        .find(|(from, _)| from == label)
+        .map_or(label, |(_, to)| to)
+}
+
+/// A label name must start with an ASCII letter, only contain ASCII alphanumerics, `_` or `.`,
+/// and be at most 255 characters long.
+fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    let starts_with_letter = chars
+        .next()
+        .map(|c| c.is_ascii_alphabetic())
+        .unwrap_or_default();
+    starts_with_letter
+        && name.len() <= 255
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.'))
+}
+
+/// An instrument name must start with an ASCII letter, only contain ASCII
+/// alphanumerics, `_`, `.` or `-`, and be at most 63 characters long.
+fn is_valid_instrument_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    let starts_with_letter = chars
+        .next()
+        .map(|c| c.is_ascii_alphabetic())
+        .unwrap_or_default();
+    starts_with_letter
+        && name.len() <= 63
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+/// The configured static labels & label renames, applied to each measurement.
+#[derive(Debug, Clone)]
+pub(crate) struct LabelPolicy {
+    static_labels: Arc<[KeyValue]>,
+    renames: Arc<[(Key, Key)]>,
+    /// Whether the measurements are logged rather than recorded
+    dry_run: bool,
+}
+
+impl LabelPolicy {
+    pub(crate) fn new(config: &OpenTelemetryConfig) -> Self {
+        Self {
+            static_labels: config.static_labels.clone(),
+            renames: config.label_names.clone(),
+            dry_run: config.dry_run,
+        }
+    }
+
+    #[cfg(feature = "prometheus")]
+    pub(crate) fn static_labels(&self) -> &[KeyValue] {
+        &self.static_labels
+    }
+
+    /// The name the label is recorded under
+    #[cfg(feature = "prometheus")]
+    pub(crate) fn renamed<'a>(&'a self, key: &'a Key) -> &'a Key {
+        renamed(&self.renames, key)
+    }
+
+    #[cfg(feature = "prometheus")]
+    pub(crate) fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+}
+
+/// An instrument which records the configured static labels alongside the labels of each
+/// measurement, under their configured names.
+#[derive(Debug, Clone)]
+pub(crate) struct Labelled<T> {
+    instrument: T,
+    labels: LabelPolicy,
+}
+
+/// Wraps an instrument so it records the static labels
+trait WithStaticLabels: Sized {
+    fn labelled(self, labels: &LabelPolicy) -> Labelled<Self> {
+        Labelled {
+            instrument: self,
+            labels: labels.clone(),
+        }
+    }
+}
+
+impl<T> WithStaticLabels for Counter<T> {}
+impl<T> WithStaticLabels for ValueRecorder<T> {}
+impl<T> WithStaticLabels for UpDownCounter<T> {}
+
+impl<T> Labelled<T> {
+    /// Runs the closure with the full set of labels for a measurement
+    fn with_labels(&self, labels: &[KeyValue], f: impl FnOnce(&[KeyValue])) {
+        let LabelPolicy {
+            static_labels,
+            renames,
+            ..
+        } = &self.labels;
+        if static_labels.is_empty() && renames.is_empty() {
+            return f(labels);
+        }
+        let mut all = Vec::with_capacity(static_labels.len() + labels.len());
+        all.extend_from_slice(static_labels);
+        all.extend_from_slice(labels);
+        for label in &mut all {
+            label.key = renamed(renames, &label.key).clone();
+        }
+        f(&all)
+    }
+}
+
+impl<T: Into<Number>> Labelled<Counter<T>> {
+    pub(crate) fn add(&self, value: T, labels: &[KeyValue]) {
+        self.with_labels(labels, |labels| match self.labels.dry_run {
+            true => log_dry_run(self.instrument.measurement(value), labels),
+            false => self.instrument.add(value, labels),
+        })
+    }
+}
+
+impl<T: Into<Number>> Labelled<UpDownCounter<T>> {
+    pub(crate) fn add(&self, value: T, labels: &[KeyValue]) {
+        self.with_labels(labels, |labels| match self.labels.dry_run {
+            true => log_dry_run(self.instrument.measurement(value), labels),
+            false => self.instrument.add(value, labels),
+        })
+    }
+}
+
+impl<T: Into<Number>> Labelled<ValueRecorder<T>> {
+    pub(crate) fn record(&self, value: T, labels: &[KeyValue]) {
+        self.with_labels(labels, |labels| match self.labels.dry_run {
+            true => log_dry_run(self.instrument.measurement(value), labels),
+            false => self.instrument.record(value, labels),
+        })
+    }
+}
+
+impl<T: Into<Number> + Copy> Labelled<ValueRecorder<T>> {
+    /// Records each of the values with the same labels, binding the instrument to the labels once
+    /// rather than looking them up for every value
+    fn record_all(&self, values: &[T], labels: &[KeyValue]) {
+        self.with_labels(labels, |labels| match self.labels.dry_run {
+            true => {
+                for &value in values {
+                    log_dry_run(self.instrument.measurement(value), labels);
+                }
+            }
+            false => {
+                let bound = self.instrument.bind(labels);
+                for &value in values {
+                    bound.record(value);
+                }
+            }
+        })
+    }
+}
+
+/// Logs the measurement which would have been recorded, in a dry run
+fn log_dry_run(measurement: Measurement, labels: &[KeyValue]) {
+    let descriptor = measurement.instrument().descriptor();
+    tracing::debug!(
+        target: crate::DRY_RUN_TARGET,
+        metric = descriptor.name(),
+        value = measurement.number().to_f64(descriptor.number_kind()),
+        ?labels,
+        "would have recorded metric"
+    );
+}
+
+/// A counter which can be recorded by either metrics backend
+pub(crate) trait CounterBackend: fmt::Debug + Send + Sync {
+    fn add(&self, value: u64, labels: &[KeyValue]);
+}
+
+/// A distribution of values which can be recorded by either metrics backend
+pub(crate) trait RecorderBackend: fmt::Debug + Send + Sync {
+    fn record(&self, value: u64, labels: &[KeyValue]);
+
+    /// Records each of the values with the same labels
+    fn record_all(&self, values: &[u64], labels: &[KeyValue]) {
+        for &value in values {
+            self.record(value, labels);
+        }
+    }
+}
+
+impl CounterBackend for Labelled<Counter<u64>> {
+    fn add(&self, value: u64, labels: &[KeyValue]) {
+        // The inherent method, which takes precedence over the trait's
+        self.add(value, labels)
+    }
+}
+
+impl RecorderBackend for Labelled<ValueRecorder<u64>> {
+    fn record(&self, value: u64, labels: &[KeyValue]) {
+        self.record(value, labels)
+    }
+
+    fn record_all(&self, values: &[u64], labels: &[KeyValue]) {
+        self.record_all(values, labels)
+    }
+}
+
+pub(crate) const REQUESTS_DESCRIPTION: &str =
+    "total number of HTTP requests sent to the graphQL server";
+pub(crate) const REQUEST_ERRORS_DESCRIPTION: &str =
+    "total number of graphQL queries resulting in an error being returned";
+pub(crate) const FIELD_ERRORS_DESCRIPTION: &str =
+    "total number of errors returned by each graphQL field resolver";
+pub(crate) const REQUEST_DURATION_DESCRIPTION: &str = "duration of graphql queries in milliseconds";
+const SUBSCRIPTIONS_DESCRIPTION: &str = "total number of subscriptions sent to the graphQL server";
+
+/// The request, error & duration instruments, which can be recorded with either the meter or a
+/// Prometheus registry.
+#[derive(Debug, Clone)]
+pub(crate) struct CoreInstruments {
+    pub(crate) requests: Arc<dyn CounterBackend>,
+    pub(crate) request_errors: Arc<dyn CounterBackend>,
+    pub(crate) field_errors: Arc<dyn CounterBackend>,
+    pub(crate) request_duration: Arc<dyn RecorderBackend>,
+}
+
+impl CoreInstruments {
+    fn new(meter: &Meter, names: &MetricNames, labels: &LabelPolicy) -> Self {
+        Self {
+            requests: Arc::new(
+                meter
+                    .u64_counter(names.requests.clone())
+                    .with_description(REQUESTS_DESCRIPTION)
+                    .init()
+                    .labelled(labels),
+            ),
+            request_errors: Arc::new(
+                meter
+                    .u64_counter(names.request_errors.clone())
+                    .with_description(REQUEST_ERRORS_DESCRIPTION)
+                    .init()
+                    .labelled(labels),
+            ),
+            field_errors: Arc::new(
+                meter
+                    .u64_counter(names.field_errors.clone())
+                    .with_description(FIELD_ERRORS_DESCRIPTION)
+                    .init()
+                    .labelled(labels),
+            ),
+            request_duration: Arc::new(
+                meter
+                    .u64_value_recorder(names.request_duration.clone())
+                    .with_description(REQUEST_DURATION_DESCRIPTION)
+                    .with_unit(Unit::new("milliseconds"))
+                    .init()
+                    .labelled(labels),
+            ),
+        }
+    }
+}
+
+/// The metric instruments recorded by the extension.
+#[derive(Debug)]
+pub(crate) struct Instruments {
+    /// Whether every instrument records nothing, so the labels of the measurements recorded for
+    /// each field don't need to be worked out
+    pub(crate) discards: bool,
+    pub(crate) requests: Arc<dyn CounterBackend>,
+    pub(crate) subscriptions: Labelled<Counter<u64>>,
+    pub(crate) operation_name_mismatch: Labelled<Counter<u64>>,
+    pub(crate) empty_subscriptions: Labelled<Counter<u64>>,
+    pub(crate) request_duration: Arc<dyn RecorderBackend>,
+    pub(crate) request_errors: Arc<dyn CounterBackend>,
+    pub(crate) max_resolve_depth: Labelled<ValueRecorder<u64>>,
+    pub(crate) queue_time: Labelled<ValueRecorder<u64>>,
+    pub(crate) field_errors: Arc<dyn CounterBackend>,
+    pub(crate) alias_count: Labelled<ValueRecorder<u64>>,
+    pub(crate) parse_cache_hits: Labelled<Counter<u64>>,
+    pub(crate) parse_cache_misses: Labelled<Counter<u64>>,
+    pub(crate) type_resolve_duration: Labelled<ValueRecorder<u64>>,
+    pub(crate) time_to_first_response: Labelled<ValueRecorder<u64>>,
+    pub(crate) list_field_size: Labelled<ValueRecorder<u64>>,
+    pub(crate) response_cache_hits: Labelled<Counter<u64>>,
+    pub(crate) response_errors: Labelled<Counter<u64>>,
+    pub(crate) anonymous_operations: Labelled<Counter<u64>>,
+    pub(crate) deadline_exceeded: Labelled<Counter<u64>>,
+    pub(crate) root_selection_width: Labelled<ValueRecorder<u64>>,
+    pub(crate) dataloader_batches: Labelled<Counter<u64>>,
+    pub(crate) dataloader_loads: Labelled<Counter<u64>>,
+    pub(crate) traces_sampled: Labelled<Counter<u64>>,
+    pub(crate) variables_validated: Labelled<ValueRecorder<u64>>,
+    pub(crate) variable_coercion_errors: Labelled<Counter<u64>>,
+    pub(crate) field_self_duration: Labelled<ValueRecorder<u64>>,
+    pub(crate) ws_messages: Labelled<Counter<u64>>,
+    pub(crate) ws_connections_active: Labelled<UpDownCounter<i64>>,
+    pub(crate) complexity_estimate_error: Labelled<ValueRecorder<f64>>,
+    pub(crate) operations_unresolved: Labelled<Counter<u64>>,
+    pub(crate) resolver_retries: Labelled<Counter<u64>>,
+    pub(crate) authz_rejections: Labelled<Counter<u64>>,
+    pub(crate) instrumentation_degraded: Labelled<Counter<u64>>,
+    pub(crate) cache_ttl: Labelled<ValueRecorder<u64>>,
+    pub(crate) slow_requests: Labelled<Counter<u64>>,
+    pub(crate) types_touched: Labelled<ValueRecorder<u64>>,
+    pub(crate) validation_errors: Labelled<Counter<u64>>,
+    pub(crate) oversized_responses: Labelled<Counter<u64>>,
+    pub(crate) phase_schedule_delay: Labelled<ValueRecorder<u64>>,
+    pub(crate) operation_directive_usage: Labelled<Counter<u64>>,
+    pub(crate) batch_size: Labelled<ValueRecorder<u64>>,
+    pub(crate) oversized_batches: Labelled<Counter<u64>>,
+    pub(crate) variables_bytes: Labelled<ValueRecorder<u64>>,
+    pub(crate) phase_duration: Labelled<ValueRecorder<f64>>,
+    pub(crate) instrumentation_overhead: Labelled<ValueRecorder<u64>>,
+    /// The instruments of the configured business metrics, keyed by their accumulator key
+    pub(crate) business: Vec<(Cow<'static, str>, BusinessInstrument)>,
+}
+
+#[derive(Debug)]
+pub(crate) enum BusinessInstrument {
+    Counter(Labelled<Counter<f64>>),
+    ValueRecorder(Labelled<ValueRecorder<f64>>),
+}
+
+impl BusinessInstrument {
+    pub(crate) fn record(&self, value: f64, labels: &[KeyValue]) {
+        match self {
+            Self::Counter(counter) => counter.add(value, labels),
+            Self::ValueRecorder(recorder) => recorder.record(value, labels),
+        }
+    }
+}
+
+impl Instruments {
+    pub(crate) fn new(meter: &Meter, config: &OpenTelemetryConfig) -> Self {
+        let names = &config.metric_names;
+        let labels = &LabelPolicy::new(config);
+        #[cfg(feature = "prometheus")]
+        let core = config
+            .prometheus_instruments()
+            .cloned()
+            .unwrap_or_else(|| CoreInstruments::new(meter, names, labels));
+        #[cfg(not(feature = "prometheus"))]
+        let core = CoreInstruments::new(meter, names, labels);
+        #[cfg(feature = "prometheus")]
+        let discards = config.prometheus_instruments().is_none() && is_noop(meter, names);
+        #[cfg(not(feature = "prometheus"))]
+        let discards = is_noop(meter, names);
+        let CoreInstruments {
+            requests,
+            request_errors,
+            field_errors,
+            request_duration,
+        } = core;
+        Self {
+            discards,
+            requests,
+            request_errors,
+            field_errors,
+            request_duration,
+            subscriptions: meter
+                .u64_counter(names.subscriptions.clone())
+                .with_description(SUBSCRIPTIONS_DESCRIPTION)
+                .init()
+                .labelled(labels),
+            operation_name_mismatch: meter
+                .u64_counter(names.operation_name_mismatch.clone())
+                .with_description(
+                    "total number of requests whose operation name didn't select one of the document's operations",
+                )
+                .init()
+                .labelled(labels),
+            empty_subscriptions: meter
+                .u64_counter(names.empty_subscriptions.clone())
+                .with_description(
+                    "total number of subscriptions which ended without yielding any responses",
+                )
+                .init()
+                .labelled(labels),
+            max_resolve_depth: meter
+                .u64_value_recorder(names.max_resolve_depth.clone())
+                .with_description(
+                    "maximum nesting depth of the fields resolved while executing a graphQL query",
+                )
+                .init()
+                .labelled(labels),
+            queue_time: meter
+                .u64_value_recorder(names.queue_time.clone())
+                .with_description(
+                    "time between a graphQL request being received and executed in milliseconds",
+                )
+                .with_unit(Unit::new("milliseconds"))
+                .init()
+                .labelled(labels),
+            alias_count: meter
+                .u64_value_recorder(names.alias_count.clone())
+                .with_description("number of aliased fields in each graphQL query")
+                .init()
+                .labelled(labels),
+            parse_cache_hits: meter
+                .u64_counter(names.parse_cache_hits.clone())
+                .with_description("total number of graphQL documents served from a parse cache")
+                .init()
+                .labelled(labels),
+            parse_cache_misses: meter
+                .u64_counter(names.parse_cache_misses.clone())
+                .with_description("total number of graphQL documents which had to be parsed")
+                .init()
+                .labelled(labels),
+            type_resolve_duration: meter
+                .u64_value_recorder(names.type_resolve_duration.clone())
+                .with_description(
+                    "duration of resolving the fields of each graphQL type in microseconds",
+                )
+                .with_unit(Unit::new("microseconds"))
+                .init()
+                .labelled(labels),
+            time_to_first_response: meter
+                .u64_value_recorder(names.time_to_first_response.clone())
+                .with_description(
+                    "time until the first graphQL response was produced in milliseconds",
+                )
+                .with_unit(Unit::new("milliseconds"))
+                .init()
+                .labelled(labels),
+            list_field_size: meter
+                .u64_value_recorder(names.list_field_size.clone())
+                .with_description("number of items returned by each graphQL list field")
+                .init()
+                .labelled(labels),
+            response_cache_hits: meter
+                .u64_counter(names.response_cache_hits.clone())
+                .with_description("total number of graphQL responses served from a response cache")
+                .init()
+                .labelled(labels),
+            response_errors: meter
+                .u64_counter(names.response_errors.clone())
+                .with_description("total number of graphQL responses containing at least one error")
+                .init()
+                .labelled(labels),
+            anonymous_operations: meter
+                .u64_counter(names.anonymous_operations.clone())
+                .with_description(
+                    "total number of graphQL operations executed without an operation name",
+                )
+                .init()
+                .labelled(labels),
+            deadline_exceeded: meter
+                .u64_counter(names.deadline_exceeded.clone())
+                .with_description(
+                    "total number of graphQL requests which finished executing after their deadline",
+                )
+                .init()
+                .labelled(labels),
+            root_selection_width: meter
+                .u64_value_recorder(names.root_selection_width.clone())
+                .with_description("number of top-level fields selected by each graphQL operation")
+                .init()
+                .labelled(labels),
+            dataloader_batches: meter
+                .u64_counter(names.dataloader_batches.clone())
+                .with_description(
+                    "total number of DataLoader batches dispatched while executing graphQL requests",
+                )
+                .init()
+                .labelled(labels),
+            dataloader_loads: meter
+                .u64_counter(names.dataloader_loads.clone())
+                .with_description(
+                    "total number of keys loaded by DataLoader batches while executing graphQL requests",
+                )
+                .init()
+                .labelled(labels),
+            traces_sampled: meter
+                .u64_counter(names.traces_sampled.clone())
+                .with_description(
+                    "total number of graphQL requests whose field spans were recorded",
+                )
+                .init()
+                .labelled(labels),
+            variables_validated: meter
+                .u64_value_recorder(names.variables_validated.clone())
+                .with_description("number of variables validated for each graphQL request")
+                .init()
+                .labelled(labels),
+            variable_coercion_errors: meter
+                .u64_counter(names.variable_coercion_errors.clone())
+                .with_description(
+                    "number of graphQL variables which failed to coerce to the type of their argument",
+                )
+                .init()
+                .labelled(labels),
+            field_self_duration: meter
+                .u64_value_recorder(names.field_self_duration.clone())
+                .with_description(
+                    "time spent resolving each graphQL field, excluding its child fields, in microseconds",
+                )
+                .with_unit(Unit::new("microseconds"))
+                .init()
+                .labelled(labels),
+            ws_messages: meter
+                .u64_counter(names.ws_messages.clone())
+                .with_description(
+                    "total number of messages received over graphQL WebSocket connections",
+                )
+                .init()
+                .labelled(labels),
+            ws_connections_active: meter
+                .i64_up_down_counter(names.ws_connections_active.clone())
+                .with_description("number of graphQL WebSocket connections currently open")
+                .init()
+                .labelled(labels),
+            complexity_estimate_error: meter
+                .f64_value_recorder(names.complexity_estimate_error.clone())
+                .with_description(
+                    "ratio of the number of fields resolved to the complexity estimated for each graphQL operation",
+                )
+                .init()
+                .labelled(labels),
+            operations_unresolved: meter
+                .u64_counter(names.operations_unresolved.clone())
+                .with_description(
+                    "total number of graphQL requests which ended before any field was resolved",
+                )
+                .init()
+                .labelled(labels),
+            resolver_retries: meter
+                .u64_counter(names.resolver_retries.clone())
+                .with_description(
+                    "total number of times graphQL resolvers retried, as reported by the host",
+                )
+                .init()
+                .labelled(labels),
+            authz_rejections: meter
+                .u64_counter(names.authz_rejections.clone())
+                .with_description("total number of graphQL fields rejected with an authorization error code")
+                .init()
+                .labelled(labels),
+            instrumentation_degraded: meter
+                .u64_counter(names.instrumentation_degraded.clone())
+                .with_description(
+                    "total number of graphQL requests whose instrumentation was cut back after exceeding its budget",
+                )
+                .init()
+                .labelled(labels),
+            cache_ttl: meter
+                .u64_value_recorder(names.cache_ttl.clone())
+                .with_description(
+                    "max-age of the Cache-Control advertised by each graphQL response in seconds",
+                )
+                .with_unit(Unit::new("seconds"))
+                .init()
+                .labelled(labels),
+            slow_requests: meter
+                .u64_counter(names.slow_requests.clone())
+                .with_description(
+                    "total number of graphQL requests which took longer than the slow request threshold",
+                )
+                .init()
+                .labelled(labels),
+            types_touched: meter
+                .u64_value_recorder(names.types_touched.clone())
+                .with_description(
+                    "number of distinct graphQL types whose fields were resolved by each request",
+                )
+                .init()
+                .labelled(labels),
+            validation_errors: meter
+                .u64_counter(names.validation_errors.clone())
+                .with_description("total number of graphQL documents which failed validation")
+                .init()
+                .labelled(labels),
+            oversized_responses: meter
+                .u64_counter(names.oversized_responses.clone())
+                .with_description(
+                    "total number of graphQL responses which resolved more fields than the response field limit",
+                )
+                .init()
+                .labelled(labels),
+            phase_schedule_delay: meter
+                .u64_value_recorder(names.phase_schedule_delay.clone())
+                .with_description(
+                    "time each phase of a graphQL request waited to be scheduled by the async runtime in microseconds",
+                )
+                .with_unit(Unit::new("microseconds"))
+                .init()
+                .labelled(labels),
+            operation_directive_usage: meter
+                .u64_counter(names.operation_directive_usage.clone())
+                .with_description(
+                    "total number of uses of each directive applied to graphQL operations",
+                )
+                .init()
+                .labelled(labels),
+            batch_size: meter
+                .u64_value_recorder(names.batch_size.clone())
+                .with_description("number of operations in each batch of graphQL requests")
+                .init()
+                .labelled(labels),
+            oversized_batches: meter
+                .u64_counter(names.oversized_batches.clone())
+                .with_description(
+                    "total number of batches of graphQL requests with more operations than the batch size limit",
+                )
+                .init()
+                .labelled(labels),
+            variables_bytes: meter
+                .u64_value_recorder(names.variables_bytes.clone())
+                .with_description(
+                    "size of the variables of each graphQL request serialized as JSON in bytes",
+                )
+                .with_unit(Unit::new("bytes"))
+                .init()
+                .labelled(labels),
+            phase_duration: meter
+                .f64_value_recorder(names.phase_duration.clone())
+                .with_description("duration of each phase of a graphQL request in seconds")
+                .with_unit(Unit::new("seconds"))
+                .init()
+                .labelled(labels),
+            instrumentation_overhead: meter
+                .u64_value_recorder(names.instrumentation_overhead.clone())
+                .with_description("time each graphQL request spent in the extension's own code in microseconds")
+                .with_unit(Unit::new("microseconds"))
+                .init()
+                .labelled(labels),
+            business: config
+                .business_metrics
+                .iter()
+                .map(|spec| (spec.key.clone(), spec.instrument(meter, labels)))
+                .collect(),
+        }
+    }
+
+    /// Records one of the measurements made as each field is resolved
+    pub(crate) fn record_field(
+        &self,
+        instrument: FieldInstrument,
+        value: u64,
+        labels: &[KeyValue],
+    ) {
+        match instrument {
+            FieldInstrument::RequestDuration => self.request_duration.record(value, labels),
+            FieldInstrument::TypeResolveDuration => {
+                self.type_resolve_duration.record(value, labels)
+            }
+            FieldInstrument::ListFieldSize => self.list_field_size.record(value, labels),
+            FieldInstrument::RequestErrors => self.request_errors.add(value, labels),
+            FieldInstrument::FieldErrors => self.field_errors.add(value, labels),
+            FieldInstrument::AuthzRejections => self.authz_rejections.add(value, labels),
+        }
+    }
+
+    /// Records the held back field measurements, with a single call per instrument & set of
+    /// labels
+    pub(crate) fn record_batch(&self, batch: FieldMeasurements) {
+        for ((instrument, _), (labels, values)) in batch
+            .0
+            .into_iter()
+            .flat_map(|(key, bucket)| bucket.into_iter().map(move |group| (key, group)))
+        {
+            match instrument {
+                FieldInstrument::RequestDuration => {
+                    self.request_duration.record_all(&values, &labels)
+                }
+                FieldInstrument::TypeResolveDuration => {
+                    self.type_resolve_duration.record_all(&values, &labels)
+                }
+                FieldInstrument::ListFieldSize => self.list_field_size.record_all(&values, &labels),
+                FieldInstrument::RequestErrors => {
+                    self.request_errors.add(values.iter().sum(), &labels)
+                }
+                FieldInstrument::FieldErrors => self.field_errors.add(values.iter().sum(), &labels),
+                FieldInstrument::AuthzRejections => {
+                    self.authz_rejections.add(values.iter().sum(), &labels)
+                }
+            }
+        }
+    }
+}
+
+/// The instruments created for a config, which are created again whenever the config changes
+/// how they're created (e.g. their names or labels).
+#[derive(Debug, Default)]
+pub(crate) struct InstrumentCache {
+    /// Those of the meter set in the config
+    meter: OnceLock<Arc<Instruments>>,
+    /// Those of the global meter, once a provider has been installed
+    global: OnceLock<Arc<Instruments>>,
+    /// Those of the no-op meter the global provider hands out until one is installed, which
+    /// record nothing whichever no-op meter they come from
+    noop: OnceLock<Arc<Instruments>>,
+}
+
+impl InstrumentCache {
+    /// The instruments of the meter set in the config, created the first time they're used
+    pub(crate) fn meter(&self, meter: &Meter, config: &OpenTelemetryConfig) -> &Arc<Instruments> {
+        self.meter
+            .get_or_init(|| Arc::new(Instruments::new(meter, config)))
+    }
+
+    /// The instruments of the global meter.
+    ///
+    /// Until a provider is installed the global meter is a no-op, whose instruments are shared
+    /// until it's checked again by the next request, so one installed after the schema was built
+    /// still has the metrics recorded with it. Checking only creates a single (no-op) instrument,
+    /// while the instruments of an installed provider are created once, so a provider which is
+    /// replaced by another after recording the first request isn't picked up.
+    pub(crate) fn global(&self, config: &OpenTelemetryConfig) -> &Arc<Instruments> {
+        if let Some(instruments) = self.global.get() {
+            return instruments;
+        }
+        let meter = global::meter(NAME);
+        if is_noop(&meter, &config.metric_names) {
+            return self
+                .noop
+                .get_or_init(|| Arc::new(Instruments::new(&meter, config)));
+        }
+        self.global
+            .get_or_init(|| Arc::new(Instruments::new(&meter, config)))
+    }
+}
+
+/// Whether the meter records nothing, as the meter of the global provider does until one is
+/// installed.
+///
+/// This creates the subscriptions counter, so that it's no different to the instrument the
+/// meter would have created anyway.
+fn is_noop(meter: &Meter, names: &MetricNames) -> bool {
+    meter
+        .u64_counter(names.subscriptions.clone())
+        .with_description(SUBSCRIPTIONS_DESCRIPTION)
+        .init()
+        .measurement(0)
+        .instrument()
+        .as_any()
+        .is::<NoopSyncInstrument>()
+}
+
+/// The instruments a WebSocket connection is recorded with, kept by the connection itself so it
+/// can be recorded as closed once the requests are gone.
+#[derive(Debug)]
+pub(crate) struct WsInstruments {
+    pub(crate) active: Labelled<UpDownCounter<i64>>,
+    pub(crate) messages: Labelled<Counter<u64>>,
+}
+
+/// The instruments which record a measurement for each resolved field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum FieldInstrument {
+    RequestDuration,
+    TypeResolveDuration,
+    ListFieldSize,
+    RequestErrors,
+    FieldErrors,
+    AuthzRejections,
+}
+
+/// The field measurements of an operation, held back to be recorded together once it has
+/// executed.
+///
+/// They're grouped by instrument & labels. The labels themselves can't be hashed, so they're
+/// bucketed by a hash of their stringified values, then compared within the bucket.
+#[derive(Debug, Default)]
+pub(crate) struct FieldMeasurements(HashMap<(FieldInstrument, u64), Vec<LabelledValues>>);
+
+/// The values measured with the same set of labels
+type LabelledValues = (Vec<KeyValue>, Vec<u64>);
+
+impl FieldMeasurements {
+    pub(crate) fn push(&mut self, instrument: FieldInstrument, value: u64, labels: Vec<KeyValue>) {
+        let mut hasher = DefaultHasher::new();
+        for label in &labels {
+            label.key.hash(&mut hasher);
+            label.value.as_str().hash(&mut hasher);
+        }
+        let bucket = self.0.entry((instrument, hasher.finish())).or_default();
+        match bucket.iter_mut().find(|(existing, _)| *existing == labels) {
+            Some((_, values)) => values.push(value),
+            None => bucket.push((labels, vec![value])),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_names_are_valid() {
+        assert!(MetricNames::default().validate().is_ok());
+    }
+
+    #[test]
+    fn names_must_follow_the_instrument_naming_rules() {
+        assert!(is_valid_instrument_name("http_graphql_requests_total"));
+        assert!(is_valid_instrument_name("graphql.request-duration"));
+        assert!(!is_valid_instrument_name(""));
+        assert!(!is_valid_instrument_name("_graphql_requests"));
+        assert!(!is_valid_instrument_name("graphql requests"));
+        assert!(!is_valid_instrument_name(&"a".repeat(64)));
+
+        let names = MetricNames::default().requests("1_requests");
+        assert_eq!(
+            names.validate(),
+            Err(ConfigError::InvalidMetricName("1_requests".to_string()))
+        );
+    }
+
+    #[test]
+    fn names_must_be_unique() {
+        let names = MetricNames::default().subscriptions("graphql_requests");
+        assert_eq!(
+            names.validate(),
+            Err(ConfigError::DuplicateMetricName(
+                "graphql_requests".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn renamed_labels_must_be_valid_and_unique() {
+        let rename = |from: &'static str, to: &'static str| (Key::new(from), Key::new(to));
+        let renames = [rename("query_name", "graphql_operation")];
+        assert!(validate_label_names(&renames, &[]).is_ok());
+        assert_eq!(
+            validate_label_names(&[rename("query_name", "graphql operation")], &[]),
+            Err(ConfigError::InvalidLabelName(
+                "graphql operation".to_string()
+            ))
+        );
+        assert_eq!(
+            validate_label_names(&[rename("query_name", "status")], &[]),
+            Err(ConfigError::DuplicateLabelName("status".to_string()))
+        );
+        let static_labels = [KeyValue::new("graphql_operation", "checkout")];
+        assert!(validate_label_names(&renames, &static_labels).is_err());
+        let swapped = [
+            rename("status", "query_name"),
+            rename("query_name", "status"),
+        ];
+        assert!(validate_label_names(&swapped, &[]).is_ok());
+    }
+
+    #[test]
+    fn business_metrics_must_not_share_a_name_with_the_extensions_instruments() {
+        let names = MetricNames::default();
+        let valid = [BusinessMetricSpec::counter("orders", "shop_orders")];
+        assert!(validate_business_metrics(&valid, &names).is_ok());
+
+        let clashing = [BusinessMetricSpec::counter("orders", "graphql_requests")];
+        assert_eq!(
+            validate_business_metrics(&clashing, &names),
+            Err(ConfigError::DuplicateMetricName(
+                "graphql_requests".to_string()
+            ))
+        );
+        let twice = [
+            BusinessMetricSpec::counter("orders", "shop_orders"),
+            BusinessMetricSpec::value_recorder("order_value", "shop_orders"),
+        ];
+        assert!(validate_business_metrics(&twice, &names).is_err());
+        let invalid = [BusinessMetricSpec::counter("orders", "shop orders")];
+        assert!(validate_business_metrics(&invalid, &names).is_err());
+    }
+}
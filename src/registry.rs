@@ -0,0 +1,221 @@
+//! Recording the request, error & duration metrics into a `prometheus` registry.
+//!
+//! Prometheus requires every series of a metric to have the same label names, whereas the
+//! extension only attaches the labels which apply to each measurement. So each metric is
+//! registered with every label the extension records (along with the static labels), and the
+//! labels a measurement doesn't have are recorded as empty, which Prometheus treats the same as
+//! the label being absent.
+
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use opentelemetry::{Key, KeyValue};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+use crate::metrics::{
+    CoreInstruments, CounterBackend, LabelPolicy, RecorderBackend, FIELD_ERRORS_DESCRIPTION,
+    LABEL_KEYS, REQUESTS_DESCRIPTION, REQUEST_DURATION_DESCRIPTION, REQUEST_ERRORS_DESCRIPTION,
+};
+use crate::{MetricNames, DRY_RUN_TARGET};
+
+impl CoreInstruments {
+    /// Registers the instruments with the registry, failing if any of their names are already
+    /// registered
+    pub(crate) fn register(
+        registry: &Registry,
+        names: &MetricNames,
+        labels: &LabelPolicy,
+    ) -> prometheus::Result<Self> {
+        let columns = LabelColumns::new(labels);
+        let counter = |name: &Cow<'static, str>, help: &str| {
+            let counter = IntCounterVec::new(Opts::new(name.as_ref(), help), &columns.names())?;
+            registry.register(Box::new(counter.clone()))?;
+            Ok::<_, prometheus::Error>(PrometheusCounter {
+                counter,
+                name: name.to_string(),
+                columns: columns.clone(),
+            })
+        };
+        let requests = counter(&names.requests, REQUESTS_DESCRIPTION)?;
+        let request_errors = counter(&names.request_errors, REQUEST_ERRORS_DESCRIPTION)?;
+        let field_errors = counter(&names.field_errors, FIELD_ERRORS_DESCRIPTION)?;
+        let histogram = HistogramVec::new(
+            HistogramOpts::new(
+                names.request_duration.as_ref(),
+                REQUEST_DURATION_DESCRIPTION,
+            )
+            .buckets(prometheus::exponential_buckets(1.0, 2.0, 16)?),
+            &columns.names(),
+        )?;
+        registry.register(Box::new(histogram.clone()))?;
+        Ok(Self {
+            requests: Arc::new(requests),
+            request_errors: Arc::new(request_errors),
+            field_errors: Arc::new(field_errors),
+            request_duration: Arc::new(PrometheusHistogram {
+                histogram,
+                name: names.request_duration.to_string(),
+                columns,
+            }),
+        })
+    }
+}
+
+/// The registry the metrics of
+/// [`OpenTelemetry::recommended_with_prometheus`](crate::OpenTelemetry::recommended_with_prometheus)
+/// are recorded into, for serving them to the Prometheus scraper.
+///
+/// ```rust,ignore
+/// let (extension, exporter) = OpenTelemetry::recommended_with_prometheus();
+/// // in the `/metrics` handler
+/// let body = exporter.render()?;
+/// Response::builder().header("Content-Type", exporter.content_type()).body(body)
+/// ```
+///
+/// Cloning the exporter shares the registry.
+#[derive(Debug, Clone)]
+pub struct PrometheusExporter {
+    registry: Registry,
+}
+
+impl PrometheusExporter {
+    pub(crate) fn new(registry: Registry) -> Self {
+        Self { registry }
+    }
+
+    /// The registry, e.g. to register the host's own metrics alongside the extension's
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// The metrics of the registry in the Prometheus text format
+    pub fn render(&self) -> prometheus::Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+
+    /// The `Content-Type` of the rendered metrics
+    pub fn content_type(&self) -> String {
+        TextEncoder::new().format_type().to_string()
+    }
+}
+
+/// The label names a metric is registered with, and how the labels of each measurement fill them.
+#[derive(Debug, Clone)]
+struct LabelColumns {
+    /// The static labels, which are the first columns of every measurement
+    static_values: Vec<String>,
+    /// The name each column is registered under, static labels first
+    names: Vec<String>,
+    /// The original keys of the labels the extension records, in the order of their columns
+    keys: Vec<Key>,
+    dry_run: bool,
+}
+
+impl LabelColumns {
+    fn new(labels: &LabelPolicy) -> Self {
+        let static_labels = labels.static_labels();
+        Self {
+            static_values: static_labels
+                .iter()
+                .map(|label| label.value.as_str().into_owned())
+                .collect(),
+            names: static_labels
+                .iter()
+                .map(|label| labels.renamed(&label.key))
+                .chain(LABEL_KEYS.iter().map(|key| labels.renamed(key)))
+                .map(|key| key.as_str().to_string())
+                .collect(),
+            keys: LABEL_KEYS.to_vec(),
+            dry_run: labels.dry_run(),
+        }
+    }
+
+    fn names(&self) -> Vec<&str> {
+        self.names.iter().map(String::as_str).collect()
+    }
+
+    /// The value of each column for the labels of a measurement, empty for the labels it doesn't
+    /// have
+    fn values<'a>(&'a self, labels: &'a [KeyValue]) -> Vec<Cow<'a, str>> {
+        let recorded = self.keys.iter().map(|key| {
+            labels
+                .iter()
+                .find(|label| &label.key == key)
+                .map_or(Cow::Borrowed(""), |label| label.value.as_str())
+        });
+        self.static_values
+            .iter()
+            .map(|value| Cow::Borrowed(value.as_str()))
+            .chain(recorded)
+            .collect()
+    }
+
+    /// Runs the closure with the value of each column, unless it's a dry run, in which case the
+    /// measurements are logged instead
+    fn with_values(
+        &self,
+        metric: &str,
+        values: &[u64],
+        labels: &[KeyValue],
+        f: impl FnOnce(&[&str]),
+    ) {
+        if self.dry_run {
+            for &value in values {
+                tracing::debug!(
+                    target: DRY_RUN_TARGET,
+                    metric,
+                    value,
+                    ?labels,
+                    "would have recorded metric"
+                );
+            }
+            return;
+        }
+        let columns = self.values(labels);
+        f(&columns.iter().map(AsRef::as_ref).collect::<Vec<_>>())
+    }
+}
+
+#[derive(Debug)]
+struct PrometheusCounter {
+    counter: IntCounterVec,
+    name: String,
+    columns: LabelColumns,
+}
+
+impl CounterBackend for PrometheusCounter {
+    fn add(&self, value: u64, labels: &[KeyValue]) {
+        self.columns
+            .with_values(&self.name, &[value], labels, |columns| {
+                self.counter.with_label_values(columns).inc_by(value)
+            })
+    }
+}
+
+#[derive(Debug)]
+struct PrometheusHistogram {
+    histogram: HistogramVec,
+    name: String,
+    columns: LabelColumns,
+}
+
+impl RecorderBackend for PrometheusHistogram {
+    fn record(&self, value: u64, labels: &[KeyValue]) {
+        self.record_all(&[value], labels)
+    }
+
+    /// Looks the series up once for all of the values
+    fn record_all(&self, values: &[u64], labels: &[KeyValue]) {
+        self.columns
+            .with_values(&self.name, values, labels, |columns| {
+                let histogram = self.histogram.with_label_values(columns);
+                for &value in values {
+                    histogram.observe(value as f64);
+                }
+            })
+    }
+}
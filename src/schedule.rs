@@ -0,0 +1,85 @@
+//! Measuring how long the phases of a request wait to be scheduled by the async runtime.
+//!
+//! A phase's future only makes progress while the runtime polls it, so on a starved runtime
+//! (e.g. one whose threads are held by blocking work) a phase can sit idle long after it could
+//! have continued. The delay is measured in two parts:
+//!
+//! - from when the phase's span is created until its future is first polled, and
+//! - from each time the future is woken until it's next polled, by wrapping the waker it's
+//!   polled with so that the first wake since the last poll is timestamped.
+//!
+//! The extension polls the future straight after creating the span, so the first part is usually
+//! tiny and the wake-ups are what show a starved runtime. Only the phases which actually wait on
+//! something are woken, and async-graphql parses & validates synchronously, so their delay only
+//! ever covers the first part. The delay also includes any time the request's task is busy
+//! elsewhere before returning to the phase, such as polling other futures it's joined with, so
+//! it's an upper bound on the time spent queued by the runtime. On the other hand timers & I/O
+//! are woken by the runtime's driver, which a starved runtime runs late too, so the time until
+//! such a wake isn't seen at all. The wakes which do show the starvation are those from outside
+//! the runtime's busy threads, such as channels fed by other tasks or `spawn_blocking`.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Wake, Waker};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::Clock;
+
+/// Timestamps the first wake since the future was last polled, before passing it on
+struct TimedWaker {
+    clock: Arc<dyn Clock>,
+    inner: Mutex<Waker>,
+    woken_at: Mutex<Option<Instant>>,
+}
+
+impl Wake for TimedWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken_at.lock().get_or_insert_with(|| self.clock.now());
+        self.inner.lock().wake_by_ref();
+    }
+}
+
+/// Runs the future to completion, also returning the total time it waited to be polled since
+/// `created`
+pub(crate) async fn with_schedule_delay<F: Future>(
+    clock: Arc<dyn Clock>,
+    created: Instant,
+    fut: F,
+) -> (F::Output, Duration) {
+    futures_util::pin_mut!(fut);
+    let mut delay = None;
+    let mut timed: Option<(Arc<TimedWaker>, Waker)> = None;
+    let output = std::future::poll_fn(|cx| {
+        let now = clock.now();
+        let (timed, waker) = timed.get_or_insert_with(|| {
+            let timed = Arc::new(TimedWaker {
+                clock: clock.clone(),
+                inner: Mutex::new(cx.waker().clone()),
+                woken_at: Mutex::new(None),
+            });
+            (timed.clone(), Waker::from(timed))
+        });
+        let waited_since = match delay {
+            None => Some(created),
+            Some(_) => timed.woken_at.lock().take(),
+        };
+        if let Some(since) = waited_since {
+            *delay.get_or_insert(Duration::ZERO) += now.saturating_duration_since(since);
+        }
+        {
+            let mut inner = timed.inner.lock();
+            if !inner.will_wake(cx.waker()) {
+                *inner = cx.waker().clone();
+            }
+        }
+        fut.as_mut().poll(&mut Context::from_waker(waker))
+    })
+    .await;
+    (output, delay.unwrap_or_default())
+}
@@ -0,0 +1,295 @@
+//! Signatures which group operations by their shape.
+//!
+//! Two documents which only differ in their literal values, aliases, the order of their fields or
+//! their whitespace produce the same signature, so (much like Apollo's usage reporting
+//! signatures) they're a way to group the requests for the same operation even when the clients
+//! don't name their operations, without the cardinality of the raw query text.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use async_graphql::parser::types::{
+    Directive, ExecutableDocument, FragmentDefinition, OperationDefinition, Selection,
+    SelectionSet, VariableDefinition,
+};
+use async_graphql::parser::Positioned;
+use async_graphql::{Name, Value as ConstValue};
+
+/// The signature of an argument's value, which may reference variables.
+///
+/// async-graphql doesn't re-export the type of unresolved values, only the method which resolves
+/// their variables, so this can't be a function, and each variable is resolved to a binary value
+/// (which can't be written in a document) holding its name, to tell it apart from the literals.
+macro_rules! argument_signature {
+    ($value:expr) => {{
+        let value = $value
+            .clone()
+            .into_const_with(|variable| {
+                Ok::<_, ()>(ConstValue::Binary(variable.as_bytes().to_vec().into()))
+            })
+            .unwrap_or(ConstValue::Null);
+        value_signature(&value)
+    }};
+}
+
+/// The signature of the operation which would be executed for the operation name, or `None` if
+/// the document doesn't contain that operation. Without an operation name that's the document's
+/// only operation, so a document with several operations has no signature.
+///
+/// The signature is the operation, along with every fragment it spreads, printed with:
+/// - literal numbers & strings replaced by `0` & `""`, and literal lists & objects by `[]` & `{}`
+/// - the aliases dropped
+/// - the selections, arguments, directives, variables & fragments sorted
+/// - the whitespace reduced to the single spaces between selections
+///
+/// e.g. `{ jane: getJane { name id } }` & `{ getJane { id name } }` both have the signature
+/// `query{getJane{id name}}`.
+///
+/// The operation's name & variables are kept, as are booleans, enums & `null`, since they
+/// usually select between a small set of different shapes of response. Hosts with only the
+/// query text can get the document with `async_graphql::parser::parse_query`.
+pub fn query_signature(
+    document: &ExecutableDocument,
+    operation_name: Option<&str>,
+) -> Option<String> {
+    let mut operations = document.operations.iter();
+    let (name, operation) = match operation_name {
+        Some(operation_name) => {
+            operations.find(|(name, _)| name.map(Name::as_str) == Some(operation_name))
+        }
+        // async-graphql rejects a request without an operation name unless there's only one
+        None => operations.next().filter(|_| operations.next().is_none()),
+    }?;
+    let mut fragments = BTreeSet::new();
+    let mut signature = operation_signature(name, &operation.node, &mut fragments);
+    // Fragments can spread other fragments, so the set grows as each one is printed
+    let mut printed = BTreeMap::new();
+    while let Some(fragment) = fragments
+        .iter()
+        .find(|fragment| !printed.contains_key(*fragment))
+        .cloned()
+    {
+        let definition = document
+            .fragments
+            .get(fragment.as_str())
+            .map(|definition| fragment_signature(&fragment, &definition.node, &mut fragments))
+            .unwrap_or_default();
+        printed.insert(fragment, definition);
+    }
+    signature.extend(printed.into_values());
+    Some(signature)
+}
+
+fn operation_signature(
+    name: Option<&Name>,
+    operation: &OperationDefinition,
+    fragments: &mut BTreeSet<String>,
+) -> String {
+    let mut signature = operation.ty.to_string();
+    if let Some(name) = name {
+        let _ = write!(signature, " {}", name);
+    }
+    if !operation.variable_definitions.is_empty() {
+        let mut variables = operation
+            .variable_definitions
+            .iter()
+            .map(|variable| variable_signature(&variable.node))
+            .collect::<Vec<_>>();
+        variables.sort_unstable();
+        let _ = write!(signature, "({})", variables.join(","));
+    }
+    signature.push_str(&directives_signature(&operation.directives));
+    signature.push_str(&selection_set_signature(
+        &operation.selection_set.node,
+        fragments,
+    ));
+    signature
+}
+
+fn fragment_signature(
+    name: &str,
+    fragment: &FragmentDefinition,
+    fragments: &mut BTreeSet<String>,
+) -> String {
+    format!(
+        " fragment {} on {}{}{}",
+        name,
+        fragment.type_condition.node.on.node,
+        directives_signature(&fragment.directives),
+        selection_set_signature(&fragment.selection_set.node, fragments),
+    )
+}
+
+fn variable_signature(variable: &VariableDefinition) -> String {
+    let mut signature = format!("${}:{}", variable.name.node, variable.var_type.node);
+    if let Some(default) = &variable.default_value {
+        let _ = write!(signature, "={}", value_signature(&default.node));
+    }
+    signature.push_str(&directives_signature(&variable.directives));
+    signature
+}
+
+fn selection_set_signature(
+    selection_set: &SelectionSet,
+    fragments: &mut BTreeSet<String>,
+) -> String {
+    if selection_set.items.is_empty() {
+        return String::new();
+    }
+    let mut selections = selection_set
+        .items
+        .iter()
+        .map(|selection| match &selection.node {
+            Selection::Field(field) => {
+                let field = &field.node;
+                let mut arguments = field
+                    .arguments
+                    .iter()
+                    .map(|(name, value)| {
+                        format!("{}:{}", name.node, argument_signature!(value.node))
+                    })
+                    .collect::<Vec<_>>();
+                arguments.sort_unstable();
+                let arguments = match arguments.is_empty() {
+                    true => String::new(),
+                    false => format!("({})", arguments.join(",")),
+                };
+                format!(
+                    "{}{}{}{}",
+                    field.name.node,
+                    arguments,
+                    directives_signature(&field.directives),
+                    selection_set_signature(&field.selection_set.node, fragments),
+                )
+            }
+            Selection::FragmentSpread(spread) => {
+                let spread = &spread.node;
+                fragments.insert(spread.fragment_name.node.to_string());
+                format!(
+                    "...{}{}",
+                    spread.fragment_name.node,
+                    directives_signature(&spread.directives),
+                )
+            }
+            Selection::InlineFragment(fragment) => {
+                let fragment = &fragment.node;
+                let condition = fragment
+                    .type_condition
+                    .as_ref()
+                    .map(|condition| format!(" on {}", condition.node.on.node))
+                    .unwrap_or_default();
+                format!(
+                    "...{}{}{}",
+                    condition,
+                    directives_signature(&fragment.directives),
+                    selection_set_signature(&fragment.selection_set.node, fragments),
+                )
+            }
+        })
+        .collect::<Vec<_>>();
+    selections.sort_unstable();
+    format!("{{{}}}", selections.join(" "))
+}
+
+fn directives_signature(directives: &[Positioned<Directive>]) -> String {
+    let mut directives = directives
+        .iter()
+        .map(|directive| {
+            let mut arguments = directive
+                .node
+                .arguments
+                .iter()
+                .map(|(name, value)| format!("{}:{}", name.node, argument_signature!(value.node)))
+                .collect::<Vec<_>>();
+            arguments.sort_unstable();
+            match arguments.is_empty() {
+                true => format!("@{}", directive.node.name.node),
+                false => format!("@{}({})", directive.node.name.node, arguments.join(",")),
+            }
+        })
+        .collect::<Vec<_>>();
+    directives.sort_unstable();
+    directives.concat()
+}
+
+fn value_signature(value: &ConstValue) -> String {
+    match value {
+        ConstValue::Binary(variable) => format!("${}", String::from_utf8_lossy(variable)),
+        ConstValue::Number(_) => "0".to_string(),
+        ConstValue::String(_) => "\"\"".to_string(),
+        ConstValue::List(_) => "[]".to_string(),
+        ConstValue::Object(_) => "{}".to_string(),
+        ConstValue::Null | ConstValue::Boolean(_) | ConstValue::Enum(_) => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::parser::parse_query;
+
+    use super::*;
+
+    fn signature(query: &str) -> String {
+        query_signature(&parse_query(query).unwrap(), None).unwrap()
+    }
+
+    #[test]
+    fn equivalent_queries_have_the_same_signature() {
+        let expected = signature(
+            r#"query Greet($t: Int!) { greet(name: "jane", times: $t) getJane { id name } }"#,
+        );
+        assert_eq!(
+            expected,
+            r#"query Greet($t:Int!){getJane{id name} greet(name:"",times:$t)}"#
+        );
+        for query in [
+            r#"query Greet($t: Int!) { getJane { name id } greet(times: $t, name: "john") }"#,
+            r#"query Greet($t: Int!) {
+                jane: getJane { person: name id }
+                greeting: greet(name: "", times: $t)
+            }"#,
+        ] {
+            assert_eq!(signature(query), expected);
+        }
+    }
+
+    #[test]
+    fn different_shapes_have_different_signatures() {
+        let base = signature("{ getJane { id name } }");
+        for query in [
+            "{ getJane { id } }",
+            "{ getJane { id name } getJohn { id } }",
+            "query Named { getJane { id name } }",
+            "mutation { getJane { id name } }",
+            "{ getJane { id name @include(if: true) } }",
+        ] {
+            assert_ne!(signature(query), base, "{}", query);
+        }
+        assert_ne!(
+            signature("{ list(order: ASC) }"),
+            signature("{ list(order: DESC) }")
+        );
+    }
+
+    #[test]
+    fn the_signature_includes_the_spread_fragments() {
+        let query = r#"
+            query Jane { getJane { ...Person } }
+            query John { getJohn { ...Named } }
+            fragment Person on Person { id ...Named }
+            fragment Named on Person { name(format: [1, 2]) }
+        "#;
+        let document = parse_query(query).unwrap();
+        assert_eq!(
+            query_signature(&document, Some("Jane")).unwrap(),
+            "query Jane{getJane{...Person}} fragment Named on Person{name(format:[])} \
+             fragment Person on Person{...Named id}"
+        );
+        assert_eq!(
+            query_signature(&document, Some("John")).unwrap(),
+            "query John{getJohn{...Named}} fragment Named on Person{name(format:[])}"
+        );
+        assert_eq!(query_signature(&document, Some("Unknown")), None);
+        assert_eq!(query_signature(&document, None), None);
+    }
+}
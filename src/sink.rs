@@ -0,0 +1,297 @@
+//! Destinations for the Apollo Tracing data collected for each request.
+//!
+//! Once a request has been executed, its tracing data is handed to each of the
+//! [`TracingSink`]s set with [`OpenTelemetryConfig::tracing_sink`](crate::OpenTelemetryConfig::tracing_sink),
+//! as a [`TracingSummary`]. [`ReturnTracingToClient`] & [`LogTracingJson`] are
+//! the destinations behind
+//! [`OpenTelemetryConfig::return_tracing_data_to_client`](crate::OpenTelemetryConfig::return_tracing_data_to_client)
+//! & [`OpenTelemetryConfig::log_tracing_json`](crate::OpenTelemetryConfig::log_tracing_json),
+//! while [`ReturnTracingFor`] returns it to the client for only some of the
+//! operations. Any other destination (e.g. a Kafka topic) can implement the
+//! trait itself.
+//!
+//! async-graphql executes each event of a subscription on its own, so every
+//! event is submitted as a summary of its own, holding the fields resolved for
+//! it, and nothing is kept between the events.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use opentelemetry::trace::{SpanId, TraceId};
+use serde::{Serialize, Serializer};
+use tracing::Span;
+
+use crate::apollo::Trace;
+use crate::types::Metrics;
+use crate::TARGET;
+
+/// A destination for the tracing data of each request.
+///
+/// `submit` is called on the request's task once the request has been
+/// executed, before the response is returned, so a sink which does any I/O
+/// should hand the summary off (e.g. to a channel) rather than do it itself.
+///
+/// ```rust
+/// use std::sync::{mpsc, Arc, Mutex};
+/// use async_graphql_telemetry_extension::{OpenTelemetryConfig, TracingSink, TracingSummary};
+///
+/// #[derive(Debug)]
+/// struct Channel(Mutex<mpsc::Sender<String>>);
+///
+/// impl TracingSink for Channel {
+///     fn submit(&self, summary: TracingSummary) {
+///         if let Ok(json) = summary.to_json() {
+///             let _ = self.0.lock().unwrap().send(json);
+///         }
+///     }
+/// }
+///
+/// let (sender, receiver) = mpsc::channel();
+/// let config = OpenTelemetryConfig::default().tracing_sink(Arc::new(Channel(Mutex::new(sender))));
+/// ```
+pub trait TracingSink: fmt::Debug + Send + Sync {
+    /// Hands over the tracing data of a request which has been executed
+    fn submit(&self, summary: TracingSummary);
+}
+
+/// The tracing data of a single request.
+///
+/// It's serialized in the Apollo Tracing format, the same as the `tracing`
+/// extension of the response. Cloning the summary is cheap, as the clones share
+/// the data.
+#[derive(Clone)]
+pub struct TracingSummary {
+    metrics: Arc<Metrics>,
+    span: Span,
+    operation_name: Option<Arc<str>>,
+    returned: Arc<AtomicBool>,
+}
+
+impl TracingSummary {
+    pub(crate) fn new(metrics: Metrics, span: Span, operation_name: Option<&str>) -> Self {
+        Self {
+            metrics: Arc::new(metrics),
+            span,
+            operation_name: operation_name.map(Arc::from),
+            returned: Default::default(),
+        }
+    }
+
+    /// The name of the operation which was executed, if it has one, whether or not the client
+    /// named it in the request
+    pub fn operation_name(&self) -> Option<&str> {
+        self.operation_name.as_deref()
+    }
+
+    /// When the request started
+    pub fn start_time(&self) -> DateTime<Utc> {
+        self.metrics.start_time
+    }
+
+    /// When the request finished executing
+    pub fn end_time(&self) -> DateTime<Utc> {
+        self.metrics.end_time
+    }
+
+    /// The OpenTelemetry trace id of the request span, if the `tracing-opentelemetry` layer is
+    /// installed
+    pub fn trace_id(&self) -> Option<TraceId> {
+        self.metrics.trace_id
+    }
+
+    /// The OpenTelemetry span id of the request span, if the `tracing-opentelemetry` layer is
+    /// installed
+    pub fn span_id(&self) -> Option<SpanId> {
+        self.metrics.span_id
+    }
+
+    /// The request span, e.g. to parent events about the summary on
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// The timing of each resolved field, in the order they started resolving.
+    ///
+    /// These are the same timings as the tracing data, without having to go through its JSON or
+    /// Apollo's structure, e.g. to feed a latency tracker of the host's own. The timings are only
+    /// populated while per-field recording is enabled, which any tracing sink turns on for every
+    /// request, whether or not [`StageFlags::RESOLVE`](crate::StageFlags::RESOLVE) is set since
+    /// that only controls the field spans. The fields are also only timed while the request's
+    /// instrumentation is on, so a request which exceeded its
+    /// [`instrumentation_budget`](crate::OpenTelemetryConfig::instrumentation_budget) only has
+    /// the timings of the fields resolved until then, and none if it was exceeded before the
+    /// first field.
+    pub fn field_timings(&self) -> impl ExactSizeIterator<Item = FieldTiming<'_>> {
+        self.metrics.resolves.iter().map(|stat| FieldTiming {
+            path: &stat.path,
+            field_name: &stat.field_name,
+            parent_type: &stat.parent_type,
+            return_type: &stat.return_type,
+            start_offset: Duration::from_nanos(stat.start_offset.max(0) as u64),
+            duration: Duration::from_nanos(stat.duration_nanos().unwrap_or_default().max(0) as u64),
+        })
+    }
+
+    /// The field timings in the structure of Apollo Studio's `Trace` report
+    pub fn apollo_trace(&self) -> Trace {
+        Trace::from_metrics(&self.metrics)
+    }
+
+    /// The summary in the Apollo Tracing format, as a single line of JSON
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&*self.metrics)
+    }
+
+    /// Returns the summary to the client, as the `tracing` extension of the response.
+    ///
+    /// This only has an effect while the summary is being submitted, as the response is
+    /// returned straight afterwards.
+    pub fn return_to_client(&self) {
+        self.returned.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether a sink asked for the summary to be returned to the client
+    pub(crate) fn is_returned(&self) -> bool {
+        self.returned.load(Ordering::Relaxed)
+    }
+}
+
+impl Serialize for TracingSummary {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.metrics.serialize(serializer)
+    }
+}
+
+impl fmt::Debug for TracingSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TracingSummary")
+            .field("start_time", &self.metrics.start_time)
+            .field("end_time", &self.metrics.end_time)
+            .field("resolvers", &self.metrics.resolves.len())
+            .finish()
+    }
+}
+
+/// The timing of a single resolved field, see [`TracingSummary::field_timings`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldTiming<'a> {
+    /// The segments of the field's path in the response, including any list indices, e.g.
+    /// `["people", "0", "name"]`
+    pub path: &'a [String],
+    /// The name of the field in the schema, rather than its alias
+    pub field_name: &'a str,
+    /// The type the field belongs to
+    pub parent_type: &'a str,
+    /// The return type of the field, e.g. `[Person!]!`
+    pub return_type: &'a str,
+    /// When the field started resolving, relative to the start of execution
+    pub start_offset: Duration,
+    /// How long the field took to resolve, including its child fields
+    pub duration: Duration,
+}
+
+/// Returns the tracing data to the client, as the `tracing` extension of the response
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReturnTracingToClient;
+
+impl TracingSink for ReturnTracingToClient {
+    fn submit(&self, summary: TracingSummary) {
+        summary.return_to_client();
+    }
+}
+
+/// Returns the tracing data to the client for only some of the requests, e.g. to debug a single
+/// misbehaving operation without returning the tracing data with every response.
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use async_graphql_telemetry_extension::{OpenTelemetryConfig, ReturnTracingFor};
+///
+/// // The tracing data of one in every ten `GetOrders` operations
+/// let config = OpenTelemetryConfig::default()
+///     .tracing_sink(Arc::new(ReturnTracingFor::operations(vec!["GetOrders"]).ratio(0.1)));
+/// ```
+///
+/// The ratio is applied by counting the requests rather than at random, so the requests it
+/// returns the tracing data for are spread evenly, e.g. every fourth request for `0.25`. This is
+/// the per-operation counterpart of
+/// [`OpenTelemetryConfig::return_tracing_data_to_client`](crate::OpenTelemetryConfig::return_tracing_data_to_client),
+/// which returns the tracing data with every response.
+#[derive(Debug, Default)]
+pub struct ReturnTracingFor {
+    operations: Option<HashSet<String>>,
+    ratio: Option<f64>,
+    requests: AtomicU64,
+}
+
+impl ReturnTracingFor {
+    /// Only the operations with one of the names. Anonymous operations never have their tracing
+    /// data returned.
+    pub fn operations<I, S>(names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            operations: Some(names.into_iter().map(Into::into).collect()),
+            ..Default::default()
+        }
+    }
+
+    /// Only a fraction of the requests, from `0.0` (none) to `1.0` (all of them). Alongside
+    /// [`operations`](Self::operations) this is the fraction of the named operations.
+    pub fn ratio(mut self, ratio: f64) -> Self {
+        self.ratio = Some(ratio.clamp(0.0, 1.0));
+        self
+    }
+
+    fn applies(&self, summary: &TracingSummary) -> bool {
+        if let Some(operations) = &self.operations {
+            match summary.operation_name() {
+                Some(name) if operations.contains(name) => {}
+                _ => return false,
+            }
+        }
+        match self.ratio {
+            // The request is sampled whenever the count passes another whole multiple of the ratio
+            Some(ratio) => {
+                let seen = self.requests.fetch_add(1, Ordering::Relaxed) as f64;
+                ((seen + 1.0) * ratio).floor() > (seen * ratio).floor()
+            }
+            None => true,
+        }
+    }
+}
+
+impl TracingSink for ReturnTracingFor {
+    fn submit(&self, summary: TracingSummary) {
+        if self.applies(&summary) {
+            summary.return_to_client();
+        }
+    }
+}
+
+/// Logs the tracing data as a single line of JSON, in an `INFO` "tracing summary" event on the
+/// request span
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogTracingJson;
+
+impl TracingSink for LogTracingJson {
+    fn submit(&self, summary: TracingSummary) {
+        match summary.to_json() {
+            Ok(json) => tracing::info!(
+                target: TARGET,
+                parent: summary.span(),
+                tracing = %json,
+                "tracing summary",
+            ),
+            Err(err) => {
+                tracing::warn!(target: TARGET, error = %err, "failed to serialize tracing data")
+            }
+        }
+    }
+}
@@ -0,0 +1,432 @@
+//! Helpers for asserting on the spans & metrics produced by the extension in tests.
+//!
+//! Enabled with the `testing` feature. The [`SpanCollector`] is a
+//! [`tracing_subscriber::Layer`] which keeps every span created while it's
+//! installed in memory, so that tests can assert on the shape of the trace:
+//!
+//! ```rust,ignore
+//! use async_graphql_telemetry_extension::testing::SpanCollector;
+//! use tracing_subscriber::layer::SubscriberExt;
+//!
+//! let collector = SpanCollector::default();
+//! let _guard = tracing::subscriber::set_default(
+//!     tracing_subscriber::registry().with(collector.clone()),
+//! );
+//!
+//! schema.execute("{ getJane { details { name } } }").await;
+//!
+//! let spans = collector.spans();
+//! let field = spans.field("getJane.details.name").unwrap();
+//! assert!(spans.is_descendant_of(field, spans.span("execute").unwrap()));
+//! assert!(spans.is_descendant_of(spans.span("execute").unwrap(), spans.span("request").unwrap()));
+//! ```
+//!
+//! Spans created through the OpenTelemetry API directly can be collected by
+//! exporting them to a [`SpanExportCollector`].
+//!
+//! Similarly the `MetricsCollector` keeps the metrics recorded against its
+//! meter in memory, when the `metrics` feature is enabled:
+//!
+//! ```rust,ignore
+//! let metrics = MetricsCollector::default();
+//! let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+//!     .extension(OpenTelemetry)
+//!     .data(OpenTelemetryConfig::default().meter(metrics.meter()))
+//!     .finish();
+//!
+//! schema.execute("{ getJane { id } }").await;
+//! assert_eq!(metrics.sum("graphql_requests"), 1.0);
+//! ```
+//!
+//! The durations are made exact by measuring them with a [`MockClock`], which
+//! only moves when it's advanced.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+#[cfg(feature = "metrics")]
+use opentelemetry::metrics::{Meter, MeterProvider};
+#[cfg(feature = "metrics")]
+use opentelemetry::sdk::export::metrics::{CheckpointSet, Count, Sum};
+use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+#[cfg(feature = "metrics")]
+use opentelemetry::sdk::metrics::aggregators::{ArrayAggregator, SumAggregator};
+#[cfg(feature = "metrics")]
+use opentelemetry::sdk::metrics::controllers::{self, PullController};
+#[cfg(feature = "metrics")]
+use opentelemetry::sdk::metrics::selectors::simple::Selector;
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::Clock;
+#[cfg(feature = "metrics")]
+use crate::Temporality;
+
+/// A span recorded by the [`SpanCollector`].
+#[derive(Debug, Clone)]
+pub struct CollectedSpan {
+    /// The name the span was created with, e.g. `request` or `field`
+    pub name: &'static str,
+    /// The span's fields, formatted with their `Debug` implementation
+    pub fields: HashMap<String, String>,
+    index: usize,
+    parent: Option<usize>,
+}
+
+impl CollectedSpan {
+    /// The value of one of the span's fields
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(String::as_str)
+    }
+}
+
+/// The spans recorded by a [`SpanCollector`], in the order they were created.
+#[derive(Debug, Clone)]
+pub struct SpanTree {
+    spans: Vec<CollectedSpan>,
+    events: Vec<CollectedEvent>,
+}
+
+impl SpanTree {
+    /// Every span which was recorded
+    pub fn all(&self) -> &[CollectedSpan] {
+        &self.spans
+    }
+
+    /// Every event which was recorded
+    pub fn events(&self) -> &[CollectedEvent] {
+        &self.events
+    }
+
+    /// The span the event was emitted within, if there was one
+    pub fn event_span(&self, event: &CollectedEvent) -> Option<&CollectedSpan> {
+        event.span.map(|index| &self.spans[index])
+    }
+
+    /// The first span with the given name
+    pub fn span(&self, name: &str) -> Option<&CollectedSpan> {
+        self.spans.iter().find(|span| span.name == name)
+    }
+
+    /// Every span with the given name
+    pub fn spans_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a CollectedSpan> {
+        self.spans.iter().filter(move |span| span.name == name)
+    }
+
+    /// The `field` span for the given path, e.g. `getJane.details.name`
+    pub fn field(&self, path: &str) -> Option<&CollectedSpan> {
+        self.spans_named("field")
+            .find(|span| span.field("path") == Some(path))
+    }
+
+    /// The parent of the span, if it has one
+    pub fn parent(&self, span: &CollectedSpan) -> Option<&CollectedSpan> {
+        span.parent.map(|index| &self.spans[index])
+    }
+
+    /// The children of the span
+    pub fn children<'a>(
+        &'a self,
+        span: &'a CollectedSpan,
+    ) -> impl Iterator<Item = &'a CollectedSpan> {
+        self.spans
+            .iter()
+            .filter(move |child| child.parent == Some(span.index))
+    }
+
+    /// Whether `ancestor` is the parent of the span, or of any of its parents
+    pub fn is_descendant_of(&self, span: &CollectedSpan, ancestor: &CollectedSpan) -> bool {
+        let mut current = self.parent(span);
+        while let Some(parent) = current {
+            if parent.index == ancestor.index {
+                return true;
+            }
+            current = self.parent(parent);
+        }
+        false
+    }
+}
+
+/// An event recorded by the [`SpanCollector`].
+#[derive(Debug, Clone)]
+pub struct CollectedEvent {
+    /// The level the event was emitted at
+    pub level: Level,
+    /// The event's fields, including its `message`, formatted with their `Debug` implementation
+    pub fields: HashMap<String, String>,
+    span: Option<usize>,
+}
+
+impl CollectedEvent {
+    /// The value of one of the event's fields
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(String::as_str)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    spans: Vec<CollectedSpan>,
+    events: Vec<CollectedEvent>,
+    // Span ids are reused once a span closes, so they're mapped to the index of
+    // the most recent span which was created with that id.
+    ids: HashMap<u64, usize>,
+}
+
+/// A [`Layer`] which records every span created while it's installed.
+///
+/// Cloning the collector shares the recorded spans, so a clone can be installed
+/// in the subscriber while the original is kept to inspect them afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct SpanCollector {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl SpanCollector {
+    /// A snapshot of the spans recorded so far
+    pub fn spans(&self) -> SpanTree {
+        let inner = self.inner.lock().unwrap();
+        SpanTree {
+            spans: inner.spans.clone(),
+            events: inner.events.clone(),
+        }
+    }
+}
+
+struct FieldVisitor<'a>(&'a mut HashMap<String, String>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
+
+impl<S> Layer<S> for SpanCollector
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = HashMap::new();
+        attrs.record(&mut FieldVisitor(&mut fields));
+
+        let parent_id = ctx
+            .span(id)
+            .and_then(|span| span.parent().map(|parent| parent.id()));
+
+        let mut inner = self.inner.lock().unwrap();
+        let parent = parent_id.and_then(|parent| inner.ids.get(&parent.into_u64()).copied());
+        let index = inner.spans.len();
+        inner.spans.push(CollectedSpan {
+            name: attrs.metadata().name(),
+            fields,
+            index,
+            parent,
+        });
+        inner.ids.insert(id.into_u64(), index);
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = HashMap::new();
+        event.record(&mut FieldVisitor(&mut fields));
+
+        let span_id = ctx.event_span(event).map(|span| span.id());
+
+        let mut inner = self.inner.lock().unwrap();
+        let span = span_id.and_then(|span| inner.ids.get(&span.into_u64()).copied());
+        inner.events.push(CollectedEvent {
+            level: *event.metadata().level(),
+            fields,
+            span,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(index) = inner.ids.get(&id.into_u64()).copied() {
+            values.record(&mut FieldVisitor(&mut inner.spans[index].fields));
+        }
+    }
+}
+
+/// A metric recorded by the [`MetricsCollector`], one per distinct label set.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollectedMetric {
+    /// The name of the instrument
+    pub name: String,
+    /// The labels the values were recorded with
+    pub labels: HashMap<String, String>,
+    /// The sum of every value recorded
+    pub sum: f64,
+    /// The number of values recorded, only available for value recorders
+    pub count: Option<u64>,
+}
+
+/// An in-memory meter provider which keeps every metric recorded against it.
+///
+/// Cloning the collector shares the recorded metrics.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone)]
+pub struct MetricsCollector {
+    controller: Arc<Mutex<PullController>>,
+    temporality: Temporality,
+}
+
+#[cfg(feature = "metrics")]
+impl Default for MetricsCollector {
+    fn default() -> Self {
+        Self::with_temporality(Temporality::Cumulative)
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl MetricsCollector {
+    /// A collector which exports with the given temporality, so with
+    /// [`Temporality::Delta`] each snapshot only holds what was recorded since
+    /// the previous one
+    pub fn with_temporality(temporality: Temporality) -> Self {
+        let controller = controllers::pull(
+            Box::new(Selector::Exact),
+            Box::new(temporality.export_kind_selector()),
+        )
+        .with_cache_period(Duration::from_secs(0))
+        .with_memory(temporality == Temporality::Cumulative)
+        .build();
+        Self {
+            controller: Arc::new(Mutex::new(controller)),
+            temporality,
+        }
+    }
+
+    /// The meter provider, e.g. to install as the global provider
+    pub fn provider(&self) -> impl MeterProvider {
+        self.controller.lock().unwrap().provider()
+    }
+
+    /// A meter which records into this collector
+    pub fn meter(&self) -> Meter {
+        self.provider().meter(crate::NAME, None)
+    }
+
+    /// A snapshot of the metrics recorded so far
+    pub fn metrics(&self) -> Vec<CollectedMetric> {
+        let mut controller = self.controller.lock().unwrap();
+        let mut metrics = Vec::new();
+        controller.collect().expect("failed to collect metrics");
+        controller
+            .try_for_each(&self.temporality.export_kind_selector(), &mut |record| {
+                let descriptor = record.descriptor();
+                let kind = descriptor.number_kind();
+                let (sum, count) = match record.aggregator().map(|agg| agg.as_any()) {
+                    Some(agg) if agg.is::<SumAggregator>() => {
+                        let agg = agg.downcast_ref::<SumAggregator>().unwrap();
+                        (agg.sum()?.to_f64(kind), None)
+                    }
+                    Some(agg) if agg.is::<ArrayAggregator>() => {
+                        let agg = agg.downcast_ref::<ArrayAggregator>().unwrap();
+                        (agg.sum()?.to_f64(kind), Some(agg.count()?))
+                    }
+                    _ => return Ok(()),
+                };
+                metrics.push(CollectedMetric {
+                    name: descriptor.name().to_string(),
+                    labels: record
+                        .labels()
+                        .iter()
+                        .map(|(key, value)| (key.as_str().to_string(), value.to_string()))
+                        .collect(),
+                    sum,
+                    count,
+                });
+                Ok(())
+            })
+            .expect("failed to read metrics");
+        metrics
+    }
+
+    /// Every recorded label set of the named instrument
+    pub fn named(&self, name: &str) -> Vec<CollectedMetric> {
+        self.metrics()
+            .into_iter()
+            .filter(|metric| metric.name == name)
+            .collect()
+    }
+
+    /// The sum of the values recorded by the named instrument, across all of its labels
+    pub fn sum(&self, name: &str) -> f64 {
+        self.named(name).iter().map(|metric| metric.sum).sum()
+    }
+}
+
+/// A [`SpanExporter`] which keeps every OpenTelemetry span exported to it in memory.
+///
+/// Cloning the collector shares the exported spans.
+#[derive(Debug, Clone, Default)]
+pub struct SpanExportCollector {
+    spans: Arc<Mutex<Vec<SpanData>>>,
+}
+
+impl SpanExportCollector {
+    /// The spans exported so far
+    pub fn spans(&self) -> Vec<SpanData> {
+        self.spans.lock().unwrap().clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl SpanExporter for SpanExportCollector {
+    async fn export(&mut self, batch: Vec<SpanData>) -> ExportResult {
+        self.spans.lock().unwrap().extend(batch);
+        Ok(())
+    }
+}
+
+/// A [`Clock`] which only moves when it's advanced, so the durations the extension records are
+/// exactly the sum of the advances made while they were measured.
+///
+/// Cloning the clock shares its time, so a resolver can advance the same clock the extension
+/// measures with, e.g. by adding a clone to the schema's data.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<(Instant, DateTime<Utc>)>>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self {
+            now: Arc::new(Mutex::new((Instant::now(), Utc::now()))),
+        }
+    }
+}
+
+impl MockClock {
+    /// Moves the clock forward
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        now.0 += duration;
+        now.1 += chrono::Duration::from_std(duration).expect("duration out of range");
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.now.lock().unwrap().0
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        self.now.lock().unwrap().1
+    }
+}
@@ -0,0 +1,622 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+
+#[cfg(feature = "metrics")]
+use async_graphql::parser::types::{Directive, Field};
+#[cfg(feature = "metrics")]
+use async_graphql::parser::Positioned;
+use async_graphql::Pos;
+#[cfg(feature = "metrics")]
+use async_graphql::{ServerError, Value as ConstValue};
+
+use async_graphql::parser::types::{
+    ExecutableDocument, OperationDefinition, OperationType, Selection, SelectionSet,
+};
+use chrono::{DateTime, Utc};
+use opentelemetry::trace::{SpanId, TraceId};
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use tracing::Span;
+
+use crate::bridge::bridged_span_context;
+#[cfg(feature = "metrics")]
+use crate::metrics::FieldMeasurements;
+use crate::{HostContext, OpenTelemetryConfig};
+
+/// The timing information for a single resolved field, serialized in the
+/// format expected by Apollo Tracing.
+#[derive(Clone)]
+pub(crate) struct ResolveStat {
+    pub(crate) path: Vec<String>,
+    pub(crate) field_name: String,
+    pub(crate) parent_type: String,
+    pub(crate) return_type: String,
+    pub(crate) start_time: DateTime<Utc>,
+    pub(crate) end_time: DateTime<Utc>,
+    pub(crate) start_offset: i64,
+    /// Where the field is selected in the query, when it's recorded
+    pub(crate) location: Option<Pos>,
+    /// The constant complexity cost of the field in the schema, when it's recorded
+    pub(crate) complexity_cost: Option<usize>,
+}
+
+impl ResolveStat {
+    /// The path of the field this field was resolved within, skipping over any list indices
+    pub(crate) fn parent_path(&self) -> Option<String> {
+        let mut parent = &self.path[..self.path.len().saturating_sub(1)];
+        while let Some((last, rest)) = parent.split_last() {
+            if last.parse::<usize>().is_err() {
+                break;
+            }
+            parent = rest;
+        }
+        if parent.is_empty() {
+            None
+        } else {
+            Some(parent.join("."))
+        }
+    }
+
+    pub(crate) fn duration_nanos(&self) -> Option<i64> {
+        (self.end_time - self.start_time).num_nanoseconds()
+    }
+}
+
+/// The duration of each field less the durations of its child fields, in nanoseconds.
+#[cfg(feature = "metrics")]
+pub(crate) fn self_durations(stats: &[ResolveStat]) -> Vec<(&ResolveStat, i64)> {
+    let mut children = HashMap::<String, i64>::new();
+    for stat in stats {
+        if let Some(parent) = stat.parent_path() {
+            *children.entry(parent).or_default() += stat.duration_nanos().unwrap_or_default();
+        }
+    }
+    stats
+        .iter()
+        .map(|stat| {
+            let children = children.get(&stat.path.join(".")).copied();
+            let duration = stat.duration_nanos().unwrap_or_default();
+            (stat, (duration - children.unwrap_or_default()).max(0))
+        })
+        .collect()
+}
+
+impl Serialize for ResolveStat {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("path", &self.path)?;
+        map.serialize_entry("fieldName", &self.field_name)?;
+        map.serialize_entry("parentType", &self.parent_type)?;
+        map.serialize_entry("returnType", &self.return_type)?;
+        map.serialize_entry("startOffset", &self.start_offset)?;
+        map.serialize_entry("duration", &self.duration_nanos())?;
+        map.end()
+    }
+}
+
+/// The tracing data collected over the course of a single request.
+///
+/// When serialized this produces the `tracing` block of the Apollo Tracing
+/// format, optionally extended with the OpenTelemetry `traceId` & `spanId`
+/// of the root span so that clients can pivot from the inline timings to the
+/// distributed trace.
+pub(crate) struct Metrics {
+    pub(crate) start_time: DateTime<Utc>,
+    pub(crate) end_time: DateTime<Utc>,
+    pub(crate) resolves: Vec<ResolveStat>,
+    pub(crate) trace_id: Option<TraceId>,
+    pub(crate) span_id: Option<SpanId>,
+}
+
+impl Metrics {
+    /// The tracing data of a request starting at `now`
+    pub(crate) fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            start_time: now,
+            end_time: now,
+            resolves: Default::default(),
+            trace_id: None,
+            span_id: None,
+        }
+    }
+
+    /// Records the OpenTelemetry ids of the provided span.
+    ///
+    /// If the `tracing-opentelemetry` bridge isn't active then the span has no
+    /// valid OpenTelemetry context, in which case both ids are left unset.
+    pub(crate) fn set_span_context(&mut self, span: &Span) {
+        if let Some(span_context) = bridged_span_context(span) {
+            self.trace_id = Some(span_context.trace_id());
+            self.span_id = Some(span_context.span_id());
+        }
+    }
+}
+
+impl Serialize for Metrics {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("version", &1)?;
+        map.serialize_entry("startTime", &self.start_time.to_rfc3339())?;
+        map.serialize_entry("endTime", &self.end_time.to_rfc3339())?;
+        map.serialize_entry(
+            "duration",
+            &(self.end_time - self.start_time).num_nanoseconds(),
+        )?;
+        if let Some(trace_id) = self.trace_id {
+            map.serialize_entry("traceId", &trace_id.to_hex())?;
+        }
+        if let Some(span_id) = self.span_id {
+            map.serialize_entry("spanId", &span_id.to_hex())?;
+        }
+        map.serialize_entry("execution", &Execution(&self.resolves))?;
+        map.end()
+    }
+}
+
+struct Execution<'a>(&'a [ResolveStat]);
+
+impl Serialize for Execution<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry("resolvers", self.0)?;
+        map.end()
+    }
+}
+
+/// The state built up over the course of a single request, shared between the extension's hooks.
+///
+/// It's kept behind a single lock, which each field takes once before it resolves and once
+/// after, and a field which errors once more in between, for the metrics & log limit of its
+/// error. The exceptions are the counters & flags which are updated by every field without
+/// needing anything else from the request (the resolve depth & field counts, the resolve
+/// sequence, the instrumentation budget & overhead, and the degraded & upstream-unsampled
+/// flags), which are atomics on the extension instead, so that updating them never waits on the
+/// lock.
+pub(crate) struct RequestState {
+    /// When the request started
+    pub(crate) start: std::time::Instant,
+    pub(crate) metrics: Metrics,
+    /// The buffered field timings, keyed by path, when tail sampling the field spans
+    pub(crate) fields: BTreeMap<String, ResolveStat>,
+    pub(crate) operations: Operations,
+    /// Where each field is selected in the query, when the locations are recorded
+    pub(crate) field_locations: FieldLocations,
+    /// The kind of the operation being executed, once execution has started
+    pub(crate) operation_kind: Option<OperationType>,
+    pub(crate) root_span: Span,
+    /// The data the host attached to the request, once it's been prepared
+    pub(crate) host: HostContext,
+    /// The bounded schema version, from the request or the config
+    #[cfg(feature = "metrics")]
+    pub(crate) schema_version: Option<String>,
+    /// Whether the request's fields were (or will be) recorded on its trace
+    pub(crate) sampled: bool,
+    /// The number of times each field error message has been seen, when the error logs are
+    /// limited
+    pub(crate) error_logs: HashMap<String, usize>,
+    /// The number of variables sent with the request
+    #[cfg(feature = "metrics")]
+    pub(crate) variables_count: usize,
+    /// Where the variables are used in the document, when recording the coercion errors
+    #[cfg(feature = "metrics")]
+    pub(crate) variable_uses: VariableUses,
+    /// The name of the operation the request asked to execute
+    pub(crate) operation_name: Option<String>,
+    /// The signature of the operation, when it's recorded
+    pub(crate) query_signature: Option<String>,
+    /// The field measurements held back until the operation has executed, when they're batched
+    #[cfg(feature = "metrics")]
+    pub(crate) field_measurements: Option<FieldMeasurements>,
+    /// The distinct parent types of the fields resolved by the operation, when they're recorded
+    #[cfg(feature = "metrics")]
+    pub(crate) types_touched: HashSet<String>,
+    /// The complexity of the operation, as estimated while validating it
+    pub(crate) estimated_complexity: Option<usize>,
+    /// The host's id for the request, recorded on each of its spans
+    pub(crate) request_id: Option<Arc<str>>,
+}
+
+impl RequestState {
+    /// The state of a request starting now, by the config's clock
+    pub(crate) fn new(config: &OpenTelemetryConfig) -> Self {
+        Self {
+            start: config.now(),
+            metrics: Metrics::new(config.utc_now()),
+            fields: Default::default(),
+            operations: Default::default(),
+            field_locations: Default::default(),
+            operation_kind: None,
+            root_span: Span::none(),
+            host: Default::default(),
+            #[cfg(feature = "metrics")]
+            schema_version: None,
+            sampled: false,
+            error_logs: Default::default(),
+            #[cfg(feature = "metrics")]
+            variables_count: 0,
+            #[cfg(feature = "metrics")]
+            variable_uses: Default::default(),
+            operation_name: None,
+            query_signature: None,
+            #[cfg(feature = "metrics")]
+            field_measurements: None,
+            #[cfg(feature = "metrics")]
+            types_touched: Default::default(),
+            estimated_complexity: None,
+            request_id: None,
+        }
+    }
+
+    /// Whether the operation being executed is a mutation
+    pub(crate) fn is_write(&self) -> Option<bool> {
+        self.operation_kind
+            .map(|kind| kind == OperationType::Mutation)
+    }
+}
+
+/// The name, kind & root selection width of each of the operations in a parsed document.
+#[derive(Debug, Default)]
+pub(crate) struct Operations(Vec<(Option<String>, OperationType, Option<usize>)>);
+
+impl Operations {
+    /// The operations of the document, along with their root selection widths if they're needed,
+    /// as working them out walks each operation's selections
+    pub(crate) fn from_document(document: &ExecutableDocument, root_widths: bool) -> Self {
+        Self(
+            document
+                .operations
+                .iter()
+                .map(|(name, operation)| {
+                    let width = root_widths.then(|| {
+                        let mut fields = HashSet::new();
+                        root_fields(
+                            document,
+                            &operation.node.selection_set.node,
+                            &mut fields,
+                            &mut HashSet::new(),
+                        );
+                        fields.len()
+                    });
+                    (name.map(|name| name.to_string()), operation.node.ty, width)
+                })
+                .collect(),
+        )
+    }
+
+    /// The kind of the operation which is being executed
+    pub(crate) fn kind(&self, operation_name: Option<&str>) -> Option<OperationType> {
+        self.find(operation_name).map(|(_, kind, _)| *kind)
+    }
+
+    /// The name of the operation which is being executed, which may not have been named by the
+    /// request
+    pub(crate) fn name(&self, operation_name: Option<&str>) -> Option<&str> {
+        self.find(operation_name)
+            .and_then(|(name, _, _)| name.as_deref())
+    }
+
+    /// The number of distinct top-level fields selected by the operation which is being executed
+    pub(crate) fn root_selection_width(&self, operation_name: Option<&str>) -> Option<usize> {
+        self.find(operation_name).and_then(|(_, _, width)| *width)
+    }
+
+    /// Whether the request's operation name selects one of the operations, without which
+    /// async-graphql won't execute the document
+    pub(crate) fn selects(&self, operation_name: Option<&str>) -> bool {
+        self.find(operation_name).is_some()
+    }
+
+    fn find(
+        &self,
+        operation_name: Option<&str>,
+    ) -> Option<&(Option<String>, OperationType, Option<usize>)> {
+        match operation_name {
+            Some(operation_name) => self
+                .0
+                .iter()
+                .find(|(name, _, _)| name.as_deref() == Some(operation_name)),
+            // A document with a single operation can be executed without naming it
+            None if self.0.len() == 1 => self.0.first(),
+            None => None,
+        }
+    }
+}
+
+/// Where each of the fields of the operations in a parsed document is selected, keyed by the
+/// field's path without any list indices (e.g. `people.name`).
+///
+/// A field selected more than once under the same path (e.g. through two fragments) is located
+/// at its first selection.
+#[derive(Debug, Default)]
+pub(crate) struct FieldLocations(Vec<(Option<String>, HashMap<String, Pos>)>);
+
+impl FieldLocations {
+    pub(crate) fn from_document(document: &ExecutableDocument) -> Self {
+        Self(
+            document
+                .operations
+                .iter()
+                .map(|(name, operation)| {
+                    let mut locations = HashMap::new();
+                    field_locations(
+                        document,
+                        &operation.node.selection_set.node,
+                        "",
+                        &mut locations,
+                        &mut HashSet::new(),
+                    );
+                    (name.map(|name| name.to_string()), locations)
+                })
+                .collect(),
+        )
+    }
+
+    /// Where the field at the path is selected in the operation which is being executed, which
+    /// without an operation name is the document's only operation
+    pub(crate) fn get(&self, operation_name: Option<&str>, path: &str) -> Option<Pos> {
+        let operation = match operation_name {
+            Some(operation_name) => self
+                .0
+                .iter()
+                .find(|(name, _)| name.as_deref() == Some(operation_name)),
+            None if self.0.len() == 1 => self.0.first(),
+            None => None,
+        };
+        operation
+            .and_then(|(_, locations)| locations.get(path))
+            .copied()
+    }
+}
+
+/// The names of the directives applied to the operation being executed, e.g. `live` for
+/// `query @live { .. }`, in the order they're applied.
+pub(crate) fn operation_directives(
+    document: &ExecutableDocument,
+    operation_name: Option<&str>,
+) -> Vec<String> {
+    find_operation(document, operation_name)
+        .map(|operation| {
+            operation
+                .directives
+                .iter()
+                .map(|directive| directive.node.name.node.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The names of the fragments spread by the operation being executed, including those spread by
+/// its fragments.
+pub(crate) fn operation_fragments<'a>(
+    document: &'a ExecutableDocument,
+    operation_name: Option<&str>,
+) -> BTreeSet<&'a str> {
+    let mut fragments = BTreeSet::new();
+    if let Some(operation) = find_operation(document, operation_name) {
+        fragment_spreads(document, &operation.selection_set.node, &mut fragments);
+    }
+    fragments
+}
+
+/// The operation being executed, which may not have been named by the request
+fn find_operation<'a>(
+    document: &'a ExecutableDocument,
+    operation_name: Option<&str>,
+) -> Option<&'a OperationDefinition> {
+    let mut operations = document.operations.iter();
+    let operation = match operation_name {
+        Some(operation_name) => {
+            operations.find(|(name, _)| name.map(|name| name.as_str()) == Some(operation_name))
+        }
+        None => operations.next().filter(|_| operations.next().is_none()),
+    };
+    operation.map(|(_, operation)| &operation.node)
+}
+
+/// Collects the fragments spread within the selection set, at any depth.
+///
+/// The document hasn't been validated yet, so the fragments already collected aren't followed
+/// again in case the fragments are cyclic.
+fn fragment_spreads<'a>(
+    document: &'a ExecutableDocument,
+    selection_set: &'a SelectionSet,
+    fragments: &mut BTreeSet<&'a str>,
+) {
+    for selection in &selection_set.items {
+        match &selection.node {
+            Selection::Field(field) => {
+                fragment_spreads(document, &field.node.selection_set.node, fragments)
+            }
+            Selection::InlineFragment(fragment) => {
+                fragment_spreads(document, &fragment.node.selection_set.node, fragments)
+            }
+            Selection::FragmentSpread(spread) => {
+                let name = spread.node.fragment_name.node.as_str();
+                if let Some(fragment) = document.fragments.get(name) {
+                    if fragments.insert(name) {
+                        fragment_spreads(document, &fragment.node.selection_set.node, fragments);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Records the position of each field in the selection set, and those of the fields selected
+/// through fragments.
+///
+/// The document hasn't been validated yet, so the fragments being spread are tracked in case the
+/// fragments are cyclic.
+fn field_locations<'a>(
+    document: &'a ExecutableDocument,
+    selection_set: &'a SelectionSet,
+    prefix: &str,
+    locations: &mut HashMap<String, Pos>,
+    spreading: &mut HashSet<&'a str>,
+) {
+    for selection in &selection_set.items {
+        match &selection.node {
+            Selection::Field(field) => {
+                let key = field.node.response_key().node.as_str();
+                let path = match prefix.is_empty() {
+                    true => key.to_string(),
+                    false => format!("{}.{}", prefix, key),
+                };
+                field_locations(
+                    document,
+                    &field.node.selection_set.node,
+                    &path,
+                    locations,
+                    spreading,
+                );
+                locations.entry(path).or_insert(field.pos);
+            }
+            Selection::InlineFragment(fragment) => field_locations(
+                document,
+                &fragment.node.selection_set.node,
+                prefix,
+                locations,
+                spreading,
+            ),
+            Selection::FragmentSpread(spread) => {
+                let name = spread.node.fragment_name.node.as_str();
+                if let Some(fragment) = document.fragments.get(name) {
+                    if spreading.insert(name) {
+                        field_locations(
+                            document,
+                            &fragment.node.selection_set.node,
+                            prefix,
+                            locations,
+                            spreading,
+                        );
+                        spreading.remove(name);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The variables referenced by each of the arguments in a parsed document, keyed by the position
+/// of the argument, so that the validation errors caused by the variables' values can be told
+/// apart from those caused by the query itself.
+///
+/// Variable definitions are keyed by their own position, referencing just their variable, as
+/// their default values are validated too.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Default)]
+pub(crate) struct VariableUses(HashMap<Pos, Vec<String>>);
+
+#[cfg(feature = "metrics")]
+impl VariableUses {
+    pub(crate) fn from_document(document: &ExecutableDocument) -> Self {
+        let mut uses = HashMap::new();
+        for (_, operation) in document.operations.iter() {
+            for definition in &operation.node.variable_definitions {
+                uses.insert(definition.pos, vec![definition.node.name.node.to_string()]);
+            }
+            directive_uses(&operation.node.directives, &mut uses);
+            selection_uses(&operation.node.selection_set.node, &mut uses);
+        }
+        for fragment in document.fragments.values() {
+            directive_uses(&fragment.node.directives, &mut uses);
+            selection_uses(&fragment.node.selection_set.node, &mut uses);
+        }
+        uses.retain(|_, variables| !variables.is_empty());
+        Self(uses)
+    }
+
+    /// The variables whose values caused the validation error, if it's a coercion error
+    pub(crate) fn coercion_error(&self, err: &ServerError) -> Option<&[String]> {
+        if !err.message.starts_with("Invalid value for argument")
+            && !err
+                .message
+                .starts_with("Invalid default value for argument")
+        {
+            return None;
+        }
+        err.locations
+            .iter()
+            .find_map(|pos| self.0.get(pos))
+            .map(Vec::as_slice)
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn selection_uses(selection_set: &SelectionSet, uses: &mut HashMap<Pos, Vec<String>>) {
+    for selection in &selection_set.items {
+        match &selection.node {
+            Selection::Field(field) => field_uses(field, uses),
+            Selection::InlineFragment(fragment) => {
+                directive_uses(&fragment.node.directives, uses);
+                selection_uses(&fragment.node.selection_set.node, uses);
+            }
+            Selection::FragmentSpread(spread) => directive_uses(&spread.node.directives, uses),
+        }
+    }
+}
+
+/// Records the variables referenced by each of the arguments.
+///
+/// async-graphql doesn't re-export the type of unresolved values, only the method which resolves
+/// their variables, so this can't be a function, and the variables are collected by resolving
+/// each of them to `null`.
+#[cfg(feature = "metrics")]
+macro_rules! argument_uses {
+    ($arguments:expr, $uses:expr) => {
+        for (name, value) in $arguments {
+            let mut variables = Vec::new();
+            let _ = value.node.clone().into_const_with(|variable| {
+                variables.push(variable.to_string());
+                Ok::<_, ()>(ConstValue::Null)
+            });
+            $uses.insert(name.pos, variables);
+        }
+    };
+}
+
+#[cfg(feature = "metrics")]
+fn field_uses(field: &Positioned<Field>, uses: &mut HashMap<Pos, Vec<String>>) {
+    argument_uses!(&field.node.arguments, uses);
+    directive_uses(&field.node.directives, uses);
+    selection_uses(&field.node.selection_set.node, uses);
+}
+
+#[cfg(feature = "metrics")]
+fn directive_uses(directives: &[Positioned<Directive>], uses: &mut HashMap<Pos, Vec<String>>) {
+    for directive in directives {
+        argument_uses!(&directive.node.arguments, uses);
+    }
+}
+
+/// Collects the response keys of the fields in the selection set, including those selected
+/// through fragments.
+///
+/// Fields selected more than once under the same key are merged into a single field of the
+/// response, so they're only counted once. The document hasn't been validated yet, so each
+/// fragment is only visited once in case the fragments are cyclic.
+fn root_fields<'a>(
+    document: &'a ExecutableDocument,
+    selection_set: &'a SelectionSet,
+    fields: &mut HashSet<&'a str>,
+    visited: &mut HashSet<&'a str>,
+) {
+    for selection in &selection_set.items {
+        match &selection.node {
+            Selection::Field(field) => {
+                fields.insert(field.node.response_key().node.as_str());
+            }
+            Selection::InlineFragment(fragment) => {
+                root_fields(document, &fragment.node.selection_set.node, fields, visited)
+            }
+            Selection::FragmentSpread(spread) => {
+                let name = spread.node.fragment_name.node.as_str();
+                if let Some(fragment) = document.fragments.get(name) {
+                    if visited.insert(name) {
+                        root_fields(document, &fragment.node.selection_set.node, fields, visited);
+                    }
+                }
+            }
+        }
+    }
+}
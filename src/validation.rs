@@ -0,0 +1,75 @@
+//! Classifying the errors of documents which fail validation.
+//!
+//! async-graphql reports which validation rule a document broke only in the message of each
+//! error, so the rules are recognised by the wording of the messages of the rules built into
+//! async-graphql 2.x.
+
+use async_graphql::ServerError;
+
+/// The fragments of the messages each category of validation error is recognised by, checked in
+/// order.
+const RULES: &[(&str, &[&str])] = &[
+    ("unknown_field", &["Unknown field"]),
+    ("unknown_argument", &["Unknown argument"]),
+    ("duplicate_argument", &["There can only be one argument"]),
+    ("missing_argument", &["is required but not provided"]),
+    (
+        "invalid_argument",
+        &[
+            "Invalid value for argument",
+            "Invalid default value",
+            "can't have a default value",
+        ],
+    ),
+    ("unknown_type", &["Unknown type"]),
+    (
+        "unknown_directive",
+        &["Unknown directive", "may not be used on"],
+    ),
+    ("unknown_fragment", &["Unknown fragment"]),
+    ("fragment_cycle", &["Cannot spread fragment"]),
+    (
+        "invalid_fragment_spread",
+        &[
+            "cannot be spread here",
+            "cannot condition non composite type",
+        ],
+    ),
+    ("unused_fragment", &["is never used"]),
+    ("undefined_variable", &["is not defined by operation"]),
+    ("unused_variable", &["is not used by operation"]),
+    (
+        "invalid_variable",
+        &[
+            "There can only be one variable",
+            "cannot be of non-input type",
+            "used in position expecting type",
+        ],
+    ),
+    ("field_conflict", &["conflict because"]),
+    (
+        "leaf_selection",
+        &[
+            "must not have a selection",
+            "must have a selection of subfields",
+        ],
+    ),
+    ("unsupported_operation", &["Schema is not configured for"]),
+    ("invalid_upload", &["The Upload type is only allowed"]),
+];
+
+/// The coarse category of the validation rule the error came from, e.g. `unknown_field` or
+/// `fragment_cycle`, or `other` for an error it doesn't recognise.
+///
+/// This is the default classifier of
+/// [`OpenTelemetryConfig::validation_rule_classifier`](crate::OpenTelemetryConfig::validation_rule_classifier).
+pub fn classify_validation_error(err: &ServerError) -> &'static str {
+    RULES
+        .iter()
+        .find(|(_, fragments)| {
+            fragments
+                .iter()
+                .any(|fragment| err.message.contains(fragment))
+        })
+        .map_or("other", |(rule, _)| rule)
+}